@@ -42,13 +42,82 @@ COMPLEX_TABLE = {
 
 LARGE_TABLE = (1..50).to_h { |i| ["key_#{i}", "value_#{i}"] }.freeze
 
+# Headers of a message dead-lettered repeatedly between a few queues
+def x_death_headers(entries)
+  {
+    "x-death" => (1..entries).map do |i|
+      {
+        "count" => i,
+        "reason" => "expired",
+        "queue" => "orders.retry.#{i}",
+        "time" => Time.at(1_700_000_000 + i),
+        "exchange" => "orders.dlx",
+        "routing-keys" => ["orders.created", "orders.retry.#{i}"]
+      }
+    end,
+    "x-first-death-exchange" => "orders.dlx",
+    "x-first-death-queue" => "orders.retry.1",
+    "x-first-death-reason" => "expired"
+  }.freeze
+end
+
+X_DEATH_HEADERS = x_death_headers(3)
+X_DEATH_HEADERS_LONG = x_death_headers(25)
+
+# Tables nested `depth` levels deep, each level carrying a few scalar values
+def deeply_nested_table(depth)
+  (1..depth).reduce({ "leaf" => "value" }) do |inner, level|
+    { "level" => level, "name" => "level-#{level}", "child" => inner, "list" => [inner] }
+  end.freeze
+end
+
+DEEPLY_NESTED_TABLE = deeply_nested_table(8)
+
+# Baseline for the nested table benchmarks: a plain Ruby encoder that builds
+# every nested table and array as a String of its own and copies it into its
+# parent, as tables were encoded before lengths were back-patched. Covers the
+# value types of the tables above.
+module CopyingTableEncoder
+  module_function
+
+  def encode(table)
+    fields = table.map { |key, value| [key.bytesize].pack("C") << key.b << field(value) }.join
+    [fields.bytesize].pack("N") << fields
+  end
+
+  def field(value)
+    case value
+    when String then "S".b << [value.bytesize].pack("N") << value.b
+    when Integer then "l".b << [value].pack("q>")
+    when Float then "d".b << [value].pack("G")
+    when true, false then "t".b << (value ? 1 : 0).chr
+    when Time then "T".b << [value.to_i].pack("q>")
+    when Hash then "F".b << encode(value)
+    when Array
+      items = value.map { |item| field(item) }.join
+      "A".b << [items.bytesize].pack("N") << items
+    else raise ArgumentError, "Unsupported value #{value.inspect}"
+    end
+  end
+end
+
+[X_DEATH_HEADERS, X_DEATH_HEADERS_LONG, DEEPLY_NESTED_TABLE].each do |table|
+  next if CopyingTableEncoder.encode(table) == AMQ::Protocol::Table.encode(table)
+
+  raise "The baseline encoder does not match Table.encode"
+end
+
 ENCODED_EMPTY = AMQ::Protocol::Table.encode(EMPTY_TABLE)
 ENCODED_SIMPLE = AMQ::Protocol::Table.encode(SIMPLE_TABLE)
 ENCODED_TYPICAL = AMQ::Protocol::Table.encode(TYPICAL_HEADERS)
 ENCODED_COMPLEX = AMQ::Protocol::Table.encode(COMPLEX_TABLE)
 ENCODED_LARGE = AMQ::Protocol::Table.encode(LARGE_TABLE)
+ENCODED_X_DEATH = AMQ::Protocol::Table.encode(X_DEATH_HEADERS)
+ENCODED_X_DEATH_LONG = AMQ::Protocol::Table.encode(X_DEATH_HEADERS_LONG)
+ENCODED_DEEPLY_NESTED = AMQ::Protocol::Table.encode(DEEPLY_NESTED_TABLE)
 
 puts "Table sizes (bytes): empty=#{ENCODED_EMPTY.bytesize}, simple=#{ENCODED_SIMPLE.bytesize}, typical=#{ENCODED_TYPICAL.bytesize}, complex=#{ENCODED_COMPLEX.bytesize}, large=#{ENCODED_LARGE.bytesize}"
+puts "Nested table sizes (bytes): x-death (3)=#{ENCODED_X_DEATH.bytesize}, x-death (25)=#{ENCODED_X_DEATH_LONG.bytesize}, 8 levels=#{ENCODED_DEEPLY_NESTED.bytesize}"
 puts
 
 puts "=== Table Encoding ==="
@@ -78,6 +147,48 @@ Benchmark.ips do |x|
   x.compare!
 end
 
+puts
+puts "=== Nested Table Encoding ==="
+# Nested tables and arrays are written in a single pass, with their length
+# prefixes back-patched, so throughput should not fall off with nesting depth
+# faster than the encoded size grows. The baseline runs copy each level into
+# its parent instead.
+Benchmark.ips do |x|
+  x.config(time: 5, warmup: 2)
+
+  x.report("encode x-death headers (3 entries)") do
+    AMQ::Protocol::Table.encode(X_DEATH_HEADERS)
+  end
+
+  x.report("baseline x-death headers (3 entries)") do
+    CopyingTableEncoder.encode(X_DEATH_HEADERS)
+  end
+
+  x.report("encode x-death headers (25 entries)") do
+    AMQ::Protocol::Table.encode(X_DEATH_HEADERS_LONG)
+  end
+
+  x.report("baseline x-death headers (25 entries)") do
+    CopyingTableEncoder.encode(X_DEATH_HEADERS_LONG)
+  end
+
+  x.report("encode nested (8 levels)") do
+    AMQ::Protocol::Table.encode(DEEPLY_NESTED_TABLE)
+  end
+
+  x.report("baseline nested (8 levels)") do
+    CopyingTableEncoder.encode(DEEPLY_NESTED_TABLE)
+  end
+
+  buffer = AMQ::Protocol::WriteBuffer.new
+  x.report("encode_into x-death headers (25 entries)") do
+    AMQ::Protocol::Table.encode_into(buffer, X_DEATH_HEADERS_LONG)
+    buffer.clear
+  end
+
+  x.compare!
+end
+
 puts
 puts "=== Table Decoding ==="
 Benchmark.ips do |x|
@@ -103,5 +214,76 @@ Benchmark.ips do |x|
     AMQ::Protocol::Table.decode(ENCODED_LARGE)
   end
 
+  x.report("decode x-death headers (25 entries)") do
+    AMQ::Protocol::Table.decode(ENCODED_X_DEATH_LONG)
+  end
+
+  x.compare!
+end
+
+puts
+puts "=== Table Decoding Options ==="
+Benchmark.ips do |x|
+  x.config(time: 5, warmup: 2)
+
+  x.report("decode typical headers") do
+    AMQ::Protocol::Table.decode(ENCODED_TYPICAL)
+  end
+
+  x.report("decode typical headers (symbolize_keys)") do
+    AMQ::Protocol::Table.decode(ENCODED_TYPICAL, symbolize_keys: true)
+  end
+
+  x.report("decode typical headers (freeze)") do
+    AMQ::Protocol::Table.decode(ENCODED_TYPICAL, freeze: true)
+  end
+
+  x.compare!
+end
+
+# Objects allocated per decode, averaged over many runs. String keys are
+# interned, so only the Hash and its values should show up here.
+def allocations_per_call(iterations = 10_000)
+  yield
+  before = GC.stat(:total_allocated_objects)
+  iterations.times { yield }
+  (GC.stat(:total_allocated_objects) - before).fdiv(iterations)
+end
+
+puts
+puts "=== Allocations Per Decode ==="
+{
+  "typical headers (5 keys)" => ENCODED_TYPICAL,
+  "large (50 keys)" => ENCODED_LARGE,
+  "x-death headers (25 entries)" => ENCODED_X_DEATH_LONG
+}.each do |name, encoded|
+  default = allocations_per_call { AMQ::Protocol::Table.decode(encoded) }
+  symbols = allocations_per_call { AMQ::Protocol::Table.decode(encoded, symbolize_keys: true) }
+  frozen = allocations_per_call { AMQ::Protocol::Table.decode(encoded, freeze: true) }
+  puts format("%-30s default: %6.1f  symbolize_keys: %6.1f  freeze: %6.1f", name, default, symbols, frozen)
+end
+
+puts
+puts "=== Single Header Lookup ==="
+Benchmark.ips do |x|
+  x.config(time: 5, warmup: 2)
+
+  x.report("decode + [] first-death queue (25 entries)") do
+    AMQ::Protocol::Table.decode(ENCODED_X_DEATH_LONG)["x-first-death-queue"]
+  end
+
+  x.report("fetch first-death queue (25 entries)") do
+    AMQ::Protocol::Table.fetch(ENCODED_X_DEATH_LONG, "x-first-death-queue")
+  end
+
+  view = AMQ::Protocol::Table::View.new(ENCODED_X_DEATH_LONG)
+  x.report("View#[] first-death queue (25 entries)") do
+    view["x-first-death-queue"]
+  end
+
+  x.report("fetch key from large (50 keys)") do
+    AMQ::Protocol::Table.fetch(ENCODED_LARGE, "key_50")
+  end
+
   x.compare!
 end
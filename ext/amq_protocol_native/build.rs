@@ -0,0 +1,6 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    println!("cargo:rustc-check-cfg=cfg(ruby_have_rb_ext_ractor_safe)");
+    let _ = rb_sys_env::activate()?;
+
+    Ok(())
+}
@@ -0,0 +1,179 @@
+//! Assembly of message bodies from received body frames
+
+use std::cell::Cell;
+
+use magnus::{
+    exception, function, gc, method, prelude::*, scan_args, typed_data::Obj, value::Opaque,
+    DataTypeFunctions, Error, Module, RString, Ruby, TryConvert, TypedData, Value,
+};
+
+use crate::error::AmqpError;
+
+/// Most bytes reserved up front for a body. The body size comes from the
+/// peer, so larger bodies grow as their frames arrive instead.
+const MAX_PREALLOCATED_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Where body frame payloads go.
+#[derive(Clone, Copy)]
+enum Target {
+    /// A binary String preallocated to the body size, up to
+    /// `MAX_PREALLOCATED_SIZE`
+    String(Opaque<RString>),
+    /// An IO-like object payloads are written to as they arrive
+    Sink(Opaque<Value>),
+}
+
+/// Collects body frame payloads into a single binary String allocated up
+/// front from the content header's body size, so assembling a message of up
+/// to `MAX_PREALLOCATED_SIZE` bytes copies each payload exactly once. With a
+/// sink, payloads are written to it instead and no String is created at all.
+#[derive(TypedData)]
+#[magnus(class = "AMQ::Protocol::BodyAccumulator", free_immediately, mark, size)]
+struct BodyAccumulator {
+    body_size: u64,
+    /// Bytes appended, or for a sink, bytes it reported as written
+    received: Cell<u64>,
+    /// None once the body has been taken
+    target: Cell<Option<Target>>,
+}
+
+impl DataTypeFunctions for BodyAccumulator {
+    fn mark(&self, marker: &gc::Marker) {
+        match self.target.get() {
+            Some(Target::String(body)) => marker.mark(body),
+            Some(Target::Sink(sink)) => marker.mark(sink),
+            None => {}
+        }
+    }
+}
+
+/// Parses one positional argument followed by an optional `sink:` keyword.
+fn sink_option(args: &[Value]) -> std::result::Result<(Value, Option<Value>), Error> {
+    let args = scan_args::scan_args::<(Value,), (), (), (), _, ()>(args)?;
+    let kwargs =
+        scan_args::get_kwargs::<_, (), (Option<Value>,), ()>(args.keywords, &[], &["sink"])?;
+    let (sink,) = kwargs.optional;
+    Ok((args.required.0, sink.filter(|sink| !sink.is_nil())))
+}
+
+impl BodyAccumulator {
+    fn with_target(body_size: u64, sink: Option<Value>) -> std::result::Result<Self, Error> {
+        let target = match sink {
+            Some(sink) => Target::Sink(sink.into()),
+            None => {
+                let capacity = body_size.min(MAX_PREALLOCATED_SIZE) as usize;
+                Target::String(RString::buf_new(capacity).into())
+            }
+        };
+        Ok(Self {
+            body_size,
+            received: Cell::new(0),
+            target: Cell::new(Some(target)),
+        })
+    }
+
+    /// `new(body_size, sink: nil)`
+    fn new(args: &[Value]) -> std::result::Result<Self, Error> {
+        let (body_size, sink) = sink_option(args)?;
+        Self::with_target(u64::try_convert(body_size)?, sink)
+    }
+
+    /// `from_header(header_payload, sink: nil)`, creating an accumulator for
+    /// the body announced by a content header frame payload.
+    fn from_header(args: &[Value]) -> std::result::Result<Self, Error> {
+        let (header, sink) = sink_option(args)?;
+        let header = RString::try_convert(header)?;
+        let bytes = unsafe { header.as_slice() };
+        let body_size = bytes.get(4..12).ok_or(AmqpError::BufferTooShort {
+            needed: 12,
+            available: bytes.len(),
+        })?;
+        Self::with_target(u64::from_be_bytes(body_size.try_into().unwrap()), sink)
+    }
+
+    fn taken_error() -> Error {
+        Error::new(exception::runtime_error(), "body has already been taken")
+    }
+
+    /// Appends the payload of a body frame.
+    fn append(
+        ruby: &Ruby,
+        rb_self: Obj<Self>,
+        payload: RString,
+    ) -> std::result::Result<Obj<Self>, Error> {
+        let target = rb_self.target.get().ok_or_else(Self::taken_error)?;
+        let size = rb_self.received.get() + payload.len() as u64;
+        if size > rb_self.body_size {
+            return Err(AmqpError::BodySizeExceeded {
+                size,
+                body_size: rb_self.body_size,
+            }
+            .into());
+        }
+
+        match target {
+            Target::String(body) => {
+                // Only reallocates past MAX_PREALLOCATED_SIZE
+                ruby.get_inner(body).cat(unsafe { payload.as_slice() });
+                rb_self.received.set(size);
+            }
+            Target::Sink(sink) => {
+                let written: u64 = ruby.get_inner(sink).funcall("write", (payload,))?;
+                rb_self.received.set(rb_self.received.get() + written);
+            }
+        }
+        Ok(rb_self)
+    }
+
+    fn body_size(&self) -> u64 {
+        self.body_size
+    }
+
+    fn bytesize(&self) -> u64 {
+        self.received.get()
+    }
+
+    fn remaining(&self) -> u64 {
+        self.body_size.saturating_sub(self.received.get())
+    }
+
+    fn is_complete(&self) -> bool {
+        self.received.get() == self.body_size
+    }
+
+    /// Returns the assembled body, or the sink once everything has been
+    /// written to it. Can only be called once, after all body frames have
+    /// been appended.
+    fn take(ruby: &Ruby, rb_self: &Self) -> std::result::Result<Value, Error> {
+        let target = rb_self.target.get().ok_or_else(Self::taken_error)?;
+        if !rb_self.is_complete() {
+            return Err(AmqpError::IncompleteBody {
+                received: rb_self.received.get(),
+                body_size: rb_self.body_size,
+            }
+            .into());
+        }
+        rb_self.target.set(None);
+        Ok(match target {
+            Target::String(body) => ruby.get_inner(body).as_value(),
+            Target::Sink(sink) => ruby.get_inner(sink),
+        })
+    }
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let accumulator = protocol.define_class("BodyAccumulator", ruby.class_object())?;
+
+    accumulator.define_singleton_method("new", function!(BodyAccumulator::new, -1))?;
+    accumulator
+        .define_singleton_method("from_header", function!(BodyAccumulator::from_header, -1))?;
+    accumulator.define_method("append", method!(BodyAccumulator::append, 1))?;
+    accumulator.define_method("<<", method!(BodyAccumulator::append, 1))?;
+    accumulator.define_method("body_size", method!(BodyAccumulator::body_size, 0))?;
+    accumulator.define_method("bytesize", method!(BodyAccumulator::bytesize, 0))?;
+    accumulator.define_method("remaining", method!(BodyAccumulator::remaining, 0))?;
+    accumulator.define_method("complete?", method!(BodyAccumulator::is_complete, 0))?;
+    accumulator.define_method("take", method!(BodyAccumulator::take, 0))?;
+
+    Ok(())
+}
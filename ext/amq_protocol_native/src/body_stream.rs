@@ -0,0 +1,201 @@
+//! Lazy encoding of message bodies read from an IO or Enumerator
+//!
+//! The content header carries the total body size, so it is written up
+//! front from the size declared by the caller. Body frames are then produced
+//! one at a time, never holding more than a frame's worth of the body.
+
+use std::cell::{Cell, RefCell};
+
+use magnus::{
+    function, gc, method, prelude::*, value::Opaque, DataTypeFunctions, Error, Module, RHash,
+    RString, Ruby, TryConvert, TypedData, Value,
+};
+
+use crate::error::AmqpError;
+use crate::frame::{write_frame, FrameType, FRAME_OVERHEAD};
+use crate::publish_batch::Framing;
+use crate::types::Encoder;
+
+/// Bytes per body frame when frame_max is 0.
+const CHUNK_SIZE: usize = 128 * 1024;
+
+#[derive(Clone, Copy)]
+enum Source {
+    /// Read from with `read(length)`
+    Io,
+    /// Pulled from with `next` until StopIteration
+    Enumerator,
+}
+
+#[derive(TypedData)]
+#[magnus(class = "AMQ::Protocol::BodyStream", free_immediately, mark, size)]
+struct BodyStream {
+    framing: Framing,
+    source: Opaque<Value>,
+    kind: Source,
+    body_size: u64,
+    header: Vec<u8>,
+    sent: Cell<u64>,
+    /// Source output not yet sent in a frame
+    pending: RefCell<Vec<u8>>,
+}
+
+impl DataTypeFunctions for BodyStream {
+    fn mark(&self, marker: &gc::Marker) {
+        marker.mark(self.source);
+    }
+}
+
+/// Returns the next chunk of an Enumerator, or None once it is exhausted.
+fn next_chunk(ruby: &Ruby, source: Value) -> std::result::Result<Option<RString>, Error> {
+    match source.funcall::<_, _, Value>("next", ()) {
+        Ok(chunk) => RString::try_convert(chunk).map(Some),
+        Err(e) if e.is_kind_of(ruby.exception_stop_iteration()) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl BodyStream {
+    /// Accepts anything responding to `read`, an Enumerator, or any other
+    /// object responding to `each`.
+    fn new(
+        ruby: &Ruby,
+        channel: u16,
+        frame_max: u32,
+        body_size: u64,
+        properties: RHash,
+        source: Value,
+    ) -> std::result::Result<Self, Error> {
+        let framing = Framing::new(channel, frame_max)?;
+        let (source, kind) = if source.respond_to("read", false)? {
+            (source, Source::Io)
+        } else if source.is_kind_of(ruby.class_enumerator()) {
+            (source, Source::Enumerator)
+        } else {
+            (source.funcall("to_enum", ())?, Source::Enumerator)
+        };
+
+        let mut header = Encoder::with_capacity(64);
+        framing.write_header(ruby, &mut header, body_size, properties)?;
+
+        Ok(Self {
+            framing,
+            source: source.into(),
+            kind,
+            body_size,
+            header: header.as_slice().to_vec(),
+            sent: Cell::new(0),
+            pending: RefCell::new(Vec::new()),
+        })
+    }
+
+    fn header_frame(&self) -> RString {
+        RString::from_slice(&self.header)
+    }
+
+    fn body_size(&self) -> u64 {
+        self.body_size
+    }
+
+    fn bytes_sent(&self) -> u64 {
+        self.sent.get()
+    }
+
+    fn is_complete(&self) -> bool {
+        self.sent.get() == self.body_size
+    }
+
+    fn incomplete(&self) -> Error {
+        AmqpError::IncompleteBody {
+            received: self.sent.get(),
+            body_size: self.body_size,
+        }
+        .into()
+    }
+
+    fn exceeded(&self, size: u64) -> Error {
+        AmqpError::BodySizeExceeded {
+            size,
+            body_size: self.body_size,
+        }
+        .into()
+    }
+
+    fn encode_frame(&self, payload: &[u8]) -> RString {
+        let mut encoder = Encoder::with_capacity(FRAME_OVERHEAD + payload.len());
+        write_frame(
+            &mut encoder,
+            FrameType::Body as u8,
+            self.framing.channel,
+            payload,
+        );
+        self.sent.set(self.sent.get() + payload.len() as u64);
+        RString::from_slice(encoder.as_slice())
+    }
+
+    /// Returns the next encoded body frame, or nil once the whole body has
+    /// been sent. Raises FrameError if the source ends early or yields more
+    /// than the declared body size.
+    fn next_frame(ruby: &Ruby, rb_self: &Self) -> std::result::Result<Option<RString>, Error> {
+        let source = ruby.get_inner(rb_self.source);
+        let remaining = rb_self.body_size - rb_self.sent.get();
+        let max_payload = rb_self.framing.max_payload.unwrap_or(CHUNK_SIZE);
+        let want = (max_payload as u64).min(remaining) as usize;
+
+        // Not borrowed while calling into Ruby
+        match rb_self.kind {
+            Source::Io => {
+                // Bytes an IO returns beyond the length asked for are kept
+                // for the following frames
+                if remaining > 0 && rb_self.pending.borrow().is_empty() {
+                    let chunk: Option<RString> = source.funcall("read", (want,))?;
+                    if let Some(chunk) = chunk {
+                        rb_self
+                            .pending
+                            .borrow_mut()
+                            .extend_from_slice(unsafe { chunk.as_slice() });
+                    }
+                }
+            }
+            Source::Enumerator => {
+                while rb_self.pending.borrow().len() < want.max(1) {
+                    match next_chunk(ruby, source)? {
+                        Some(chunk) => rb_self
+                            .pending
+                            .borrow_mut()
+                            .extend_from_slice(unsafe { chunk.as_slice() }),
+                        None => break,
+                    }
+                }
+            }
+        }
+
+        let mut pending = rb_self.pending.borrow_mut();
+        if pending.len() as u64 > remaining {
+            return Err(rb_self.exceeded(rb_self.sent.get() + pending.len() as u64));
+        }
+        if remaining == 0 {
+            return Ok(None);
+        }
+        if pending.is_empty() {
+            return Err(rb_self.incomplete());
+        }
+        let len = want.min(pending.len());
+        let frame = rb_self.encode_frame(&pending[..len]);
+        pending.drain(..len);
+        Ok(Some(frame))
+    }
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let stream = protocol.define_class("BodyStream", ruby.class_object())?;
+
+    stream.define_singleton_method("new", function!(BodyStream::new, 5))?;
+    stream.define_method("header_frame", method!(BodyStream::header_frame, 0))?;
+    stream.define_method("next_frame", method!(BodyStream::next_frame, 0))?;
+    stream.define_method("body_size", method!(BodyStream::body_size, 0))?;
+    stream.define_method("bytes_sent", method!(BodyStream::bytes_sent, 0))?;
+    stream.define_method("complete?", method!(BodyStream::is_complete, 0))?;
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+//! Caller-supplied output buffers for the `encode_into` family of functions
+
+use std::cell::{Ref, RefCell, RefMut};
+
+use magnus::{
+    encoding::RbEncoding, exception, function, method, prelude::*, Error, Module, RString, Ruby,
+    TryConvert, Value,
+};
+
+use crate::types::Encoder;
+
+/// Scratch encoders that grew past this size are dropped after use instead
+/// of being kept around for the lifetime of the thread.
+const SCRATCH_RETAIN_LIMIT: usize = 1024 * 1024;
+
+thread_local! {
+    static SCRATCH: RefCell<Encoder> = RefCell::new(Encoder::new());
+}
+
+/// A native, growable write buffer that encoders append to in place.
+#[magnus::wrap(class = "AMQ::Protocol::WriteBuffer", free_immediately, size)]
+pub struct WriteBuffer(RefCell<Encoder>);
+
+impl WriteBuffer {
+    fn new() -> Self {
+        Self(RefCell::new(Encoder::new()))
+    }
+
+    /// Borrows the encoder, raising instead of panicking while it is being
+    /// written to, which can happen when Ruby code called while encoding or
+    /// another thread uses the buffer.
+    fn encoder(&self) -> std::result::Result<Ref<'_, Encoder>, Error> {
+        self.0.try_borrow().map_err(|_| busy())
+    }
+
+    fn encoder_mut(&self) -> std::result::Result<RefMut<'_, Encoder>, Error> {
+        self.0.try_borrow_mut().map_err(|_| busy())
+    }
+
+    fn bytesize(&self) -> std::result::Result<usize, Error> {
+        Ok(self.encoder()?.len())
+    }
+
+    fn is_empty(&self) -> std::result::Result<bool, Error> {
+        Ok(self.encoder()?.is_empty())
+    }
+
+    fn to_s(&self) -> std::result::Result<RString, Error> {
+        Ok(RString::from_slice(self.encoder()?.as_slice()))
+    }
+
+    fn take(&self) -> std::result::Result<RString, Error> {
+        let mut encoder = self.encoder_mut()?;
+        let string = RString::from_slice(encoder.as_slice());
+        encoder.clear();
+        Ok(string)
+    }
+
+    fn clear(&self) -> std::result::Result<(), Error> {
+        self.encoder_mut()?.clear();
+        Ok(())
+    }
+}
+
+fn busy() -> Error {
+    Error::new(
+        exception::runtime_error(),
+        "WriteBuffer is already being written to",
+    )
+}
+
+/// Runs `f` with an encoder whose output ends up appended to `buffer`.
+///
+/// A `WriteBuffer` is written to directly. A `String`, which must be
+/// ASCII-8BIT, receives the output with a single copy from a per-thread
+/// scratch encoder, so no intermediate Ruby strings are allocated. Nothing
+/// is appended if `f` fails.
+pub fn with_encoder<F>(buffer: Value, f: F) -> std::result::Result<(), Error>
+where
+    F: FnOnce(&mut Encoder) -> std::result::Result<(), Error>,
+{
+    if let Ok(write_buffer) = <&WriteBuffer>::try_convert(buffer) {
+        let mut encoder = write_buffer.encoder_mut()?;
+        let start = encoder.len();
+        let result = f(&mut encoder);
+        if result.is_err() {
+            encoder.truncate(start);
+        }
+        return result;
+    }
+
+    let string = RString::from_value(buffer).ok_or_else(|| {
+        Error::new(
+            exception::type_error(),
+            format!(
+                "Expected a String or AMQ::Protocol::WriteBuffer, got {}",
+                buffer.class().inspect()
+            ),
+        )
+    })?;
+    if string.is_frozen() {
+        return Err(Error::new(
+            exception::frozen_error(),
+            "can't modify frozen String",
+        ));
+    }
+    // Appending raw bytes to a String in a text encoding would leave it
+    // holding invalid characters
+    let encoding = string.enc_get();
+    if encoding != Ruby::get_with(string).ascii8bit_encindex() {
+        return Err(Error::new(
+            exception::arg_error(),
+            format!(
+                "Expected an ASCII-8BIT String, got {}",
+                RbEncoding::from(encoding).name()
+            ),
+        ));
+    }
+
+    SCRATCH.with(|scratch| match scratch.try_borrow_mut() {
+        Ok(mut encoder) => {
+            encoder.clear();
+            let result = f(&mut encoder);
+            if result.is_ok() {
+                string.cat(encoder.as_slice());
+            }
+            if encoder.capacity() > SCRATCH_RETAIN_LIMIT {
+                *encoder = Encoder::new();
+            }
+            result
+        }
+        // Re-entered from Ruby code called while encoding
+        Err(_) => {
+            let mut encoder = Encoder::new();
+            f(&mut encoder)?;
+            string.cat(encoder.as_slice());
+            Ok(())
+        }
+    })
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let write_buffer = protocol.define_class("WriteBuffer", ruby.class_object())?;
+
+    write_buffer.define_singleton_method("new", function!(WriteBuffer::new, 0))?;
+    write_buffer.define_method("bytesize", method!(WriteBuffer::bytesize, 0))?;
+    write_buffer.define_method("empty?", method!(WriteBuffer::is_empty, 0))?;
+    write_buffer.define_method("to_s", method!(WriteBuffer::to_s, 0))?;
+    write_buffer.define_method("take", method!(WriteBuffer::take, 0))?;
+    write_buffer.define_method("clear", method!(WriteBuffer::clear, 0))?;
+
+    Ok(())
+}
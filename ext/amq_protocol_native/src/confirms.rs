@@ -0,0 +1,130 @@
+//! Publisher confirm sequence tracking
+
+use std::cell::RefCell;
+
+use magnus::{function, method, prelude::*, Error, Module, RArray, Ruby};
+
+use crate::tag_set::{ranges_to_array, TagSet};
+
+/// Tracks publish sequence numbers on a channel in confirm mode.
+///
+/// Sequence numbers start at 1 after confirm.select-ok and are incremented for
+/// every basic.publish, matching the delivery tags the broker uses in
+/// basic.ack and basic.nack.
+#[derive(Debug)]
+pub struct ConfirmTracker {
+    next_seq: u64,
+    outstanding: TagSet,
+}
+
+impl ConfirmTracker {
+    pub fn new() -> Self {
+        Self {
+            next_seq: 1,
+            outstanding: TagSet::new(),
+        }
+    }
+
+    pub fn publish(&mut self) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.outstanding.insert(seq);
+        seq
+    }
+
+    /// Settles the publishes covered by a basic.ack or basic.nack and returns
+    /// them as inclusive ranges. Tags that are not outstanding are ignored.
+    pub fn settle(&mut self, delivery_tag: u64, multiple: bool) -> Vec<(u64, u64)> {
+        if multiple {
+            self.outstanding.remove_up_to(delivery_tag)
+        } else if self.outstanding.remove(delivery_tag) {
+            vec![(delivery_tag, delivery_tag)]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+impl Default for ConfirmTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[magnus::wrap(class = "AMQ::Protocol::ConfirmTracker", free_immediately, size)]
+struct RbConfirmTracker(RefCell<ConfirmTracker>);
+
+impl RbConfirmTracker {
+    fn new() -> Self {
+        Self(RefCell::new(ConfirmTracker::new()))
+    }
+
+    fn publish(&self) -> u64 {
+        self.0.borrow_mut().publish()
+    }
+
+    fn next_publish_seq_no(&self) -> u64 {
+        self.0.borrow().next_seq
+    }
+
+    fn ack(
+        ruby: &Ruby,
+        rb_self: &Self,
+        delivery_tag: u64,
+        multiple: bool,
+    ) -> std::result::Result<RArray, Error> {
+        let settled = rb_self.0.borrow_mut().settle(delivery_tag, multiple);
+        ranges_to_array(ruby, settled)
+    }
+
+    fn nack(
+        ruby: &Ruby,
+        rb_self: &Self,
+        delivery_tag: u64,
+        multiple: bool,
+    ) -> std::result::Result<RArray, Error> {
+        let settled = rb_self.0.borrow_mut().settle(delivery_tag, multiple);
+        ranges_to_array(ruby, settled)
+    }
+
+    fn outstanding(ruby: &Ruby, rb_self: &Self) -> std::result::Result<RArray, Error> {
+        ranges_to_array(ruby, rb_self.0.borrow().outstanding.ranges())
+    }
+
+    fn outstanding_count(&self) -> u64 {
+        self.0.borrow().outstanding.len()
+    }
+
+    fn is_outstanding(&self, seq: u64) -> bool {
+        self.0.borrow().outstanding.contains(seq)
+    }
+
+    fn all_confirmed(&self) -> bool {
+        self.0.borrow().outstanding.is_empty()
+    }
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let tracker = protocol.define_class("ConfirmTracker", ruby.class_object())?;
+
+    tracker.define_singleton_method("new", function!(RbConfirmTracker::new, 0))?;
+    tracker.define_method("publish", method!(RbConfirmTracker::publish, 0))?;
+    tracker.define_method(
+        "next_publish_seq_no",
+        method!(RbConfirmTracker::next_publish_seq_no, 0),
+    )?;
+    tracker.define_method("ack", method!(RbConfirmTracker::ack, 2))?;
+    tracker.define_method("nack", method!(RbConfirmTracker::nack, 2))?;
+    tracker.define_method("outstanding", method!(RbConfirmTracker::outstanding, 0))?;
+    tracker.define_method(
+        "outstanding_count",
+        method!(RbConfirmTracker::outstanding_count, 0),
+    )?;
+    tracker.define_method("outstanding?", method!(RbConfirmTracker::is_outstanding, 1))?;
+    tracker.define_method(
+        "all_confirmed?",
+        method!(RbConfirmTracker::all_confirmed, 0),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,244 @@
+//! Consumer delivery tag bookkeeping and ack coalescing
+
+use std::cell::RefCell;
+
+use magnus::{function, method, prelude::*, Error, Module, RArray, RString, Ruby};
+
+use crate::error::{AmqpError, Result};
+use crate::frame::{write_frame, FrameType};
+use crate::methods::{write_basic_ack, write_basic_nack};
+use crate::tag_set::TagSet;
+use crate::types::Encoder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Settlement {
+    Ack,
+    Nack { requeue: bool },
+}
+
+const SETTLEMENTS: [Settlement; 3] = [
+    Settlement::Ack,
+    Settlement::Nack { requeue: true },
+    Settlement::Nack { requeue: false },
+];
+
+/// Tracks delivery tags received on a channel and the application's
+/// acknowledgement decisions until they are flushed to the broker.
+#[derive(Debug)]
+pub struct DeliveryTracker {
+    last_delivered: u64,
+    unsettled: TagSet,
+    acks: TagSet,
+    nacks_requeue: TagSet,
+    nacks_discard: TagSet,
+}
+
+impl DeliveryTracker {
+    pub fn new() -> Self {
+        Self {
+            last_delivered: 0,
+            unsettled: TagSet::new(),
+            acks: TagSet::new(),
+            nacks_requeue: TagSet::new(),
+            nacks_discard: TagSet::new(),
+        }
+    }
+
+    fn pending(&self, settlement: Settlement) -> &TagSet {
+        match settlement {
+            Settlement::Ack => &self.acks,
+            Settlement::Nack { requeue: true } => &self.nacks_requeue,
+            Settlement::Nack { requeue: false } => &self.nacks_discard,
+        }
+    }
+
+    fn pending_mut(&mut self, settlement: Settlement) -> &mut TagSet {
+        match settlement {
+            Settlement::Ack => &mut self.acks,
+            Settlement::Nack { requeue: true } => &mut self.nacks_requeue,
+            Settlement::Nack { requeue: false } => &mut self.nacks_discard,
+        }
+    }
+
+    pub fn pending_count(&self) -> u64 {
+        self.acks.len() + self.nacks_requeue.len() + self.nacks_discard.len()
+    }
+
+    pub fn deliver(&mut self, delivery_tag: u64) -> Result<()> {
+        if delivery_tag <= self.last_delivered {
+            return Err(AmqpError::DeliveryTagOutOfOrder {
+                delivery_tag,
+                last_delivered: self.last_delivered,
+            });
+        }
+        self.last_delivered = delivery_tag;
+        self.unsettled.insert(delivery_tag);
+        Ok(())
+    }
+
+    /// Records the application's decision for a delivery. Fails for tags that
+    /// were never delivered or that were already acknowledged, both of which
+    /// the broker would answer with a 406 channel error.
+    pub fn settle(&mut self, delivery_tag: u64, settlement: Settlement) -> Result<()> {
+        if delivery_tag == 0 || delivery_tag > self.last_delivered {
+            return Err(AmqpError::UnknownDeliveryTag(delivery_tag));
+        }
+        if !self.unsettled.remove(delivery_tag) {
+            return Err(AmqpError::DeliveryTagAlreadySettled(delivery_tag));
+        }
+        self.pending_mut(settlement).insert(delivery_tag);
+        Ok(())
+    }
+
+    /// Drains pending decisions into the minimal sequence of
+    /// `(settlement, delivery_tag, multiple)` instructions.
+    ///
+    /// A `multiple` frame acknowledges every outstanding tag up to and
+    /// including its delivery tag, so it can only cover a prefix of
+    /// outstanding deliveries that share the same settlement. Decisions made
+    /// after a still-unsettled delivery are sent individually.
+    pub fn flush(&mut self) -> Vec<(Settlement, u64, bool)> {
+        let mut instructions = Vec::new();
+
+        while let Some((settlement, first)) = SETTLEMENTS
+            .iter()
+            .filter_map(|&s| self.pending(s).first().map(|tag| (s, tag)))
+            .min_by_key(|&(_, tag)| tag)
+        {
+            let unsettled = self.unsettled.first();
+            if unsettled.is_some_and(|tag| tag < first) {
+                break;
+            }
+
+            let bound = SETTLEMENTS
+                .iter()
+                .filter(|&&s| s != settlement)
+                .filter_map(|&s| self.pending(s).first())
+                .chain(unsettled)
+                .min();
+
+            let removed = self
+                .pending_mut(settlement)
+                .remove_up_to(bound.map_or(u64::MAX, |tag| tag - 1));
+            let count: u64 = removed.iter().map(|&(start, end)| end - start + 1).sum();
+            if let Some(&(_, last)) = removed.last() {
+                instructions.push((settlement, last, count > 1));
+            }
+        }
+
+        let mut rest = Vec::with_capacity(self.pending_count() as usize);
+        for settlement in SETTLEMENTS {
+            let set = self.pending_mut(settlement);
+            for (start, end) in set.ranges() {
+                rest.extend((start..=end).map(|tag| (settlement, tag, false)));
+            }
+            set.clear();
+        }
+        rest.sort_unstable_by_key(|&(_, tag, _)| tag);
+        instructions.extend(rest);
+
+        instructions
+    }
+}
+
+impl Default for DeliveryTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[magnus::wrap(class = "AMQ::Protocol::DeliveryTracker", free_immediately, size)]
+struct RbDeliveryTracker {
+    channel: u16,
+    inner: RefCell<DeliveryTracker>,
+}
+
+impl RbDeliveryTracker {
+    fn new(channel: u16) -> Self {
+        Self {
+            channel,
+            inner: RefCell::new(DeliveryTracker::new()),
+        }
+    }
+
+    fn channel(&self) -> u16 {
+        self.channel
+    }
+
+    fn deliver(&self, delivery_tag: u64) -> std::result::Result<(), Error> {
+        self.inner
+            .borrow_mut()
+            .deliver(delivery_tag)
+            .map_err(Error::from)
+    }
+
+    fn ack(&self, delivery_tag: u64) -> std::result::Result<(), Error> {
+        self.inner
+            .borrow_mut()
+            .settle(delivery_tag, Settlement::Ack)
+            .map_err(Error::from)
+    }
+
+    fn nack(&self, delivery_tag: u64, requeue: bool) -> std::result::Result<(), Error> {
+        self.inner
+            .borrow_mut()
+            .settle(delivery_tag, Settlement::Nack { requeue })
+            .map_err(Error::from)
+    }
+
+    fn flush(ruby: &Ruby, rb_self: &Self) -> std::result::Result<RArray, Error> {
+        let instructions = rb_self.inner.borrow_mut().flush();
+
+        let frames = ruby.ary_new_capa(instructions.len());
+        let mut payload = Encoder::with_capacity(16);
+        let mut frame = Encoder::with_capacity(24);
+        for (settlement, delivery_tag, multiple) in instructions {
+            payload.clear();
+            frame.clear();
+            match settlement {
+                Settlement::Ack => write_basic_ack(&mut payload, delivery_tag, multiple)?,
+                Settlement::Nack { requeue } => {
+                    write_basic_nack(&mut payload, delivery_tag, multiple, requeue)?
+                }
+            }
+            write_frame(
+                &mut frame,
+                FrameType::Method as u8,
+                rb_self.channel,
+                payload.as_slice(),
+            );
+            frames.push(RString::from_slice(frame.as_slice()))?;
+        }
+
+        Ok(frames)
+    }
+
+    fn unsettled_count(&self) -> u64 {
+        self.inner.borrow().unsettled.len()
+    }
+
+    fn pending_count(&self) -> u64 {
+        self.inner.borrow().pending_count()
+    }
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let tracker = protocol.define_class("DeliveryTracker", ruby.class_object())?;
+
+    tracker.define_singleton_method("new", function!(RbDeliveryTracker::new, 1))?;
+    tracker.define_method("channel", method!(RbDeliveryTracker::channel, 0))?;
+    tracker.define_method("deliver", method!(RbDeliveryTracker::deliver, 1))?;
+    tracker.define_method("ack", method!(RbDeliveryTracker::ack, 1))?;
+    tracker.define_method("nack", method!(RbDeliveryTracker::nack, 2))?;
+    tracker.define_method("flush", method!(RbDeliveryTracker::flush, 0))?;
+    tracker.define_method(
+        "unsettled_count",
+        method!(RbDeliveryTracker::unsettled_count, 0),
+    )?;
+    tracker.define_method(
+        "pending_count",
+        method!(RbDeliveryTracker::pending_count, 0),
+    )?;
+
+    Ok(())
+}
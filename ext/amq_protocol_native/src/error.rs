@@ -1,6 +1,9 @@
 //! Error types for AMQP protocol handling
 
-use magnus::{exception, Error};
+use magnus::{
+    error::ErrorType, exception, prelude::*, Error, ExceptionClass, RModule, Ruby, TryConvert,
+    Value,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -20,8 +23,11 @@ pub enum AmqpError {
     #[error("Payload cannot be nil")]
     NilPayload,
 
-    #[error("Invalid table value for key '{0}': {1}")]
-    InvalidTableValue(String, String),
+    #[error("Unsupported table value of class {0}")]
+    InvalidTableValue(String),
+
+    #[error("Integer {value} is outside the {range} range of table values")]
+    IntegerOutOfRange { value: String, range: &'static str },
 
     #[error("Invalid table type: {0}")]
     InvalidTableType(char),
@@ -37,6 +43,73 @@ pub enum AmqpError {
 
     #[error("Decoding error: {0}")]
     DecodingError(String),
+
+    /// An exception raised by Ruby code called while encoding, such as a
+    /// `to_amqp_field` method, which is re-raised as is
+    #[error("{0}")]
+    Raised(Error),
+
+    #[error("{error} at byte {offset}{}", describe_key_path(.key_path))]
+    DecodeFailed {
+        error: Box<AmqpError>,
+        offset: usize,
+        /// Keys and array indices leading to the value, empty for the table
+        /// itself and for frames
+        key_path: String,
+    },
+
+    #[error("{error}{}", describe_key_path(.key_path))]
+    EncodeFailed {
+        error: Box<AmqpError>,
+        /// The value or key that could not be encoded
+        value: Value,
+        /// Keys and array indices leading to the value, empty for the table
+        /// itself and for frames
+        key_path: String,
+    },
+
+    #[error("Frame payload of {size} bytes exceeds frame_max of {frame_max}")]
+    FrameTooLarge { size: u32, frame_max: u32 },
+
+    /// Located by `AmqpError::DecodeFailed`
+    #[error("Frame end octet missing")]
+    MissingFrameEnd,
+
+    #[error("Received {size} body bytes but the content header declared {body_size}")]
+    BodySizeExceeded { size: u64, body_size: u64 },
+
+    #[error("Body is incomplete: received {received} of {body_size} bytes")]
+    IncompleteBody { received: u64, body_size: u64 },
+
+    #[error("Unknown delivery tag: {0}")]
+    UnknownDeliveryTag(u64),
+
+    #[error("Delivery tag {0} has already been acknowledged")]
+    DeliveryTagAlreadySettled(u64),
+
+    #[error(
+        "Delivery tag {delivery_tag} is not greater than the last delivered tag {last_delivered}"
+    )]
+    DeliveryTagOutOfOrder {
+        delivery_tag: u64,
+        last_delivered: u64,
+    },
+
+    #[error("Server does not support AMQP 0-9-1 (replied with version {major}-{minor})")]
+    ProtocolVersionMismatch {
+        protocol_id: u8,
+        major: u8,
+        minor: u8,
+        revision: Option<u8>,
+    },
+}
+
+fn describe_key_path(key_path: &str) -> String {
+    if key_path.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", key_path)
+    }
 }
 
 impl From<AmqpError> for Error {
@@ -45,7 +118,7 @@ impl From<AmqpError> for Error {
             AmqpError::InvalidFrameType(_)
             | AmqpError::FrameTypeError(_)
             | AmqpError::InvalidTableType(_)
-            | AmqpError::InvalidTableValue(_, _) => {
+            | AmqpError::InvalidTableValue(_) => {
                 Error::new(exception::arg_error(), err.to_string())
             }
             AmqpError::EmptyResponse
@@ -53,12 +126,143 @@ impl From<AmqpError> for Error {
             | AmqpError::DecodingError(_) => {
                 Error::new(exception::runtime_error(), err.to_string())
             }
+            AmqpError::IntegerOutOfRange { .. } => {
+                Error::new(exception::range_error(), err.to_string())
+            }
             AmqpError::ChannelOutOfRange(_)
             | AmqpError::NilPayload
             | AmqpError::ShortStringTooLong(_)
-            | AmqpError::EncodingError(_) => Error::new(exception::arg_error(), err.to_string()),
+            | AmqpError::EncodingError(_)
+            | AmqpError::DeliveryTagOutOfOrder { .. } => {
+                Error::new(exception::arg_error(), err.to_string())
+            }
+            AmqpError::FrameTooLarge { .. }
+            | AmqpError::MissingFrameEnd
+            | AmqpError::BodySizeExceeded { .. }
+            | AmqpError::IncompleteBody { .. } => {
+                Error::new(protocol_exception("FrameError"), err.to_string())
+            }
+            AmqpError::UnknownDeliveryTag(_) => Error::new(
+                protocol_exception("UnknownDeliveryTagError"),
+                err.to_string(),
+            ),
+            AmqpError::DeliveryTagAlreadySettled(_) => {
+                Error::new(protocol_exception("DoubleAckError"), err.to_string())
+            }
+            AmqpError::ProtocolVersionMismatch {
+                protocol_id,
+                major,
+                minor,
+                revision,
+            } => protocol_exception("ProtocolVersionMismatch")
+                .new_instance((protocol_id, major, minor, revision))
+                .map_or_else(|e| e, Error::from),
+            AmqpError::Raised(e) => e,
+            AmqpError::DecodeFailed { .. } => located_error(err),
+            AmqpError::EncodeFailed { .. } => encode_failed_error(err),
         }
     }
 }
 
+/// Raises the exception the underlying error would raise, with its location
+/// exposed by `AMQ::Protocol::DecodingErrorLocation`.
+fn located_error(err: AmqpError) -> Error {
+    let message = err.to_string();
+    let AmqpError::DecodeFailed {
+        error,
+        offset,
+        key_path,
+    } = err
+    else {
+        unreachable!()
+    };
+
+    let inner = Error::from(*error);
+    let class = match inner.error_type() {
+        ErrorType::Error(class, _) => *class,
+        _ => return inner,
+    };
+    let Ok(ruby) = Ruby::get() else {
+        return inner;
+    };
+    let key_path = (!key_path.is_empty()).then_some(key_path.as_str());
+    with_location(
+        class,
+        &message,
+        "DecodingErrorLocation",
+        &[
+            ("@offset", ruby.into_value(offset)),
+            ("@key_path", ruby.into_value(key_path)),
+        ],
+    )
+}
+
+/// Raises `AMQ::Protocol::TableEncodingError`, or for Integers that do not
+/// fit in 64 bits, a RangeError with the same `key_path` and `value`.
+fn encode_failed_error(err: AmqpError) -> Error {
+    let message = err.to_string();
+    let AmqpError::EncodeFailed {
+        error,
+        value,
+        key_path,
+    } = err
+    else {
+        unreachable!()
+    };
+
+    if let AmqpError::Raised(e) = *error {
+        return e;
+    }
+    let key_path = (!key_path.is_empty()).then_some(key_path.as_str());
+    if let (AmqpError::IntegerOutOfRange { .. }, Ok(ruby)) = (&*error, Ruby::get()) {
+        return with_location(
+            exception::range_error(),
+            &message,
+            "EncodingErrorLocation",
+            &[("@key_path", ruby.into_value(key_path)), ("@value", value)],
+        );
+    }
+    protocol_exception("TableEncodingError")
+        .new_instance((
+            message.as_str(),
+            magnus::kwargs!("key_path" => key_path, "value" => value),
+        ))
+        .map_or_else(|e| e, Error::from)
+}
+
+/// Creates a `class` exception with `ivars` set, extended with the
+/// `AMQ::Protocol` module `location` that reads them.
+fn with_location(
+    class: ExceptionClass,
+    message: &str,
+    location: &str,
+    ivars: &[(&str, Value)],
+) -> Error {
+    let extended = || -> Option<Error> {
+        let exception = class.new_instance((message,)).ok()?;
+        for &(name, value) in ivars {
+            let _: Value = exception
+                .funcall("instance_variable_set", (name, value))
+                .ok()?;
+        }
+        let location = protocol_const::<RModule>(location)?;
+        let _: Value = exception.funcall("extend", (location,)).ok()?;
+        Some(exception.into())
+    };
+    extended().unwrap_or_else(|| Error::new(class, message.to_string()))
+}
+
+/// Looks up an exception class defined in `lib/amq/protocol.rb`, falling back
+/// to `RuntimeError` if it is not available.
+pub fn protocol_exception(name: &str) -> ExceptionClass {
+    protocol_const(name).unwrap_or_else(exception::runtime_error)
+}
+
+fn protocol_const<T: TryConvert>(name: &str) -> Option<T> {
+    let ruby = Ruby::get().ok()?;
+    let amq: RModule = ruby.class_object().const_get("AMQ").ok()?;
+    let protocol: RModule = amq.const_get("Protocol").ok()?;
+    protocol.const_get(name).ok()
+}
+
 pub type Result<T> = std::result::Result<T, AmqpError>;
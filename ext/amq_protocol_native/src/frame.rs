@@ -2,7 +2,11 @@
 
 use magnus::{function, prelude::*, Error, Module, RArray, RString, Ruby, TryConvert, Value};
 
+use crate::buffer;
 use crate::error::{AmqpError, Result};
+use crate::frozen_str;
+use crate::nogvl;
+use crate::protocol_header;
 use crate::types::{Decoder, Encoder};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -52,15 +56,209 @@ impl FrameType {
 pub const FRAME_END: u8 = 0xCE;
 pub const MAX_CHANNEL: u16 = 65535;
 pub const FRAME_HEADER_SIZE: usize = 7;
+/// Frame header plus the frame end octet
+pub const FRAME_OVERHEAD: usize = FRAME_HEADER_SIZE + 1;
+/// Smallest frame_max a peer may negotiate
+pub const FRAME_MIN_SIZE: u32 = 4096;
 
-pub fn encode_frame(frame_type: u8, channel: u16, payload: &[u8]) -> Vec<u8> {
-    let mut encoder = Encoder::with_capacity(FRAME_HEADER_SIZE + payload.len() + 1);
+pub fn write_frame(encoder: &mut Encoder, frame_type: u8, channel: u16, payload: &[u8]) {
     encoder.write_u8(frame_type);
     encoder.write_u16(channel);
     encoder.write_u32(payload.len() as u32);
     encoder.write_bytes(payload);
     encoder.write_u8(FRAME_END);
-    encoder.into_bytes().to_vec()
+}
+
+/// Writes a frame whose payload is produced in place by `f`, back-patching
+/// the payload size. Returns the payload size.
+pub fn write_frame_with<F>(
+    encoder: &mut Encoder,
+    frame_type: u8,
+    channel: u16,
+    f: F,
+) -> std::result::Result<usize, Error>
+where
+    F: FnOnce(&mut Encoder) -> std::result::Result<(), Error>,
+{
+    encoder.write_u8(frame_type);
+    encoder.write_u16(channel);
+    let size_offset = encoder.reserve_length();
+    f(encoder)?;
+    let size = encoder.len() - size_offset - 4;
+    encoder.patch_length(size_offset);
+    encoder.write_u8(FRAME_END);
+    Ok(size)
+}
+
+/// Writes a frame into `out`, which must be exactly
+/// `FRAME_OVERHEAD + payload.len()` bytes long.
+fn write_frame_to_slice(out: &mut [u8], frame_type: u8, channel: u16, payload: &[u8]) {
+    let (header, rest) = out.split_at_mut(FRAME_HEADER_SIZE);
+    header[0] = frame_type;
+    header[1..3].copy_from_slice(&channel.to_be_bytes());
+    header[3..7].copy_from_slice(&(payload.len() as u32).to_be_bytes());
+    rest[..payload.len()].copy_from_slice(payload);
+    rest[payload.len()] = FRAME_END;
+}
+
+/// Raises ArgumentError for a frame_max no peer may negotiate. 0 means
+/// unlimited.
+pub fn check_frame_max(frame_max: u32) -> std::result::Result<(), Error> {
+    if frame_max != 0 && frame_max < FRAME_MIN_SIZE {
+        return Err(Error::new(
+            magnus::exception::arg_error(),
+            format!(
+                "frame_max must be 0 or at least {} but was {}",
+                FRAME_MIN_SIZE, frame_max
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Largest body frame payload for a frame_max that passed
+/// `check_frame_max`, None if unlimited.
+pub fn max_body_payload(frame_max: u32) -> Option<usize> {
+    (frame_max != 0).then(|| frame_max as usize - FRAME_OVERHEAD)
+}
+
+/// Total size of the body frames `write_body_frames` produces.
+pub fn body_frames_len(body_len: usize, max_payload: Option<usize>) -> usize {
+    let frames = match max_payload {
+        _ if body_len == 0 => 0,
+        Some(max) => (body_len + max - 1) / max,
+        None => 1,
+    };
+    body_len + frames * FRAME_OVERHEAD
+}
+
+/// Splits a message body into body frames of at most `max_payload` bytes.
+pub fn write_body_frames(
+    encoder: &mut Encoder,
+    channel: u16,
+    body: &[u8],
+    max_payload: Option<usize>,
+) {
+    if body.is_empty() {
+        return;
+    }
+    for chunk in body.chunks(max_payload.unwrap_or(body.len())) {
+        write_frame(encoder, FrameType::Body as u8, channel, chunk);
+    }
+}
+
+pub fn check_frame_size(size: u32, frame_max: u32) -> Result<()> {
+    if frame_max != 0 && size as usize + FRAME_OVERHEAD > frame_max as usize {
+        return Err(AmqpError::FrameTooLarge { size, frame_max });
+    }
+    Ok(())
+}
+
+/// A complete frame found in a buffer, with the range of its payload.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameRef {
+    pub frame_type: FrameType,
+    pub channel: u16,
+    pub payload: std::ops::Range<usize>,
+}
+
+/// Wraps a frame error in `AmqpError::DecodeFailed` at `offset`, or if it
+/// already is one, moves its offset `offset` bytes further on.
+fn locate(error: AmqpError, offset: usize) -> AmqpError {
+    match error {
+        AmqpError::DecodeFailed {
+            error,
+            offset: inner,
+            key_path,
+        } => AmqpError::DecodeFailed {
+            error,
+            offset: offset + inner,
+            key_path,
+        },
+        // Raised as ProtocolVersionMismatch, which has details of its own
+        error @ AmqpError::ProtocolVersionMismatch { .. } => error,
+        error => AmqpError::DecodeFailed {
+            error: Box::new(error),
+            offset,
+            key_path: String::new(),
+        },
+    }
+}
+
+/// Decodes the header at the start of `data` and checks its size against
+/// `frame_max`, with errors located at the start of the frame.
+fn frame_header(data: &[u8], frame_max: u32) -> Result<(FrameType, u16, u32)> {
+    let (frame_type, channel, size) = decode_frame_header(data).map_err(|e| locate(e, 0))?;
+    check_frame_size(size, frame_max).map_err(|e| locate(e, 0))?;
+    Ok((frame_type, channel, size))
+}
+
+/// Validates the frame at the start of `data`, returning None if it is
+/// incomplete. The frame spans `..payload.end + 1`.
+pub fn next_frame(data: &[u8], frame_max: u32) -> Result<Option<FrameRef>> {
+    if data.len() < FRAME_HEADER_SIZE {
+        return Ok(None);
+    }
+    let (frame_type, channel, size) = frame_header(data, frame_max)?;
+
+    let end = FRAME_HEADER_SIZE + size as usize;
+    match data.get(end) {
+        None => Ok(None),
+        Some(&FRAME_END) => Ok(Some(FrameRef {
+            frame_type,
+            channel,
+            payload: FRAME_HEADER_SIZE..end,
+        })),
+        Some(_) => Err(locate(AmqpError::MissingFrameEnd, end)),
+    }
+}
+
+/// Validates the complete frames at the start of `data`, stopping at the
+/// first incomplete one. Returns the frames and the number of bytes they span.
+pub fn scan_frames(data: &[u8], frame_max: u32) -> Result<(Vec<FrameRef>, usize)> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+
+    while let Some(frame) = next_frame(&data[offset..], frame_max).map_err(|e| locate(e, offset))? {
+        let end = offset + frame.payload.end;
+        frames.push(FrameRef {
+            payload: offset + frame.payload.start..end,
+            ..frame
+        });
+        offset = end + 1;
+    }
+
+    Ok((frames, offset))
+}
+
+/// Validates that `data` holds exactly one well-formed frame.
+pub fn validate_frame(data: &[u8], frame_max: u32) -> Result<FrameRef> {
+    let (frame_type, channel, size) = frame_header(data, frame_max)?;
+
+    let end = FRAME_HEADER_SIZE + size as usize;
+    if data.len() <= end {
+        let error = AmqpError::BufferTooShort {
+            needed: end + 1,
+            available: data.len(),
+        };
+        return Err(locate(error, data.len()));
+    }
+    if data[end] != FRAME_END {
+        return Err(locate(AmqpError::MissingFrameEnd, end));
+    }
+    if data.len() > end + 1 {
+        let error = AmqpError::DecodingError(format!(
+            "{} trailing bytes after frame end",
+            data.len() - end - 1
+        ));
+        return Err(locate(error, end + 1));
+    }
+
+    Ok(FrameRef {
+        frame_type,
+        channel,
+        payload: FRAME_HEADER_SIZE..end,
+    })
 }
 
 pub fn decode_frame_header(data: &[u8]) -> Result<(FrameType, u16, u32)> {
@@ -71,6 +269,10 @@ pub fn decode_frame_header(data: &[u8]) -> Result<(FrameType, u16, u32)> {
         });
     }
 
+    if protocol_header::is_protocol_header(data) {
+        return Err(protocol_header::version_mismatch(data));
+    }
+
     let mut decoder = Decoder::new(data);
     let type_id = decoder.read_u8()?;
     let channel = decoder.read_u16()?;
@@ -81,12 +283,7 @@ pub fn decode_frame_header(data: &[u8]) -> Result<(FrameType, u16, u32)> {
     Ok((frame_type, channel, size))
 }
 
-fn rb_frame_encode(
-    ruby: &Ruby,
-    frame_type: Value,
-    payload: RString,
-    channel: i64,
-) -> std::result::Result<RString, Error> {
+pub fn check_channel(channel: i64) -> std::result::Result<u16, Error> {
     if channel < 0 || channel > MAX_CHANNEL as i64 {
         return Err(Error::new(
             magnus::exception::runtime_error(),
@@ -96,12 +293,15 @@ fn rb_frame_encode(
             ),
         ));
     }
+    Ok(channel as u16)
+}
 
-    let type_id: u8 = if frame_type.is_kind_of(ruby.class_symbol()) {
+pub fn frame_type_id(ruby: &Ruby, frame_type: Value) -> std::result::Result<u8, Error> {
+    if frame_type.is_kind_of(ruby.class_symbol()) {
         let ft = FrameType::from_symbol(frame_type).ok_or_else(|| {
             Error::new(magnus::exception::arg_error(), "Invalid frame type symbol")
         })?;
-        ft as u8
+        Ok(ft as u8)
     } else {
         let id: i64 = TryConvert::try_convert(frame_type).map_err(|_| {
             Error::new(
@@ -109,50 +309,128 @@ fn rb_frame_encode(
                 "Expected symbol or integer for frame type",
             )
         })?;
-        id as u8
-    };
+        Ok(id as u8)
+    }
+}
 
-    let payload_bytes = unsafe { payload.as_slice() };
-    let encoded = encode_frame(type_id, channel as u16, payload_bytes);
+fn rb_frame_encode(
+    ruby: &Ruby,
+    frame_type: Value,
+    payload: RString,
+    channel: i64,
+) -> std::result::Result<RString, Error> {
+    let channel = check_channel(channel)?;
+    let type_id = frame_type_id(ruby, frame_type)?;
+
+    let (frame, out) = unsafe { nogvl::new_binary_str(FRAME_OVERHEAD + payload.len()) };
+    nogvl::with_str_bytes(payload, |bytes| {
+        write_frame_to_slice(out, type_id, channel, bytes)
+    });
 
-    Ok(RString::from_slice(&encoded))
+    Ok(frame)
 }
 
-fn rb_frame_encode_to_array(
+fn rb_frame_encode_into(
     ruby: &Ruby,
+    buffer: Value,
     frame_type: Value,
     payload: RString,
     channel: i64,
+) -> std::result::Result<Value, Error> {
+    let channel = check_channel(channel)?;
+    let type_id = frame_type_id(ruby, frame_type)?;
+
+    let payload_bytes = unsafe { payload.as_slice() };
+    buffer::with_encoder(buffer, |encoder| {
+        write_frame(encoder, type_id, channel, payload_bytes);
+        Ok(())
+    })?;
+
+    Ok(buffer)
+}
+
+/// Splits a message body into a String of body frames that fit `frame_max`.
+fn rb_frame_encode_body(
+    body: RString,
+    channel: i64,
+    frame_max: u32,
+) -> std::result::Result<RString, Error> {
+    let channel = check_channel(channel)?;
+    check_frame_max(frame_max)?;
+    let max_payload = max_body_payload(frame_max);
+
+    let (frames, out) = unsafe { nogvl::new_binary_str(body_frames_len(body.len(), max_payload)) };
+    nogvl::with_str_bytes(body, |bytes| {
+        if bytes.is_empty() {
+            return;
+        }
+        let mut offset = 0;
+        for chunk in bytes.chunks(max_payload.unwrap_or(bytes.len())) {
+            let len = FRAME_OVERHEAD + chunk.len();
+            write_frame_to_slice(
+                &mut out[offset..offset + len],
+                FrameType::Body as u8,
+                channel,
+                chunk,
+            );
+            offset += len;
+        }
+    });
+
+    Ok(frames)
+}
+
+fn frame_to_array(
+    ruby: &Ruby,
+    data: &[u8],
+    frame: &FrameRef,
 ) -> std::result::Result<RArray, Error> {
-    if channel < 0 || channel > MAX_CHANNEL as i64 {
-        return Err(Error::new(
-            magnus::exception::runtime_error(),
-            format!(
-                "Channel has to be 0 or an integer in range 1..65535 but was {}",
-                channel
-            ),
-        ));
+    let array = ruby.ary_new_capa(3);
+    array.push(ruby.sym_new(frame.frame_type.symbol_name()))?;
+    array.push(frame.channel)?;
+    array.push(RString::from_slice(&data[frame.payload.clone()]))?;
+    Ok(array)
+}
+
+/// Returns `[[type, channel, payload], ...]` for the complete frames at the
+/// start of `data`, along with the number of bytes they span.
+fn rb_frame_scan(
+    ruby: &Ruby,
+    data: RString,
+    frame_max: u32,
+) -> std::result::Result<(RArray, usize), Error> {
+    // The frames found are sliced out of the same bytes that were scanned
+    let data = nogvl::pin(data);
+    let (frames, consumed) =
+        nogvl::with_str_bytes(data, |bytes| scan_frames(bytes, frame_max)).map_err(Error::from)?;
+
+    let bytes = unsafe { data.as_slice() };
+    let array = ruby.ary_new_capa(frames.len());
+    for frame in &frames {
+        array.push(frame_to_array(ruby, bytes, frame)?)?;
     }
 
-    let type_id: u8 = if frame_type.is_kind_of(ruby.class_symbol()) {
-        let ft = FrameType::from_symbol(frame_type).ok_or_else(|| {
-            Error::new(magnus::exception::arg_error(), "Invalid frame type symbol")
-        })?;
-        ft as u8
-    } else {
-        let id: i64 = TryConvert::try_convert(frame_type).map_err(|_| {
-            Error::new(
-                magnus::exception::type_error(),
-                "Expected symbol or integer for frame type",
-            )
-        })?;
-        id as u8
-    };
+    Ok((array, consumed))
+}
+
+fn rb_frame_validate(data: RString, frame_max: u32) -> std::result::Result<bool, Error> {
+    nogvl::with_str_bytes(data, |bytes| validate_frame(bytes, frame_max)).map_err(Error::from)?;
+    Ok(true)
+}
+
+fn rb_frame_encode_to_array(
+    ruby: &Ruby,
+    frame_type: Value,
+    payload: RString,
+    channel: i64,
+) -> std::result::Result<RArray, Error> {
+    let channel = check_channel(channel)?;
+    let type_id = frame_type_id(ruby, frame_type)?;
 
     let payload_bytes = unsafe { payload.as_slice() };
     let mut header = Encoder::with_capacity(FRAME_HEADER_SIZE);
     header.write_u8(type_id);
-    header.write_u16(channel as u16);
+    header.write_u16(channel);
     header.write_u32(payload_bytes.len() as u32);
 
     let array = ruby.ary_new();
@@ -173,7 +451,8 @@ fn rb_frame_decode_header(ruby: &Ruby, header: RString) -> std::result::Result<R
         ));
     }
 
-    let (frame_type, channel, size) = decode_frame_header(header_bytes).map_err(Error::from)?;
+    let (frame_type, channel, size) =
+        decode_frame_header(header_bytes).map_err(|e| Error::from(locate(e, 0)))?;
 
     let array = ruby.ary_new();
     array.push(ruby.sym_new(frame_type.symbol_name()))?;
@@ -191,6 +470,7 @@ pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Erro
     types_hash.aset(ruby.sym_new("headers"), 2)?;
     types_hash.aset(ruby.sym_new("body"), 3)?;
     types_hash.aset(ruby.sym_new("heartbeat"), 8)?;
+    types_hash.freeze();
     frame_class.const_set("TYPES", types_hash)?;
 
     let types_reverse = ruby.hash_new();
@@ -198,6 +478,7 @@ pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Erro
     types_reverse.aset(2, ruby.sym_new("headers"))?;
     types_reverse.aset(3, ruby.sym_new("body"))?;
     types_reverse.aset(8, ruby.sym_new("heartbeat"))?;
+    types_reverse.freeze();
     frame_class.const_set("TYPES_REVERSE", types_reverse)?;
 
     let types_options = ruby.ary_new();
@@ -205,30 +486,36 @@ pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Erro
     types_options.push(ruby.sym_new("headers"))?;
     types_options.push(ruby.sym_new("body"))?;
     types_options.push(ruby.sym_new("heartbeat"))?;
+    types_options.freeze();
     frame_class.const_set("TYPES_OPTIONS", types_options)?;
 
     let final_octet = RString::from_slice(&[0xCE_u8]);
+    final_octet.freeze();
     frame_class.const_set("FINAL_OCTET", final_octet)?;
 
     frame_class.define_singleton_method("encode", function!(rb_frame_encode, 3))?;
+    frame_class.define_singleton_method("encode_into", function!(rb_frame_encode_into, 4))?;
     frame_class
         .define_singleton_method("encode_to_array", function!(rb_frame_encode_to_array, 3))?;
+    frame_class.define_singleton_method("encode_body", function!(rb_frame_encode_body, 3))?;
+    frame_class.define_singleton_method("scan", function!(rb_frame_scan, 2))?;
+    frame_class.define_singleton_method("validate", function!(rb_frame_validate, 2))?;
     frame_class.define_singleton_method("decode_header", function!(rb_frame_decode_header, 1))?;
 
-    protocol.const_set("PACK_CHAR", "C")?;
-    protocol.const_set("PACK_UINT16", "n")?;
-    protocol.const_set("PACK_UINT16_X2", "n2")?;
-    protocol.const_set("PACK_UINT32", "N")?;
-    protocol.const_set("PACK_UINT32_X2", "N2")?;
-    protocol.const_set("PACK_UINT64_BE", "Q>")?;
-    protocol.const_set("PACK_INT64_BE", "q>")?;
-    protocol.const_set("PACK_INT8", "c")?;
-    protocol.const_set("PACK_INT64", "q")?;
-    protocol.const_set("PACK_UCHAR_UINT32", "CN")?;
-    protocol.const_set("PACK_CHAR_UINT16_UINT32", "cnN")?;
-    protocol.const_set("PACK_32BIT_FLOAT", "f")?;
-    protocol.const_set("PACK_64BIT_FLOAT", "G")?;
-    protocol.const_set("EMPTY_STRING", "")?;
+    protocol.const_set("PACK_CHAR", frozen_str("C"))?;
+    protocol.const_set("PACK_UINT16", frozen_str("n"))?;
+    protocol.const_set("PACK_UINT16_X2", frozen_str("n2"))?;
+    protocol.const_set("PACK_UINT32", frozen_str("N"))?;
+    protocol.const_set("PACK_UINT32_X2", frozen_str("N2"))?;
+    protocol.const_set("PACK_UINT64_BE", frozen_str("Q>"))?;
+    protocol.const_set("PACK_INT64_BE", frozen_str("q>"))?;
+    protocol.const_set("PACK_INT8", frozen_str("c"))?;
+    protocol.const_set("PACK_INT64", frozen_str("q"))?;
+    protocol.const_set("PACK_UCHAR_UINT32", frozen_str("CN"))?;
+    protocol.const_set("PACK_CHAR_UINT16_UINT32", frozen_str("cnN"))?;
+    protocol.const_set("PACK_32BIT_FLOAT", frozen_str("f"))?;
+    protocol.const_set("PACK_64BIT_FLOAT", frozen_str("G"))?;
+    protocol.const_set("EMPTY_STRING", frozen_str(""))?;
 
     Ok(())
 }
@@ -0,0 +1,277 @@
+//! Frame-level reading and writing over a Ruby IO object
+//!
+//! All I/O goes through `read_nonblock`/`write_nonblock`, waiting with
+//! `IO#wait_readable`/`IO#wait_writable` when the IO is not ready. With a
+//! `Fiber.scheduler` set, those waits yield to other fibers instead of
+//! blocking the thread.
+
+use std::cell::{Cell, RefCell};
+
+use magnus::{
+    exception, function, gc, method, prelude::*, typed_data::Obj, value::Opaque, DataTypeFunctions,
+    Error, KwArgs, Module, RClass, RString, Ruby, Symbol, TryConvert, TypedData, Value,
+};
+
+use crate::frame::{
+    check_channel, check_frame_size, frame_type_id, next_frame, write_frame, FRAME_OVERHEAD,
+};
+use crate::protocol_header::PROTOCOL_HEADER;
+use crate::types::Encoder;
+
+/// Bytes requested from the IO per read.
+const READ_SIZE: usize = 64 * 1024;
+
+/// Bytes received but not yet returned as frames.
+struct ReadBuffer {
+    data: Vec<u8>,
+    start: usize,
+}
+
+impl ReadBuffer {
+    fn pending(&self) -> &[u8] {
+        &self.data[self.start..]
+    }
+
+    fn consume(&mut self, len: usize) {
+        self.start += len;
+        if self.start == self.data.len() {
+            self.data.clear();
+            self.start = 0;
+        }
+    }
+
+    fn extend(&mut self, bytes: &[u8]) {
+        // Compact before growing rather than on every consumed frame
+        if self.start > 0 && self.data.len() + bytes.len() > self.data.capacity() {
+            self.data.drain(..self.start);
+            self.start = 0;
+        }
+        self.data.extend_from_slice(bytes);
+    }
+}
+
+#[derive(TypedData)]
+#[magnus(class = "AMQ::Protocol::FrameIO", free_immediately, mark, size)]
+struct FrameIO {
+    io: Opaque<Value>,
+    /// A Mutex held for the whole of each write, so frames written by
+    /// fibers or threads that wait on the IO never interleave
+    write_lock: Opaque<Value>,
+    /// The same for reads, so each frame is read by a single caller even
+    /// when several wait on the IO at once
+    read_lock: Opaque<Value>,
+    frame_max: Cell<u32>,
+    buffer: RefCell<ReadBuffer>,
+}
+
+impl DataTypeFunctions for FrameIO {
+    fn mark(&self, marker: &gc::Marker) {
+        marker.mark(self.io);
+        marker.mark(self.write_lock);
+        marker.mark(self.read_lock);
+    }
+}
+
+fn no_exception(ruby: &Ruby) -> std::result::Result<KwArgs, Error> {
+    let options = ruby.hash_new();
+    options.aset(ruby.to_symbol("exception"), false)?;
+    Ok(KwArgs(options))
+}
+
+/// Calls `wait_readable`/`wait_writable` when a nonblocking call returned
+/// one of those symbols. Returns false for any other result.
+fn wait_if_needed(io: Value, result: Value) -> std::result::Result<bool, Error> {
+    let Some(symbol) = Symbol::from_value(result) else {
+        return Ok(false);
+    };
+    let name = symbol.name()?;
+    if name != "wait_readable" && name != "wait_writable" {
+        return Ok(false);
+    }
+    let _: Value = io.funcall(&*name, ())?;
+    Ok(true)
+}
+
+/// Runs `f` while holding `lock`, a Ruby Mutex, which is released again
+/// whether or not `f` succeeds.
+fn with_lock<T>(
+    lock: Value,
+    f: impl FnOnce() -> std::result::Result<T, Error>,
+) -> std::result::Result<T, Error> {
+    let _: Value = lock.funcall("lock", ())?;
+    let result = f();
+    let _: Value = lock.funcall("unlock", ())?;
+    result
+}
+
+fn read_chunk(ruby: &Ruby, io: Value) -> std::result::Result<Option<RString>, Error> {
+    loop {
+        let result: Value = io.funcall("read_nonblock", (READ_SIZE, no_exception(ruby)?))?;
+        if result.is_nil() {
+            return Ok(None);
+        }
+        if !wait_if_needed(io, result)? {
+            return RString::try_convert(result).map(Some);
+        }
+    }
+}
+
+fn write_all(ruby: &Ruby, io: Value, data: RString) -> std::result::Result<usize, Error> {
+    let total = data.len();
+    let mut remaining = data;
+    while !remaining.is_empty() {
+        let result: Value = io.funcall("write_nonblock", (remaining, no_exception(ruby)?))?;
+        if wait_if_needed(io, result)? {
+            continue;
+        }
+        let written = usize::try_convert(result)?;
+        if written == remaining.len() {
+            break;
+        }
+        remaining = remaining.funcall("byteslice", (written, remaining.len() - written))?;
+    }
+    Ok(total)
+}
+
+impl FrameIO {
+    fn new(ruby: &Ruby, io: Value, frame_max: u32) -> std::result::Result<Self, Error> {
+        let mutex: RClass = ruby.class_object().const_get("Mutex")?;
+        Ok(Self {
+            io: io.into(),
+            write_lock: mutex.new_instance(())?.into(),
+            read_lock: mutex.new_instance(())?.into(),
+            frame_max: Cell::new(frame_max),
+            buffer: RefCell::new(ReadBuffer {
+                data: Vec::new(),
+                start: 0,
+            }),
+        })
+    }
+
+    /// Writes all of `data` while holding the write lock.
+    fn write_locked(
+        ruby: &Ruby,
+        rb_self: &Self,
+        data: RString,
+    ) -> std::result::Result<usize, Error> {
+        with_lock(ruby.get_inner(rb_self.write_lock), || {
+            write_all(ruby, ruby.get_inner(rb_self.io), data)
+        })
+    }
+
+    fn io(ruby: &Ruby, rb_self: &Self) -> Value {
+        ruby.get_inner(rb_self.io)
+    }
+
+    fn frame_max(&self) -> u32 {
+        self.frame_max.get()
+    }
+
+    fn set_frame_max(&self, frame_max: u32) {
+        self.frame_max.set(frame_max);
+    }
+
+    fn buffered_bytes(&self) -> usize {
+        self.buffer.borrow().pending().len()
+    }
+
+    /// Returns the next frame as `[type, channel, payload]`, reading from the
+    /// IO as needed, or nil if the IO reached EOF between frames.
+    fn read_frame(
+        ruby: &Ruby,
+        rb_self: Obj<Self>,
+    ) -> std::result::Result<Option<(Symbol, u16, RString)>, Error> {
+        with_lock(ruby.get_inner(rb_self.read_lock), || {
+            Self::read_frame_locked(ruby, rb_self)
+        })
+    }
+
+    /// Does the work of `read_frame` once the read lock is held.
+    fn read_frame_locked(
+        ruby: &Ruby,
+        rb_self: Obj<Self>,
+    ) -> std::result::Result<Option<(Symbol, u16, RString)>, Error> {
+        let io = ruby.get_inner(rb_self.io);
+        loop {
+            {
+                let mut buffer = rb_self.buffer.borrow_mut();
+                let frame =
+                    next_frame(buffer.pending(), rb_self.frame_max.get()).map_err(Error::from)?;
+                if let Some(frame) = frame {
+                    let payload = RString::from_slice(&buffer.pending()[frame.payload.clone()]);
+                    buffer.consume(frame.payload.end + 1);
+                    return Ok(Some((
+                        ruby.to_symbol(frame.frame_type.symbol_name()),
+                        frame.channel,
+                        payload,
+                    )));
+                }
+            }
+
+            // The buffer must not be borrowed here: with a fiber scheduler,
+            // other fibers may use this object while the read waits.
+            match read_chunk(ruby, io)? {
+                Some(chunk) => rb_self
+                    .buffer
+                    .borrow_mut()
+                    .extend(unsafe { chunk.as_slice() }),
+                None if rb_self.buffer.borrow().pending().is_empty() => return Ok(None),
+                None => {
+                    return Err(Error::new(
+                        exception::eof_error(),
+                        "end of file reached in the middle of a frame",
+                    ))
+                }
+            }
+        }
+    }
+
+    /// Encodes and writes one frame, returning the number of bytes written.
+    fn write_frame(
+        ruby: &Ruby,
+        rb_self: &Self,
+        frame_type: Value,
+        payload: RString,
+        channel: i64,
+    ) -> std::result::Result<usize, Error> {
+        let channel = check_channel(channel)?;
+        let type_id = frame_type_id(ruby, frame_type)?;
+        check_frame_size(payload.len() as u32, rb_self.frame_max.get()).map_err(Error::from)?;
+
+        let mut encoder = Encoder::with_capacity(FRAME_OVERHEAD + payload.len());
+        write_frame(&mut encoder, type_id, channel, unsafe {
+            payload.as_slice()
+        });
+
+        Self::write_locked(ruby, rb_self, RString::from_slice(encoder.as_slice()))
+    }
+
+    /// Writes already encoded frames, such as the contents of a
+    /// `WriteBuffer` or `PublishBatch`.
+    fn write(ruby: &Ruby, rb_self: &Self, data: RString) -> std::result::Result<usize, Error> {
+        Self::write_locked(ruby, rb_self, data)
+    }
+
+    fn write_protocol_header(ruby: &Ruby, rb_self: &Self) -> std::result::Result<usize, Error> {
+        Self::write_locked(ruby, rb_self, RString::from_slice(&PROTOCOL_HEADER))
+    }
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let frame_io = protocol.define_class("FrameIO", ruby.class_object())?;
+
+    frame_io.define_singleton_method("new", function!(FrameIO::new, 2))?;
+    frame_io.define_method("io", method!(FrameIO::io, 0))?;
+    frame_io.define_method("frame_max", method!(FrameIO::frame_max, 0))?;
+    frame_io.define_method("frame_max=", method!(FrameIO::set_frame_max, 1))?;
+    frame_io.define_method("buffered_bytes", method!(FrameIO::buffered_bytes, 0))?;
+    frame_io.define_method("read_frame", method!(FrameIO::read_frame, 0))?;
+    frame_io.define_method("write_frame", method!(FrameIO::write_frame, 3))?;
+    frame_io.define_method("write", method!(FrameIO::write, 1))?;
+    frame_io.define_method(
+        "write_protocol_header",
+        method!(FrameIO::write_protocol_header, 0),
+    )?;
+
+    Ok(())
+}
@@ -0,0 +1,140 @@
+//! Heartbeat deadline tracking
+//!
+//! The monitor does no I/O and reads no clock: callers report traffic and pass
+//! a monotonic timestamp in seconds, such as
+//! `Process.clock_gettime(Process::CLOCK_MONOTONIC)`.
+
+use std::cell::RefCell;
+
+use magnus::{function, method, prelude::*, Error, Module, Ruby};
+
+/// Number of heartbeat intervals without inbound traffic after which the peer
+/// is considered unreachable.
+const MISSED_INTERVALS: f64 = 2.0;
+
+/// Picks the heartbeat timeout the way RabbitMQ does: the smaller of the two
+/// values, unless one side asked for 0, in which case the other value is used.
+pub fn negotiate(client: u16, server: u16) -> u16 {
+    if client == 0 || server == 0 {
+        client.max(server)
+    } else {
+        client.min(server)
+    }
+}
+
+#[derive(Debug)]
+pub struct HeartbeatMonitor {
+    heartbeat: u16,
+    last_sent: f64,
+    last_received: f64,
+}
+
+impl HeartbeatMonitor {
+    pub fn new(heartbeat: u16, now: f64) -> Self {
+        Self {
+            heartbeat,
+            last_sent: now,
+            last_received: now,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.heartbeat > 0
+    }
+
+    pub fn sent(&mut self, now: f64) {
+        self.last_sent = self.last_sent.max(now);
+    }
+
+    pub fn received(&mut self, now: f64) {
+        self.last_received = self.last_received.max(now);
+    }
+
+    /// Time at which a heartbeat frame must be sent if nothing else has been
+    /// written. Heartbeats are sent at half the negotiated timeout.
+    pub fn next_heartbeat_at(&self) -> Option<f64> {
+        self.is_enabled()
+            .then(|| self.last_sent + f64::from(self.heartbeat) / 2.0)
+    }
+
+    /// Time after which the peer is considered dead if nothing is received.
+    pub fn peer_deadline(&self) -> Option<f64> {
+        self.is_enabled()
+            .then(|| self.last_received + f64::from(self.heartbeat) * MISSED_INTERVALS)
+    }
+
+    pub fn is_heartbeat_due(&self, now: f64) -> bool {
+        self.next_heartbeat_at().is_some_and(|at| now >= at)
+    }
+
+    pub fn is_peer_dead(&self, now: f64) -> bool {
+        self.peer_deadline().is_some_and(|at| now > at)
+    }
+}
+
+#[magnus::wrap(class = "AMQ::Protocol::HeartbeatMonitor", free_immediately, size)]
+struct RbHeartbeatMonitor(RefCell<HeartbeatMonitor>);
+
+impl RbHeartbeatMonitor {
+    fn new(heartbeat: u16, now: f64) -> Self {
+        Self(RefCell::new(HeartbeatMonitor::new(heartbeat, now)))
+    }
+
+    fn heartbeat(&self) -> u16 {
+        self.0.borrow().heartbeat
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.0.borrow().is_enabled()
+    }
+
+    fn sent(&self, now: f64) {
+        self.0.borrow_mut().sent(now);
+    }
+
+    fn received(&self, now: f64) {
+        self.0.borrow_mut().received(now);
+    }
+
+    fn next_heartbeat_at(&self) -> Option<f64> {
+        self.0.borrow().next_heartbeat_at()
+    }
+
+    fn peer_deadline(&self) -> Option<f64> {
+        self.0.borrow().peer_deadline()
+    }
+
+    fn is_heartbeat_due(&self, now: f64) -> bool {
+        self.0.borrow().is_heartbeat_due(now)
+    }
+
+    fn is_peer_dead(&self, now: f64) -> bool {
+        self.0.borrow().is_peer_dead(now)
+    }
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let monitor = protocol.define_class("HeartbeatMonitor", ruby.class_object())?;
+
+    monitor.define_singleton_method("new", function!(RbHeartbeatMonitor::new, 2))?;
+    monitor.define_singleton_method("negotiate", function!(negotiate, 2))?;
+    monitor.define_method("heartbeat", method!(RbHeartbeatMonitor::heartbeat, 0))?;
+    monitor.define_method("enabled?", method!(RbHeartbeatMonitor::is_enabled, 0))?;
+    monitor.define_method("sent", method!(RbHeartbeatMonitor::sent, 1))?;
+    monitor.define_method("received", method!(RbHeartbeatMonitor::received, 1))?;
+    monitor.define_method(
+        "next_heartbeat_at",
+        method!(RbHeartbeatMonitor::next_heartbeat_at, 0),
+    )?;
+    monitor.define_method(
+        "peer_deadline",
+        method!(RbHeartbeatMonitor::peer_deadline, 0),
+    )?;
+    monitor.define_method(
+        "heartbeat_due?",
+        method!(RbHeartbeatMonitor::is_heartbeat_due, 1),
+    )?;
+    monitor.define_method("peer_dead?", method!(RbHeartbeatMonitor::is_peer_dead, 1))?;
+
+    Ok(())
+}
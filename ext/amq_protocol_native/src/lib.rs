@@ -1,27 +1,64 @@
 //! Native AMQP 0.9.1 serialization library for Ruby
 
+mod body;
+mod body_stream;
+mod buffer;
+mod confirms;
+mod deliveries;
 mod error;
 mod frame;
+mod frame_io;
+mod heartbeat;
 mod methods;
+mod nogvl;
+mod properties;
+mod protocol_header;
+mod publish_batch;
 mod table;
+mod table_view;
+mod tag_set;
 mod types;
 
-use magnus::{prelude::*, Error, Ruby};
+use magnus::{prelude::*, Error, RString, Ruby};
+
+/// Constants must be frozen to be shareable between Ractors.
+pub(crate) fn frozen_str(s: &str) -> RString {
+    let string = RString::new(s);
+    string.freeze();
+    string
+}
 
 #[magnus::init]
 fn init(ruby: &Ruby) -> Result<(), Error> {
+    // Must come before any method is defined
+    #[cfg(ruby_have_rb_ext_ractor_safe)]
+    unsafe {
+        rb_sys::rb_ext_ractor_safe(true);
+    }
+
     let amq = ruby.define_module("AMQ")?;
     let protocol = amq.define_module("Protocol")?;
 
-    protocol.const_set("PROTOCOL_VERSION", "0.9.1")?;
-    protocol.const_set("PREAMBLE", "AMQP\x00\x00\x09\x01")?;
+    protocol.const_set("PROTOCOL_VERSION", frozen_str("0.9.1"))?;
+    protocol.const_set("PREAMBLE", frozen_str("AMQP\x00\x00\x09\x01"))?;
     protocol.const_set("DEFAULT_PORT", 5672)?;
     protocol.const_set("TLS_PORT", 5671)?;
     protocol.const_set("SSL_PORT", 5671)?;
 
     table::init(ruby, &protocol)?;
+    table_view::init(ruby, &protocol)?;
     frame::init(ruby, &protocol)?;
+    protocol_header::init(ruby, &protocol)?;
     methods::init(ruby, &protocol)?;
+    properties::init(ruby, &protocol)?;
+    confirms::init(ruby, &protocol)?;
+    deliveries::init(ruby, &protocol)?;
+    heartbeat::init(ruby, &protocol)?;
+    buffer::init(ruby, &protocol)?;
+    publish_batch::init(ruby, &protocol)?;
+    frame_io::init(ruby, &protocol)?;
+    body::init(ruby, &protocol)?;
+    body_stream::init(ruby, &protocol)?;
 
     Ok(())
 }
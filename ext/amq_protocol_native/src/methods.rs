@@ -1,9 +1,12 @@
 //! AMQP 0.9.1 Method encoding
 
-use magnus::{function, prelude::*, Error, Module, RHash, RString, Ruby};
+use magnus::{function, prelude::*, Error, Exception, KwArgs, Module, RHash, RString, Ruby, Value};
 
+use crate::buffer;
+use crate::error::{protocol_exception, Result};
+use crate::frozen_str;
 use crate::table;
-use crate::types::Encoder;
+use crate::types::{Decoder, Encoder};
 
 #[allow(dead_code)]
 mod indices {
@@ -48,6 +51,128 @@ mod indices {
     pub const CONFIRM_SELECT_OK: u32 = 0x0055000B;
 }
 
+/// Resolves a class and method id pair to its AMQP name, e.g. `queue.declare`.
+pub fn method_name(class_id: u16, method_id: u16) -> Option<&'static str> {
+    let name = match (class_id, method_id) {
+        (10, 10) => "connection.start",
+        (10, 11) => "connection.start-ok",
+        (10, 20) => "connection.secure",
+        (10, 21) => "connection.secure-ok",
+        (10, 30) => "connection.tune",
+        (10, 31) => "connection.tune-ok",
+        (10, 40) => "connection.open",
+        (10, 41) => "connection.open-ok",
+        (10, 50) => "connection.close",
+        (10, 51) => "connection.close-ok",
+        (10, 60) => "connection.blocked",
+        (10, 61) => "connection.unblocked",
+        (10, 70) => "connection.update-secret",
+        (10, 71) => "connection.update-secret-ok",
+        (20, 10) => "channel.open",
+        (20, 11) => "channel.open-ok",
+        (20, 20) => "channel.flow",
+        (20, 21) => "channel.flow-ok",
+        (20, 40) => "channel.close",
+        (20, 41) => "channel.close-ok",
+        (40, 10) => "exchange.declare",
+        (40, 11) => "exchange.declare-ok",
+        (40, 20) => "exchange.delete",
+        (40, 21) => "exchange.delete-ok",
+        (40, 30) => "exchange.bind",
+        (40, 31) => "exchange.bind-ok",
+        (40, 40) => "exchange.unbind",
+        (40, 51) => "exchange.unbind-ok",
+        (50, 10) => "queue.declare",
+        (50, 11) => "queue.declare-ok",
+        (50, 20) => "queue.bind",
+        (50, 21) => "queue.bind-ok",
+        (50, 30) => "queue.purge",
+        (50, 31) => "queue.purge-ok",
+        (50, 40) => "queue.delete",
+        (50, 41) => "queue.delete-ok",
+        (50, 50) => "queue.unbind",
+        (50, 51) => "queue.unbind-ok",
+        (60, 10) => "basic.qos",
+        (60, 11) => "basic.qos-ok",
+        (60, 20) => "basic.consume",
+        (60, 21) => "basic.consume-ok",
+        (60, 30) => "basic.cancel",
+        (60, 31) => "basic.cancel-ok",
+        (60, 40) => "basic.publish",
+        (60, 50) => "basic.return",
+        (60, 60) => "basic.deliver",
+        (60, 70) => "basic.get",
+        (60, 71) => "basic.get-ok",
+        (60, 72) => "basic.get-empty",
+        (60, 80) => "basic.ack",
+        (60, 90) => "basic.reject",
+        (60, 100) => "basic.recover-async",
+        (60, 110) => "basic.recover",
+        (60, 111) => "basic.recover-ok",
+        (60, 120) => "basic.nack",
+        (85, 10) => "confirm.select",
+        (85, 11) => "confirm.select-ok",
+        (90, 10) => "tx.select",
+        (90, 11) => "tx.select-ok",
+        (90, 20) => "tx.commit",
+        (90, 21) => "tx.commit-ok",
+        (90, 30) => "tx.rollback",
+        (90, 31) => "tx.rollback-ok",
+        _ => return None,
+    };
+    Some(name)
+}
+
+/// Generates `encode` (fresh String) and `encode_into` (caller-supplied
+/// buffer, see `buffer::with_encoder`) entry points around a `write_*`
+/// function. Both produce the method payload only, without the frame
+/// around it, which `Frame.encode_into` writes.
+macro_rules! method_encoders {
+    (
+        ruby,
+        $encode:ident,
+        $encode_into:ident,
+        $write:ident,
+        $capacity:expr,
+        ($($arg:ident: $ty:ty),* $(,)?)
+    ) => {
+        #[allow(clippy::too_many_arguments)]
+        fn $encode(ruby: &Ruby, $($arg: $ty),*) -> std::result::Result<RString, Error> {
+            let mut encoder = Encoder::with_capacity($capacity);
+            $write(ruby, &mut encoder, $($arg),*)?;
+            Ok(RString::from_slice(encoder.as_slice()))
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        fn $encode_into(
+            ruby: &Ruby,
+            buffer: Value,
+            $($arg: $ty),*
+        ) -> std::result::Result<Value, Error> {
+            buffer::with_encoder(buffer, |encoder| $write(ruby, encoder, $($arg),*))?;
+            Ok(buffer)
+        }
+    };
+    (
+        $encode:ident,
+        $encode_into:ident,
+        $write:ident,
+        $capacity:expr,
+        ($($arg:ident: $ty:ty),* $(,)?)
+    ) => {
+        fn $encode($($arg: $ty),*) -> std::result::Result<RString, Error> {
+            let mut encoder = Encoder::with_capacity($capacity);
+            $write(&mut encoder, $($arg),*)?;
+            Ok(RString::from_slice(encoder.as_slice()))
+        }
+
+        fn $encode_into(buffer: Value, $($arg: $ty),*) -> std::result::Result<Value, Error> {
+            buffer::with_encoder(buffer, |encoder| $write(encoder, $($arg),*))?;
+            Ok(buffer)
+        }
+    };
+}
+
 fn write_method_header(encoder: &mut Encoder, class_id: u16, method_id: u16) {
     encoder.write_u16(class_id);
     encoder.write_u16(method_id);
@@ -57,18 +182,17 @@ fn pack_u64_be(v: u64) -> [u8; 8] {
     v.to_be_bytes()
 }
 
-fn encode_connection_start_ok(
+fn write_connection_start_ok(
     ruby: &Ruby,
+    encoder: &mut Encoder,
     client_properties: RHash,
     mechanism: RString,
     response: RString,
     locale: RString,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(256);
-    write_method_header(&mut encoder, 10, 11);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 11);
 
-    let table_bytes = table::encode_table(ruby, client_properties).map_err(Error::from)?;
-    encoder.write_bytes(&table_bytes);
+    table::write_table(ruby, client_properties, encoder).map_err(Error::from)?;
 
     let mech_str = unsafe { mechanism.as_slice() };
     encoder
@@ -83,36 +207,65 @@ fn encode_connection_start_ok(
         .write_short_string(std::str::from_utf8(locale_str).unwrap_or("en_US"))
         .map_err(|e| Error::new(magnus::exception::arg_error(), e.to_string()))?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_connection_secure_ok(response: RString) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 10, 21);
+method_encoders!(
+    ruby,
+    encode_connection_start_ok,
+    encode_connection_start_ok_into,
+    write_connection_start_ok,
+    256,
+    (client_properties: RHash, mechanism: RString, response: RString, locale: RString)
+);
+
+fn write_connection_secure_ok(
+    encoder: &mut Encoder,
+    response: RString,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 21);
 
     let resp_bytes = unsafe { response.as_slice() };
     encoder.write_long_string(resp_bytes);
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_connection_tune_ok(
+method_encoders!(
+    encode_connection_secure_ok,
+    encode_connection_secure_ok_into,
+    write_connection_secure_ok,
+    64,
+    (response: RString)
+);
+
+fn write_connection_tune_ok(
+    encoder: &mut Encoder,
     channel_max: u16,
     frame_max: u32,
     heartbeat: u16,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(16);
-    write_method_header(&mut encoder, 10, 31);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 31);
     encoder.write_u16(channel_max);
     encoder.write_u32(frame_max);
     encoder.write_u16(heartbeat);
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_connection_open(virtual_host: RString) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 10, 40);
+method_encoders!(
+    encode_connection_tune_ok,
+    encode_connection_tune_ok_into,
+    write_connection_tune_ok,
+    16,
+    (channel_max: u16, frame_max: u32, heartbeat: u16)
+);
+
+fn write_connection_open(
+    encoder: &mut Encoder,
+    virtual_host: RString,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 40);
 
     let vhost_str = unsafe { virtual_host.as_slice() };
     encoder
@@ -124,17 +277,25 @@ fn encode_connection_open(virtual_host: RString) -> std::result::Result<RString,
         .map_err(|e| Error::new(magnus::exception::arg_error(), e.to_string()))?;
     encoder.write_u8(0);
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_connection_close(
+method_encoders!(
+    encode_connection_open,
+    encode_connection_open_into,
+    write_connection_open,
+    64,
+    (virtual_host: RString)
+);
+
+fn write_connection_close(
+    encoder: &mut Encoder,
     reply_code: u16,
     reply_text: RString,
     class_id: u16,
     method_id: u16,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 10, 50);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 50);
 
     encoder.write_u16(reply_code);
 
@@ -146,39 +307,147 @@ fn encode_connection_close(
     encoder.write_u16(class_id);
     encoder.write_u16(method_id);
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
+}
+
+method_encoders!(
+    encode_connection_close,
+    encode_connection_close_into,
+    write_connection_close,
+    64,
+    (reply_code: u16, reply_text: RString, class_id: u16, method_id: u16)
+);
+
+const REPLY_SUCCESS: u16 = 200;
+
+/// Maps a reply code to the error class defined in `lib/amq/protocol.rb`.
+fn reply_code_error_class(reply_code: u16) -> Option<&'static str> {
+    let name = match reply_code {
+        311 => "ContentTooLarge",
+        312 => "NoRoute",
+        313 => "NoConsumers",
+        320 => "ConnectionForced",
+        402 => "InvalidPath",
+        403 => "AccessRefused",
+        404 => "NotFound",
+        405 => "ResourceLocked",
+        406 => "PreconditionFailed",
+        501 => "FrameError",
+        502 => "SyntaxError",
+        503 => "CommandInvalid",
+        504 => "ChannelError",
+        505 => "UnexpectedFrame",
+        506 => "ResourceError",
+        530 => "NotAllowed",
+        540 => "NotImplemented",
+        541 => "InternalError",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn read_close(data: &[u8]) -> Result<(u16, &[u8], u16, u16)> {
+    let mut decoder = Decoder::new(data);
+    let reply_code = decoder.read_u16()?;
+    let reply_text = decoder.read_short_string_bytes()?;
+    let class_id = decoder.read_u16()?;
+    let method_id = decoder.read_u16()?;
+    Ok((reply_code, reply_text, class_id, method_id))
 }
 
-fn encode_connection_close_ok() -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 10, 51);
-    Ok(RString::from_slice(encoder.as_slice()))
+/// Decodes connection.close or channel.close arguments into an instance of the
+/// error class matching the reply code, or `nil` for a normal shutdown.
+/// Unknown reply codes use `fallback` (`HardError` or `SoftError`).
+fn decode_close(
+    ruby: &Ruby,
+    data: RString,
+    fallback: &str,
+) -> std::result::Result<Option<Exception>, Error> {
+    let bytes = unsafe { data.as_slice() };
+    let (reply_code, reply_text, class_id, method_id) = read_close(bytes).map_err(Error::from)?;
+
+    if reply_code == REPLY_SUCCESS {
+        return Ok(None);
+    }
+
+    let reply_text = ruby.str_new(&String::from_utf8_lossy(reply_text));
+    let kwargs = ruby.hash_new();
+    kwargs.aset(ruby.sym_new("reply_code"), reply_code)?;
+    kwargs.aset(ruby.sym_new("reply_text"), reply_text)?;
+    kwargs.aset(ruby.sym_new("class_id"), class_id)?;
+    kwargs.aset(ruby.sym_new("method_id"), method_id)?;
+    kwargs.aset(
+        ruby.sym_new("method_name"),
+        method_name(class_id, method_id),
+    )?;
+
+    let class_name = reply_code_error_class(reply_code).unwrap_or(fallback);
+    protocol_exception(class_name)
+        .new_instance((reply_text, KwArgs(kwargs)))
+        .map(Some)
+}
+
+fn decode_connection_close(
+    ruby: &Ruby,
+    data: RString,
+) -> std::result::Result<Option<Exception>, Error> {
+    decode_close(ruby, data, "HardError")
+}
+
+fn write_connection_close_ok(encoder: &mut Encoder) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 51);
+    Ok(())
 }
 
-fn encode_connection_blocked(reason: RString) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 10, 60);
+method_encoders!(
+    encode_connection_close_ok,
+    encode_connection_close_ok_into,
+    write_connection_close_ok,
+    8,
+    ()
+);
+
+fn write_connection_blocked(
+    encoder: &mut Encoder,
+    reason: RString,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 60);
 
     let reason_str = unsafe { reason.as_slice() };
     encoder
         .write_short_string(std::str::from_utf8(reason_str).unwrap_or(""))
         .map_err(|e| Error::new(magnus::exception::arg_error(), e.to_string()))?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_connection_unblocked() -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 10, 61);
-    Ok(RString::from_slice(encoder.as_slice()))
+method_encoders!(
+    encode_connection_blocked,
+    encode_connection_blocked_into,
+    write_connection_blocked,
+    64,
+    (reason: RString)
+);
+
+fn write_connection_unblocked(encoder: &mut Encoder) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 61);
+    Ok(())
 }
 
-fn encode_connection_update_secret(
+method_encoders!(
+    encode_connection_unblocked,
+    encode_connection_unblocked_into,
+    write_connection_unblocked,
+    8,
+    ()
+);
+
+fn write_connection_update_secret(
+    encoder: &mut Encoder,
     new_secret: RString,
     reason: RString,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(128);
-    write_method_header(&mut encoder, 10, 70);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 70);
 
     let secret_bytes = unsafe { new_secret.as_slice() };
     encoder.write_long_string(secret_bytes);
@@ -188,49 +457,88 @@ fn encode_connection_update_secret(
         .write_short_string(std::str::from_utf8(reason_str).unwrap_or(""))
         .map_err(|e| Error::new(magnus::exception::arg_error(), e.to_string()))?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_connection_update_secret_ok() -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 10, 71);
-    Ok(RString::from_slice(encoder.as_slice()))
+method_encoders!(
+    encode_connection_update_secret,
+    encode_connection_update_secret_into,
+    write_connection_update_secret,
+    128,
+    (new_secret: RString, reason: RString)
+);
+
+fn write_connection_update_secret_ok(encoder: &mut Encoder) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 10, 71);
+    Ok(())
 }
 
-fn encode_channel_open(out_of_band: RString) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(32);
-    write_method_header(&mut encoder, 20, 10);
+method_encoders!(
+    encode_connection_update_secret_ok,
+    encode_connection_update_secret_ok_into,
+    write_connection_update_secret_ok,
+    8,
+    ()
+);
+
+fn write_channel_open(
+    encoder: &mut Encoder,
+    out_of_band: RString,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 20, 10);
 
     let oob_str = unsafe { out_of_band.as_slice() };
     encoder
         .write_short_string(std::str::from_utf8(oob_str).unwrap_or(""))
         .map_err(|e| Error::new(magnus::exception::arg_error(), e.to_string()))?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_channel_flow(active: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 20, 20);
+method_encoders!(
+    encode_channel_open,
+    encode_channel_open_into,
+    write_channel_open,
+    32,
+    (out_of_band: RString)
+);
+
+fn write_channel_flow(encoder: &mut Encoder, active: bool) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 20, 20);
     encoder.write_u8(if active { 1 } else { 0 });
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_channel_flow_ok(active: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 20, 21);
+method_encoders!(
+    encode_channel_flow,
+    encode_channel_flow_into,
+    write_channel_flow,
+    8,
+    (active: bool)
+);
+
+fn write_channel_flow_ok(encoder: &mut Encoder, active: bool) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 20, 21);
     encoder.write_u8(if active { 1 } else { 0 });
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_channel_close(
+method_encoders!(
+    encode_channel_flow_ok,
+    encode_channel_flow_ok_into,
+    write_channel_flow_ok,
+    8,
+    (active: bool)
+);
+
+fn write_channel_close(
+    encoder: &mut Encoder,
     reply_code: u16,
     reply_text: RString,
     class_id: u16,
     method_id: u16,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 20, 40);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 20, 40);
 
     encoder.write_u16(reply_code);
 
@@ -242,18 +550,41 @@ fn encode_channel_close(
     encoder.write_u16(class_id);
     encoder.write_u16(method_id);
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_channel_close_ok() -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 20, 41);
-    Ok(RString::from_slice(encoder.as_slice()))
+method_encoders!(
+    encode_channel_close,
+    encode_channel_close_into,
+    write_channel_close,
+    64,
+    (reply_code: u16, reply_text: RString, class_id: u16, method_id: u16)
+);
+
+fn decode_channel_close(
+    ruby: &Ruby,
+    data: RString,
+) -> std::result::Result<Option<Exception>, Error> {
+    decode_close(ruby, data, "SoftError")
 }
 
+fn write_channel_close_ok(encoder: &mut Encoder) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 20, 41);
+    Ok(())
+}
+
+method_encoders!(
+    encode_channel_close_ok,
+    encode_channel_close_ok_into,
+    write_channel_close_ok,
+    8,
+    ()
+);
+
 #[allow(clippy::too_many_arguments)]
-fn encode_exchange_declare(
+fn write_exchange_declare(
     ruby: &Ruby,
+    encoder: &mut Encoder,
     exchange: RString,
     exchange_type: RString,
     passive: bool,
@@ -262,9 +593,8 @@ fn encode_exchange_declare(
     internal: bool,
     nowait: bool,
     arguments: RHash,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(256);
-    write_method_header(&mut encoder, 40, 10);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 40, 10);
     encoder.write_u16(0);
 
     let exch_str = unsafe { exchange.as_slice() };
@@ -295,19 +625,36 @@ fn encode_exchange_declare(
     }
     encoder.write_u8(flags);
 
-    let table_bytes = table::encode_table(ruby, arguments).map_err(Error::from)?;
-    encoder.write_bytes(&table_bytes);
+    table::write_table(ruby, arguments, encoder).map_err(Error::from)?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_exchange_delete(
+method_encoders!(
+    ruby,
+    encode_exchange_declare,
+    encode_exchange_declare_into,
+    write_exchange_declare,
+    256,
+    (
+        exchange: RString,
+        exchange_type: RString,
+        passive: bool,
+        durable: bool,
+        auto_delete: bool,
+        internal: bool,
+        nowait: bool,
+        arguments: RHash,
+    )
+);
+
+fn write_exchange_delete(
+    encoder: &mut Encoder,
     exchange: RString,
     if_unused: bool,
     nowait: bool,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 40, 20);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 40, 20);
     encoder.write_u16(0);
 
     let exch_str = unsafe { exchange.as_slice() };
@@ -324,19 +671,27 @@ fn encode_exchange_delete(
     }
     encoder.write_u8(flags);
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_exchange_bind(
+method_encoders!(
+    encode_exchange_delete,
+    encode_exchange_delete_into,
+    write_exchange_delete,
+    64,
+    (exchange: RString, if_unused: bool, nowait: bool)
+);
+
+fn write_exchange_bind(
     ruby: &Ruby,
+    encoder: &mut Encoder,
     destination: RString,
     source: RString,
     routing_key: RString,
     nowait: bool,
     arguments: RHash,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(256);
-    write_method_header(&mut encoder, 40, 30);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 40, 30);
     encoder.write_u16(0);
 
     let dest_str = unsafe { destination.as_slice() };
@@ -356,22 +711,30 @@ fn encode_exchange_bind(
 
     encoder.write_u8(if nowait { 1 } else { 0 });
 
-    let table_bytes = table::encode_table(ruby, arguments).map_err(Error::from)?;
-    encoder.write_bytes(&table_bytes);
+    table::write_table(ruby, arguments, encoder).map_err(Error::from)?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_exchange_unbind(
+method_encoders!(
+    ruby,
+    encode_exchange_bind,
+    encode_exchange_bind_into,
+    write_exchange_bind,
+    256,
+    (destination: RString, source: RString, routing_key: RString, nowait: bool, arguments: RHash)
+);
+
+fn write_exchange_unbind(
     ruby: &Ruby,
+    encoder: &mut Encoder,
     destination: RString,
     source: RString,
     routing_key: RString,
     nowait: bool,
     arguments: RHash,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(256);
-    write_method_header(&mut encoder, 40, 40);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 40, 40);
     encoder.write_u16(0);
 
     let dest_str = unsafe { destination.as_slice() };
@@ -391,15 +754,24 @@ fn encode_exchange_unbind(
 
     encoder.write_u8(if nowait { 1 } else { 0 });
 
-    let table_bytes = table::encode_table(ruby, arguments).map_err(Error::from)?;
-    encoder.write_bytes(&table_bytes);
+    table::write_table(ruby, arguments, encoder).map_err(Error::from)?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
+method_encoders!(
+    ruby,
+    encode_exchange_unbind,
+    encode_exchange_unbind_into,
+    write_exchange_unbind,
+    256,
+    (destination: RString, source: RString, routing_key: RString, nowait: bool, arguments: RHash)
+);
+
 #[allow(clippy::too_many_arguments)]
-fn encode_queue_declare(
+fn write_queue_declare(
     ruby: &Ruby,
+    encoder: &mut Encoder,
     queue: RString,
     passive: bool,
     durable: bool,
@@ -407,9 +779,8 @@ fn encode_queue_declare(
     auto_delete: bool,
     nowait: bool,
     arguments: RHash,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(256);
-    write_method_header(&mut encoder, 50, 10);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 50, 10);
     encoder.write_u16(0);
 
     let queue_str = unsafe { queue.as_slice() };
@@ -435,22 +806,38 @@ fn encode_queue_declare(
     }
     encoder.write_u8(flags);
 
-    let table_bytes = table::encode_table(ruby, arguments).map_err(Error::from)?;
-    encoder.write_bytes(&table_bytes);
+    table::write_table(ruby, arguments, encoder).map_err(Error::from)?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_queue_bind(
+method_encoders!(
+    ruby,
+    encode_queue_declare,
+    encode_queue_declare_into,
+    write_queue_declare,
+    256,
+    (
+        queue: RString,
+        passive: bool,
+        durable: bool,
+        exclusive: bool,
+        auto_delete: bool,
+        nowait: bool,
+        arguments: RHash,
+    )
+);
+
+fn write_queue_bind(
     ruby: &Ruby,
+    encoder: &mut Encoder,
     queue: RString,
     exchange: RString,
     routing_key: RString,
     nowait: bool,
     arguments: RHash,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(256);
-    write_method_header(&mut encoder, 50, 20);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 50, 20);
     encoder.write_u16(0);
 
     let queue_str = unsafe { queue.as_slice() };
@@ -470,21 +857,29 @@ fn encode_queue_bind(
 
     encoder.write_u8(if nowait { 1 } else { 0 });
 
-    let table_bytes = table::encode_table(ruby, arguments).map_err(Error::from)?;
-    encoder.write_bytes(&table_bytes);
+    table::write_table(ruby, arguments, encoder).map_err(Error::from)?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_queue_unbind(
+method_encoders!(
+    ruby,
+    encode_queue_bind,
+    encode_queue_bind_into,
+    write_queue_bind,
+    256,
+    (queue: RString, exchange: RString, routing_key: RString, nowait: bool, arguments: RHash)
+);
+
+fn write_queue_unbind(
     ruby: &Ruby,
+    encoder: &mut Encoder,
     queue: RString,
     exchange: RString,
     routing_key: RString,
     arguments: RHash,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(256);
-    write_method_header(&mut encoder, 50, 50);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 50, 50);
     encoder.write_u16(0);
 
     let queue_str = unsafe { queue.as_slice() };
@@ -502,15 +897,26 @@ fn encode_queue_unbind(
         .write_short_string(std::str::from_utf8(rk_str).unwrap_or(""))
         .map_err(|e| Error::new(magnus::exception::arg_error(), e.to_string()))?;
 
-    let table_bytes = table::encode_table(ruby, arguments).map_err(Error::from)?;
-    encoder.write_bytes(&table_bytes);
+    table::write_table(ruby, arguments, encoder).map_err(Error::from)?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_queue_purge(queue: RString, nowait: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 50, 30);
+method_encoders!(
+    ruby,
+    encode_queue_unbind,
+    encode_queue_unbind_into,
+    write_queue_unbind,
+    256,
+    (queue: RString, exchange: RString, routing_key: RString, arguments: RHash)
+);
+
+fn write_queue_purge(
+    encoder: &mut Encoder,
+    queue: RString,
+    nowait: bool,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 50, 30);
 
     encoder.write_u16(0);
 
@@ -521,17 +927,25 @@ fn encode_queue_purge(queue: RString, nowait: bool) -> std::result::Result<RStri
 
     encoder.write_u8(if nowait { 1 } else { 0 });
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_queue_delete(
+method_encoders!(
+    encode_queue_purge,
+    encode_queue_purge_into,
+    write_queue_purge,
+    64,
+    (queue: RString, nowait: bool)
+);
+
+fn write_queue_delete(
+    encoder: &mut Encoder,
     queue: RString,
     if_unused: bool,
     if_empty: bool,
     nowait: bool,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 50, 40);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 50, 40);
 
     encoder.write_u16(0);
 
@@ -552,27 +966,44 @@ fn encode_queue_delete(
     }
     encoder.write_u8(flags);
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_basic_qos(
+method_encoders!(
+    encode_queue_delete,
+    encode_queue_delete_into,
+    write_queue_delete,
+    64,
+    (queue: RString, if_unused: bool, if_empty: bool, nowait: bool)
+);
+
+fn write_basic_qos(
+    encoder: &mut Encoder,
     prefetch_size: u32,
     prefetch_count: u16,
     global: bool,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(16);
-    write_method_header(&mut encoder, 60, 10);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 10);
 
     encoder.write_u32(prefetch_size);
     encoder.write_u16(prefetch_count);
     encoder.write_u8(if global { 1 } else { 0 });
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
+method_encoders!(
+    encode_basic_qos,
+    encode_basic_qos_into,
+    write_basic_qos,
+    16,
+    (prefetch_size: u32, prefetch_count: u16, global: bool)
+);
+
 #[allow(clippy::too_many_arguments)]
-fn encode_basic_consume(
+fn write_basic_consume(
     ruby: &Ruby,
+    encoder: &mut Encoder,
     queue: RString,
     consumer_tag: RString,
     no_local: bool,
@@ -580,9 +1011,8 @@ fn encode_basic_consume(
     exclusive: bool,
     nowait: bool,
     arguments: RHash,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(256);
-    write_method_header(&mut encoder, 60, 20);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 20);
     encoder.write_u16(0);
 
     let queue_str = unsafe { queue.as_slice() };
@@ -610,15 +1040,34 @@ fn encode_basic_consume(
     }
     encoder.write_u8(flags);
 
-    let table_bytes = table::encode_table(ruby, arguments).map_err(Error::from)?;
-    encoder.write_bytes(&table_bytes);
+    table::write_table(ruby, arguments, encoder).map_err(Error::from)?;
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_basic_cancel(consumer_tag: RString, nowait: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 60, 30);
+method_encoders!(
+    ruby,
+    encode_basic_consume,
+    encode_basic_consume_into,
+    write_basic_consume,
+    256,
+    (
+        queue: RString,
+        consumer_tag: RString,
+        no_local: bool,
+        no_ack: bool,
+        exclusive: bool,
+        nowait: bool,
+        arguments: RHash,
+    )
+);
+
+fn write_basic_cancel(
+    encoder: &mut Encoder,
+    consumer_tag: RString,
+    nowait: bool,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 30);
 
     let tag_str = unsafe { consumer_tag.as_slice() };
     encoder
@@ -627,17 +1076,25 @@ fn encode_basic_cancel(consumer_tag: RString, nowait: bool) -> std::result::Resu
 
     encoder.write_u8(if nowait { 1 } else { 0 });
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_basic_publish(
+method_encoders!(
+    encode_basic_cancel,
+    encode_basic_cancel_into,
+    write_basic_cancel,
+    64,
+    (consumer_tag: RString, nowait: bool)
+);
+
+pub fn write_basic_publish(
+    encoder: &mut Encoder,
     exchange: RString,
     routing_key: RString,
     mandatory: bool,
     immediate: bool,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(128);
-    write_method_header(&mut encoder, 60, 40);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 40);
     encoder.write_u16(0);
 
     let exch_str = unsafe { exchange.as_slice() };
@@ -659,12 +1116,23 @@ fn encode_basic_publish(
     }
     encoder.write_u8(flags);
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_basic_get(queue: RString, no_ack: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(64);
-    write_method_header(&mut encoder, 60, 70);
+method_encoders!(
+    encode_basic_publish,
+    encode_basic_publish_into,
+    write_basic_publish,
+    128,
+    (exchange: RString, routing_key: RString, mandatory: bool, immediate: bool)
+);
+
+fn write_basic_get(
+    encoder: &mut Encoder,
+    queue: RString,
+    no_ack: bool,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 70);
     encoder.write_u16(0);
 
     let queue_str = unsafe { queue.as_slice() };
@@ -674,36 +1142,66 @@ fn encode_basic_get(queue: RString, no_ack: bool) -> std::result::Result<RString
 
     encoder.write_u8(if no_ack { 1 } else { 0 });
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_basic_ack(delivery_tag: u64, multiple: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(16);
-    write_method_header(&mut encoder, 60, 80);
+method_encoders!(
+    encode_basic_get,
+    encode_basic_get_into,
+    write_basic_get,
+    64,
+    (queue: RString, no_ack: bool)
+);
+
+pub fn write_basic_ack(
+    encoder: &mut Encoder,
+    delivery_tag: u64,
+    multiple: bool,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 80);
 
     encoder.write_bytes(&pack_u64_be(delivery_tag));
     encoder.write_u8(if multiple { 1 } else { 0 });
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_basic_reject(delivery_tag: u64, requeue: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(16);
-    write_method_header(&mut encoder, 60, 90);
+method_encoders!(
+    encode_basic_ack,
+    encode_basic_ack_into,
+    write_basic_ack,
+    16,
+    (delivery_tag: u64, multiple: bool)
+);
+
+fn write_basic_reject(
+    encoder: &mut Encoder,
+    delivery_tag: u64,
+    requeue: bool,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 90);
 
     encoder.write_bytes(&pack_u64_be(delivery_tag));
     encoder.write_u8(if requeue { 1 } else { 0 });
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_basic_nack(
+method_encoders!(
+    encode_basic_reject,
+    encode_basic_reject_into,
+    write_basic_reject,
+    16,
+    (delivery_tag: u64, requeue: bool)
+);
+
+pub fn write_basic_nack(
+    encoder: &mut Encoder,
     delivery_tag: u64,
     multiple: bool,
     requeue: bool,
-) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(16);
-    write_method_header(&mut encoder, 60, 120);
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 120);
 
     encoder.write_bytes(&pack_u64_be(delivery_tag));
 
@@ -716,321 +1214,441 @@ fn encode_basic_nack(
     }
     encoder.write_u8(flags);
 
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_basic_recover(requeue: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 60, 110);
+method_encoders!(
+    encode_basic_nack,
+    encode_basic_nack_into,
+    write_basic_nack,
+    16,
+    (delivery_tag: u64, multiple: bool, requeue: bool)
+);
+
+fn write_basic_recover(encoder: &mut Encoder, requeue: bool) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 110);
     encoder.write_u8(if requeue { 1 } else { 0 });
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_basic_recover_async(requeue: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 60, 100);
+method_encoders!(
+    encode_basic_recover,
+    encode_basic_recover_into,
+    write_basic_recover,
+    8,
+    (requeue: bool)
+);
+
+fn write_basic_recover_async(
+    encoder: &mut Encoder,
+    requeue: bool,
+) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 60, 100);
     encoder.write_u8(if requeue { 1 } else { 0 });
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_tx_select() -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 90, 10);
-    Ok(RString::from_slice(encoder.as_slice()))
+method_encoders!(
+    encode_basic_recover_async,
+    encode_basic_recover_async_into,
+    write_basic_recover_async,
+    8,
+    (requeue: bool)
+);
+
+fn write_tx_select(encoder: &mut Encoder) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 90, 10);
+    Ok(())
 }
 
-fn encode_tx_commit() -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 90, 20);
-    Ok(RString::from_slice(encoder.as_slice()))
+method_encoders!(
+    encode_tx_select,
+    encode_tx_select_into,
+    write_tx_select,
+    8,
+    ()
+);
+
+fn write_tx_commit(encoder: &mut Encoder) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 90, 20);
+    Ok(())
 }
 
-fn encode_tx_rollback() -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 90, 30);
-    Ok(RString::from_slice(encoder.as_slice()))
+method_encoders!(
+    encode_tx_commit,
+    encode_tx_commit_into,
+    write_tx_commit,
+    8,
+    ()
+);
+
+fn write_tx_rollback(encoder: &mut Encoder) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 90, 30);
+    Ok(())
 }
 
-fn encode_confirm_select(nowait: bool) -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 85, 10);
+method_encoders!(
+    encode_tx_rollback,
+    encode_tx_rollback_into,
+    write_tx_rollback,
+    8,
+    ()
+);
+
+fn write_confirm_select(encoder: &mut Encoder, nowait: bool) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 85, 10);
     encoder.write_u8(if nowait { 1 } else { 0 });
-    Ok(RString::from_slice(encoder.as_slice()))
+    Ok(())
 }
 
-fn encode_confirm_select_ok() -> std::result::Result<RString, Error> {
-    let mut encoder = Encoder::with_capacity(8);
-    write_method_header(&mut encoder, 85, 11);
-    Ok(RString::from_slice(encoder.as_slice()))
+method_encoders!(
+    encode_confirm_select,
+    encode_confirm_select_into,
+    write_confirm_select,
+    8,
+    (nowait: bool)
+);
+
+fn write_confirm_select_ok(encoder: &mut Encoder) -> std::result::Result<(), Error> {
+    write_method_header(encoder, 85, 11);
+    Ok(())
 }
 
+method_encoders!(
+    encode_confirm_select_ok,
+    encode_confirm_select_ok_into,
+    write_confirm_select_ok,
+    8,
+    ()
+);
+
 pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
     let class_base = protocol.define_class("Class", ruby.class_object())?;
     let method_base = protocol.define_class("Method", ruby.class_object())?;
 
     let connection = protocol.define_class("Connection", class_base)?;
-    connection.const_set("@name", "connection")?;
+    connection.const_set("@name", frozen_str("connection"))?;
     connection.const_set("@method_id", 10)?;
 
     let start_ok = connection.define_class("StartOk", method_base)?;
-    start_ok.const_set("@name", "connection.start-ok")?;
+    start_ok.const_set("@name", frozen_str("connection.start-ok"))?;
     start_ok.const_set("@method_id", 11)?;
     start_ok.const_set("@index", indices::CONNECTION_START_OK)?;
     start_ok.define_singleton_method("encode", function!(encode_connection_start_ok, 4))?;
+    start_ok
+        .define_singleton_method("encode_into", function!(encode_connection_start_ok_into, 5))?;
 
     let secure_ok = connection.define_class("SecureOk", method_base)?;
-    secure_ok.const_set("@name", "connection.secure-ok")?;
+    secure_ok.const_set("@name", frozen_str("connection.secure-ok"))?;
     secure_ok.const_set("@method_id", 21)?;
     secure_ok.const_set("@index", indices::CONNECTION_SECURE_OK)?;
     secure_ok.define_singleton_method("encode", function!(encode_connection_secure_ok, 1))?;
+    secure_ok.define_singleton_method(
+        "encode_into",
+        function!(encode_connection_secure_ok_into, 2),
+    )?;
 
     let tune_ok = connection.define_class("TuneOk", method_base)?;
-    tune_ok.const_set("@name", "connection.tune-ok")?;
+    tune_ok.const_set("@name", frozen_str("connection.tune-ok"))?;
     tune_ok.const_set("@method_id", 31)?;
     tune_ok.const_set("@index", indices::CONNECTION_TUNE_OK)?;
     tune_ok.define_singleton_method("encode", function!(encode_connection_tune_ok, 3))?;
+    tune_ok.define_singleton_method("encode_into", function!(encode_connection_tune_ok_into, 4))?;
 
     let open = connection.define_class("Open", method_base)?;
-    open.const_set("@name", "connection.open")?;
+    open.const_set("@name", frozen_str("connection.open"))?;
     open.const_set("@method_id", 40)?;
     open.const_set("@index", indices::CONNECTION_OPEN)?;
     open.define_singleton_method("encode", function!(encode_connection_open, 1))?;
+    open.define_singleton_method("encode_into", function!(encode_connection_open_into, 2))?;
 
     let close = connection.define_class("Close", method_base)?;
-    close.const_set("@name", "connection.close")?;
+    close.const_set("@name", frozen_str("connection.close"))?;
     close.const_set("@method_id", 50)?;
     close.const_set("@index", indices::CONNECTION_CLOSE)?;
     close.define_singleton_method("encode", function!(encode_connection_close, 4))?;
+    close.define_singleton_method("encode_into", function!(encode_connection_close_into, 5))?;
+    close.define_singleton_method("decode", function!(decode_connection_close, 1))?;
 
     let close_ok = connection.define_class("CloseOk", method_base)?;
-    close_ok.const_set("@name", "connection.close-ok")?;
+    close_ok.const_set("@name", frozen_str("connection.close-ok"))?;
     close_ok.const_set("@method_id", 51)?;
     close_ok.const_set("@index", indices::CONNECTION_CLOSE_OK)?;
     close_ok.define_singleton_method("encode", function!(encode_connection_close_ok, 0))?;
+    close_ok
+        .define_singleton_method("encode_into", function!(encode_connection_close_ok_into, 1))?;
 
     let blocked = connection.define_class("Blocked", method_base)?;
-    blocked.const_set("@name", "connection.blocked")?;
+    blocked.const_set("@name", frozen_str("connection.blocked"))?;
     blocked.const_set("@method_id", 60)?;
     blocked.const_set("@index", indices::CONNECTION_BLOCKED)?;
     blocked.define_singleton_method("encode", function!(encode_connection_blocked, 1))?;
+    blocked.define_singleton_method("encode_into", function!(encode_connection_blocked_into, 2))?;
 
     let unblocked = connection.define_class("Unblocked", method_base)?;
-    unblocked.const_set("@name", "connection.unblocked")?;
+    unblocked.const_set("@name", frozen_str("connection.unblocked"))?;
     unblocked.const_set("@method_id", 61)?;
     unblocked.const_set("@index", indices::CONNECTION_UNBLOCKED)?;
     unblocked.define_singleton_method("encode", function!(encode_connection_unblocked, 0))?;
+    unblocked.define_singleton_method(
+        "encode_into",
+        function!(encode_connection_unblocked_into, 1),
+    )?;
 
     let update_secret = connection.define_class("UpdateSecret", method_base)?;
-    update_secret.const_set("@name", "connection.update-secret")?;
+    update_secret.const_set("@name", frozen_str("connection.update-secret"))?;
     update_secret.const_set("@method_id", 70)?;
     update_secret.const_set("@index", indices::CONNECTION_UPDATE_SECRET)?;
     update_secret
         .define_singleton_method("encode", function!(encode_connection_update_secret, 2))?;
+    update_secret.define_singleton_method(
+        "encode_into",
+        function!(encode_connection_update_secret_into, 3),
+    )?;
 
     let update_secret_ok = connection.define_class("UpdateSecretOk", method_base)?;
-    update_secret_ok.const_set("@name", "connection.update-secret-ok")?;
+    update_secret_ok.const_set("@name", frozen_str("connection.update-secret-ok"))?;
     update_secret_ok.const_set("@method_id", 71)?;
     update_secret_ok.const_set("@index", indices::CONNECTION_UPDATE_SECRET_OK)?;
     update_secret_ok
         .define_singleton_method("encode", function!(encode_connection_update_secret_ok, 0))?;
+    update_secret_ok.define_singleton_method(
+        "encode_into",
+        function!(encode_connection_update_secret_ok_into, 1),
+    )?;
 
     let channel = protocol.define_class("Channel", class_base)?;
-    channel.const_set("@name", "channel")?;
+    channel.const_set("@name", frozen_str("channel"))?;
     channel.const_set("@method_id", 20)?;
 
     let ch_open = channel.define_class("Open", method_base)?;
-    ch_open.const_set("@name", "channel.open")?;
+    ch_open.const_set("@name", frozen_str("channel.open"))?;
     ch_open.const_set("@method_id", 10)?;
     ch_open.const_set("@index", indices::CHANNEL_OPEN)?;
     ch_open.define_singleton_method("encode", function!(encode_channel_open, 1))?;
+    ch_open.define_singleton_method("encode_into", function!(encode_channel_open_into, 2))?;
 
     let ch_flow = channel.define_class("Flow", method_base)?;
-    ch_flow.const_set("@name", "channel.flow")?;
+    ch_flow.const_set("@name", frozen_str("channel.flow"))?;
     ch_flow.const_set("@method_id", 20)?;
     ch_flow.const_set("@index", indices::CHANNEL_FLOW)?;
     ch_flow.define_singleton_method("encode", function!(encode_channel_flow, 1))?;
+    ch_flow.define_singleton_method("encode_into", function!(encode_channel_flow_into, 2))?;
 
     let ch_flow_ok = channel.define_class("FlowOk", method_base)?;
-    ch_flow_ok.const_set("@name", "channel.flow-ok")?;
+    ch_flow_ok.const_set("@name", frozen_str("channel.flow-ok"))?;
     ch_flow_ok.const_set("@method_id", 21)?;
     ch_flow_ok.const_set("@index", indices::CHANNEL_FLOW_OK)?;
     ch_flow_ok.define_singleton_method("encode", function!(encode_channel_flow_ok, 1))?;
+    ch_flow_ok.define_singleton_method("encode_into", function!(encode_channel_flow_ok_into, 2))?;
 
     let ch_close = channel.define_class("Close", method_base)?;
-    ch_close.const_set("@name", "channel.close")?;
+    ch_close.const_set("@name", frozen_str("channel.close"))?;
     ch_close.const_set("@method_id", 40)?;
     ch_close.const_set("@index", indices::CHANNEL_CLOSE)?;
     ch_close.define_singleton_method("encode", function!(encode_channel_close, 4))?;
+    ch_close.define_singleton_method("encode_into", function!(encode_channel_close_into, 5))?;
+    ch_close.define_singleton_method("decode", function!(decode_channel_close, 1))?;
 
     let ch_close_ok = channel.define_class("CloseOk", method_base)?;
-    ch_close_ok.const_set("@name", "channel.close-ok")?;
+    ch_close_ok.const_set("@name", frozen_str("channel.close-ok"))?;
     ch_close_ok.const_set("@method_id", 41)?;
     ch_close_ok.const_set("@index", indices::CHANNEL_CLOSE_OK)?;
     ch_close_ok.define_singleton_method("encode", function!(encode_channel_close_ok, 0))?;
+    ch_close_ok
+        .define_singleton_method("encode_into", function!(encode_channel_close_ok_into, 1))?;
 
     let exchange = protocol.define_class("Exchange", class_base)?;
-    exchange.const_set("@name", "exchange")?;
+    exchange.const_set("@name", frozen_str("exchange"))?;
     exchange.const_set("@method_id", 40)?;
 
     let ex_declare = exchange.define_class("Declare", method_base)?;
-    ex_declare.const_set("@name", "exchange.declare")?;
+    ex_declare.const_set("@name", frozen_str("exchange.declare"))?;
     ex_declare.const_set("@method_id", 10)?;
     ex_declare.const_set("@index", indices::EXCHANGE_DECLARE)?;
     ex_declare.define_singleton_method("encode", function!(encode_exchange_declare, 8))?;
+    ex_declare
+        .define_singleton_method("encode_into", function!(encode_exchange_declare_into, 9))?;
 
     let ex_delete = exchange.define_class("Delete", method_base)?;
-    ex_delete.const_set("@name", "exchange.delete")?;
+    ex_delete.const_set("@name", frozen_str("exchange.delete"))?;
     ex_delete.const_set("@method_id", 20)?;
     ex_delete.const_set("@index", indices::EXCHANGE_DELETE)?;
     ex_delete.define_singleton_method("encode", function!(encode_exchange_delete, 3))?;
+    ex_delete.define_singleton_method("encode_into", function!(encode_exchange_delete_into, 4))?;
 
     let ex_bind = exchange.define_class("Bind", method_base)?;
-    ex_bind.const_set("@name", "exchange.bind")?;
+    ex_bind.const_set("@name", frozen_str("exchange.bind"))?;
     ex_bind.const_set("@method_id", 30)?;
     ex_bind.const_set("@index", indices::EXCHANGE_BIND)?;
     ex_bind.define_singleton_method("encode", function!(encode_exchange_bind, 5))?;
+    ex_bind.define_singleton_method("encode_into", function!(encode_exchange_bind_into, 6))?;
 
     let ex_unbind = exchange.define_class("Unbind", method_base)?;
-    ex_unbind.const_set("@name", "exchange.unbind")?;
+    ex_unbind.const_set("@name", frozen_str("exchange.unbind"))?;
     ex_unbind.const_set("@method_id", 40)?;
     ex_unbind.const_set("@index", indices::EXCHANGE_UNBIND)?;
     ex_unbind.define_singleton_method("encode", function!(encode_exchange_unbind, 5))?;
+    ex_unbind.define_singleton_method("encode_into", function!(encode_exchange_unbind_into, 6))?;
 
     let queue = protocol.define_class("Queue", class_base)?;
-    queue.const_set("@name", "queue")?;
+    queue.const_set("@name", frozen_str("queue"))?;
     queue.const_set("@method_id", 50)?;
 
     let q_declare = queue.define_class("Declare", method_base)?;
-    q_declare.const_set("@name", "queue.declare")?;
+    q_declare.const_set("@name", frozen_str("queue.declare"))?;
     q_declare.const_set("@method_id", 10)?;
     q_declare.const_set("@index", indices::QUEUE_DECLARE)?;
     q_declare.define_singleton_method("encode", function!(encode_queue_declare, 7))?;
+    q_declare.define_singleton_method("encode_into", function!(encode_queue_declare_into, 8))?;
 
     let q_bind = queue.define_class("Bind", method_base)?;
-    q_bind.const_set("@name", "queue.bind")?;
+    q_bind.const_set("@name", frozen_str("queue.bind"))?;
     q_bind.const_set("@method_id", 20)?;
     q_bind.const_set("@index", indices::QUEUE_BIND)?;
     q_bind.define_singleton_method("encode", function!(encode_queue_bind, 5))?;
+    q_bind.define_singleton_method("encode_into", function!(encode_queue_bind_into, 6))?;
 
     let q_unbind = queue.define_class("Unbind", method_base)?;
-    q_unbind.const_set("@name", "queue.unbind")?;
+    q_unbind.const_set("@name", frozen_str("queue.unbind"))?;
     q_unbind.const_set("@method_id", 50)?;
     q_unbind.const_set("@index", indices::QUEUE_UNBIND)?;
     q_unbind.define_singleton_method("encode", function!(encode_queue_unbind, 4))?;
+    q_unbind.define_singleton_method("encode_into", function!(encode_queue_unbind_into, 5))?;
 
     let q_purge = queue.define_class("Purge", method_base)?;
-    q_purge.const_set("@name", "queue.purge")?;
+    q_purge.const_set("@name", frozen_str("queue.purge"))?;
     q_purge.const_set("@method_id", 30)?;
     q_purge.const_set("@index", indices::QUEUE_PURGE)?;
     q_purge.define_singleton_method("encode", function!(encode_queue_purge, 2))?;
+    q_purge.define_singleton_method("encode_into", function!(encode_queue_purge_into, 3))?;
 
     let q_delete = queue.define_class("Delete", method_base)?;
-    q_delete.const_set("@name", "queue.delete")?;
+    q_delete.const_set("@name", frozen_str("queue.delete"))?;
     q_delete.const_set("@method_id", 40)?;
     q_delete.const_set("@index", indices::QUEUE_DELETE)?;
     q_delete.define_singleton_method("encode", function!(encode_queue_delete, 4))?;
+    q_delete.define_singleton_method("encode_into", function!(encode_queue_delete_into, 5))?;
 
     let basic = protocol.define_class("Basic", class_base)?;
-    basic.const_set("@name", "basic")?;
+    basic.const_set("@name", frozen_str("basic"))?;
     basic.const_set("@method_id", 60)?;
 
     let b_qos = basic.define_class("Qos", method_base)?;
-    b_qos.const_set("@name", "basic.qos")?;
+    b_qos.const_set("@name", frozen_str("basic.qos"))?;
     b_qos.const_set("@method_id", 10)?;
     b_qos.const_set("@index", indices::BASIC_QOS)?;
     b_qos.define_singleton_method("encode", function!(encode_basic_qos, 3))?;
+    b_qos.define_singleton_method("encode_into", function!(encode_basic_qos_into, 4))?;
 
     let b_consume = basic.define_class("Consume", method_base)?;
-    b_consume.const_set("@name", "basic.consume")?;
+    b_consume.const_set("@name", frozen_str("basic.consume"))?;
     b_consume.const_set("@method_id", 20)?;
     b_consume.const_set("@index", indices::BASIC_CONSUME)?;
     b_consume.define_singleton_method("encode", function!(encode_basic_consume, 7))?;
+    b_consume.define_singleton_method("encode_into", function!(encode_basic_consume_into, 8))?;
 
     let b_cancel = basic.define_class("Cancel", method_base)?;
-    b_cancel.const_set("@name", "basic.cancel")?;
+    b_cancel.const_set("@name", frozen_str("basic.cancel"))?;
     b_cancel.const_set("@method_id", 30)?;
     b_cancel.const_set("@index", indices::BASIC_CANCEL)?;
     b_cancel.define_singleton_method("encode", function!(encode_basic_cancel, 2))?;
+    b_cancel.define_singleton_method("encode_into", function!(encode_basic_cancel_into, 3))?;
 
     let b_publish = basic.define_class("Publish", method_base)?;
-    b_publish.const_set("@name", "basic.publish")?;
+    b_publish.const_set("@name", frozen_str("basic.publish"))?;
     b_publish.const_set("@method_id", 40)?;
     b_publish.const_set("@index", indices::BASIC_PUBLISH)?;
     b_publish.define_singleton_method("encode", function!(encode_basic_publish, 4))?;
+    b_publish.define_singleton_method("encode_into", function!(encode_basic_publish_into, 5))?;
 
     let b_get = basic.define_class("Get", method_base)?;
-    b_get.const_set("@name", "basic.get")?;
+    b_get.const_set("@name", frozen_str("basic.get"))?;
     b_get.const_set("@method_id", 70)?;
     b_get.const_set("@index", indices::BASIC_GET)?;
     b_get.define_singleton_method("encode", function!(encode_basic_get, 2))?;
+    b_get.define_singleton_method("encode_into", function!(encode_basic_get_into, 3))?;
 
     let b_ack = basic.define_class("Ack", method_base)?;
-    b_ack.const_set("@name", "basic.ack")?;
+    b_ack.const_set("@name", frozen_str("basic.ack"))?;
     b_ack.const_set("@method_id", 80)?;
     b_ack.const_set("@index", indices::BASIC_ACK)?;
     b_ack.define_singleton_method("encode", function!(encode_basic_ack, 2))?;
+    b_ack.define_singleton_method("encode_into", function!(encode_basic_ack_into, 3))?;
 
     let b_reject = basic.define_class("Reject", method_base)?;
-    b_reject.const_set("@name", "basic.reject")?;
+    b_reject.const_set("@name", frozen_str("basic.reject"))?;
     b_reject.const_set("@method_id", 90)?;
     b_reject.const_set("@index", indices::BASIC_REJECT)?;
     b_reject.define_singleton_method("encode", function!(encode_basic_reject, 2))?;
+    b_reject.define_singleton_method("encode_into", function!(encode_basic_reject_into, 3))?;
 
     let b_nack = basic.define_class("Nack", method_base)?;
-    b_nack.const_set("@name", "basic.nack")?;
+    b_nack.const_set("@name", frozen_str("basic.nack"))?;
     b_nack.const_set("@method_id", 120)?;
     b_nack.const_set("@index", indices::BASIC_NACK)?;
     b_nack.define_singleton_method("encode", function!(encode_basic_nack, 3))?;
+    b_nack.define_singleton_method("encode_into", function!(encode_basic_nack_into, 4))?;
 
     let b_recover = basic.define_class("Recover", method_base)?;
-    b_recover.const_set("@name", "basic.recover")?;
+    b_recover.const_set("@name", frozen_str("basic.recover"))?;
     b_recover.const_set("@method_id", 110)?;
     b_recover.const_set("@index", indices::BASIC_RECOVER)?;
     b_recover.define_singleton_method("encode", function!(encode_basic_recover, 1))?;
+    b_recover.define_singleton_method("encode_into", function!(encode_basic_recover_into, 2))?;
 
     let b_recover_async = basic.define_class("RecoverAsync", method_base)?;
-    b_recover_async.const_set("@name", "basic.recover-async")?;
+    b_recover_async.const_set("@name", frozen_str("basic.recover-async"))?;
     b_recover_async.const_set("@method_id", 100)?;
     b_recover_async.const_set("@index", indices::BASIC_RECOVER_ASYNC)?;
     b_recover_async.define_singleton_method("encode", function!(encode_basic_recover_async, 1))?;
+    b_recover_async
+        .define_singleton_method("encode_into", function!(encode_basic_recover_async_into, 2))?;
 
     let tx = protocol.define_class("Tx", class_base)?;
-    tx.const_set("@name", "tx")?;
+    tx.const_set("@name", frozen_str("tx"))?;
     tx.const_set("@method_id", 90)?;
 
     let tx_select = tx.define_class("Select", method_base)?;
-    tx_select.const_set("@name", "tx.select")?;
+    tx_select.const_set("@name", frozen_str("tx.select"))?;
     tx_select.const_set("@method_id", 10)?;
     tx_select.const_set("@index", indices::TX_SELECT)?;
     tx_select.define_singleton_method("encode", function!(encode_tx_select, 0))?;
+    tx_select.define_singleton_method("encode_into", function!(encode_tx_select_into, 1))?;
 
     let tx_commit = tx.define_class("Commit", method_base)?;
-    tx_commit.const_set("@name", "tx.commit")?;
+    tx_commit.const_set("@name", frozen_str("tx.commit"))?;
     tx_commit.const_set("@method_id", 20)?;
     tx_commit.const_set("@index", indices::TX_COMMIT)?;
     tx_commit.define_singleton_method("encode", function!(encode_tx_commit, 0))?;
+    tx_commit.define_singleton_method("encode_into", function!(encode_tx_commit_into, 1))?;
 
     let tx_rollback = tx.define_class("Rollback", method_base)?;
-    tx_rollback.const_set("@name", "tx.rollback")?;
+    tx_rollback.const_set("@name", frozen_str("tx.rollback"))?;
     tx_rollback.const_set("@method_id", 30)?;
     tx_rollback.const_set("@index", indices::TX_ROLLBACK)?;
     tx_rollback.define_singleton_method("encode", function!(encode_tx_rollback, 0))?;
+    tx_rollback.define_singleton_method("encode_into", function!(encode_tx_rollback_into, 1))?;
 
     let confirm = protocol.define_class("Confirm", class_base)?;
-    confirm.const_set("@name", "confirm")?;
+    confirm.const_set("@name", frozen_str("confirm"))?;
     confirm.const_set("@method_id", 85)?;
 
     let confirm_select = confirm.define_class("Select", method_base)?;
-    confirm_select.const_set("@name", "confirm.select")?;
+    confirm_select.const_set("@name", frozen_str("confirm.select"))?;
     confirm_select.const_set("@method_id", 10)?;
     confirm_select.const_set("@index", indices::CONFIRM_SELECT)?;
     confirm_select.define_singleton_method("encode", function!(encode_confirm_select, 1))?;
+    confirm_select
+        .define_singleton_method("encode_into", function!(encode_confirm_select_into, 2))?;
 
     let confirm_select_ok = confirm.define_class("SelectOk", method_base)?;
-    confirm_select_ok.const_set("@name", "confirm.select-ok")?;
+    confirm_select_ok.const_set("@name", frozen_str("confirm.select-ok"))?;
     confirm_select_ok.const_set("@method_id", 11)?;
     confirm_select_ok.const_set("@index", indices::CONFIRM_SELECT_OK)?;
     confirm_select_ok.define_singleton_method("encode", function!(encode_confirm_select_ok, 0))?;
+    confirm_select_ok
+        .define_singleton_method("encode_into", function!(encode_confirm_select_ok_into, 1))?;
 
     Ok(())
 }
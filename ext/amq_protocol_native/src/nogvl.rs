@@ -0,0 +1,100 @@
+//! Running pure byte work without holding the GVL
+//!
+//! Releasing and reacquiring the GVL has a cost of its own, so only inputs of
+//! at least `NOGVL_THRESHOLD` bytes are processed without it. Closures given to
+//! these functions must not call into Ruby in any way, including allocating
+//! Ruby objects or raising exceptions.
+
+use std::ffi::c_void;
+use std::panic::{self, AssertUnwindSafe};
+
+use magnus::{prelude::*, rb_sys::AsRawValue, RString};
+
+pub const NOGVL_THRESHOLD: usize = 64 * 1024;
+
+struct Call<F, R> {
+    f: Option<F>,
+    result: Option<std::thread::Result<R>>,
+}
+
+unsafe extern "C" fn call<F, R>(data: *mut c_void) -> *mut c_void
+where
+    F: FnOnce() -> R,
+{
+    let call = &mut *(data as *mut Call<F, R>);
+    if let Some(f) = call.f.take() {
+        call.result = Some(panic::catch_unwind(AssertUnwindSafe(f)));
+    }
+    std::ptr::null_mut()
+}
+
+/// Runs `f`, releasing the GVL while it runs when `len` bytes of work is at
+/// least `NOGVL_THRESHOLD`. Panics are carried back to the calling thread.
+pub fn nogvl<F, R>(len: usize, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    if len < NOGVL_THRESHOLD {
+        return f();
+    }
+
+    let mut data = Call {
+        f: Some(f),
+        result: None,
+    };
+    unsafe {
+        rb_sys::rb_thread_call_without_gvl(
+            Some(call::<F, R>),
+            &mut data as *mut Call<F, R> as *mut c_void,
+            None,
+            std::ptr::null_mut(),
+        );
+    }
+
+    match data.result {
+        Some(Ok(result)) => result,
+        Some(Err(payload)) => panic::resume_unwind(payload),
+        None => unreachable!("rb_thread_call_without_gvl did not run the function"),
+    }
+}
+
+/// Returns a String whose bytes stay the same while the GVL is released:
+/// `string` itself when it is frozen or small enough to be read with the GVL
+/// held, otherwise a frozen copy sharing its buffer. Other threads can keep
+/// modifying `string`, which then gets a buffer of its own.
+pub fn pin(string: RString) -> RString {
+    if string.len() < NOGVL_THRESHOLD || string.is_frozen() {
+        return string;
+    }
+    RString::new_frozen(string)
+}
+
+/// Runs `f` over the contents of `string`, releasing the GVL for large
+/// strings. The bytes are read through `pin`, so threads encoding the same
+/// String at the same time do not get in each other's way.
+pub fn with_str_bytes<F, R>(string: RString, f: F) -> R
+where
+    F: FnOnce(&[u8]) -> R,
+{
+    let pinned = pin(string);
+    let bytes = unsafe { pinned.as_slice() };
+    let result = nogvl(bytes.len(), || f(bytes));
+    // The copy must stay on the stack, where the GC finds it, until here
+    std::hint::black_box(pinned);
+    result
+}
+
+/// Allocates a binary String of `len` bytes for the caller to fill in, for
+/// example from a closure given to `nogvl`.
+///
+/// # Safety
+///
+/// The returned slice must not be used once the String is made reachable
+/// from Ruby code, and the String must be fully written before then.
+pub unsafe fn new_binary_str(len: usize) -> (RString, &'static mut [u8]) {
+    let string = RString::buf_new(len);
+    let raw = string.as_raw();
+    rb_sys::rb_str_set_len(raw, len as _);
+    let ptr = rb_sys::RSTRING_PTR(raw) as *mut u8;
+    (string, std::slice::from_raw_parts_mut(ptr, len))
+}
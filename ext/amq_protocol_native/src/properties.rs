@@ -0,0 +1,227 @@
+//! AMQP 0.9.1 content header (basic properties) encoding
+
+use magnus::{
+    function, prelude::*, r_hash::ForEach, Error, Module, RClass, RHash, RString, Ruby, TryConvert,
+    Value,
+};
+
+use crate::error::AmqpError;
+use crate::table::{self, EncodeOptions};
+use crate::types::Encoder;
+
+const BASIC_CLASS_ID: u16 = 60;
+
+#[derive(Clone, Copy)]
+enum PropertyType {
+    ShortString,
+    Octet,
+    Timestamp,
+    Table,
+}
+
+/// Basic properties in wire order, the first one using the highest flag bit.
+const PROPERTIES: [(&str, PropertyType); 14] = [
+    ("content_type", PropertyType::ShortString),
+    ("content_encoding", PropertyType::ShortString),
+    ("headers", PropertyType::Table),
+    ("delivery_mode", PropertyType::Octet),
+    ("priority", PropertyType::Octet),
+    ("correlation_id", PropertyType::ShortString),
+    ("reply_to", PropertyType::ShortString),
+    ("expiration", PropertyType::ShortString),
+    ("message_id", PropertyType::ShortString),
+    ("timestamp", PropertyType::Timestamp),
+    ("type", PropertyType::ShortString),
+    ("user_id", PropertyType::ShortString),
+    ("app_id", PropertyType::ShortString),
+    ("cluster_id", PropertyType::ShortString),
+];
+
+fn property_error(name: &str, expected: &str) -> Error {
+    Error::new(
+        magnus::exception::type_error(),
+        format!("Property {} must be {}", name, expected),
+    )
+}
+
+fn write_property(
+    ruby: &Ruby,
+    encoder: &mut Encoder,
+    name: &str,
+    property_type: PropertyType,
+    value: Value,
+) -> std::result::Result<(), Error> {
+    match property_type {
+        PropertyType::ShortString => {
+            let s: String =
+                TryConvert::try_convert(value).map_err(|_| property_error(name, "a String"))?;
+            encoder
+                .write_short_string(&s)
+                .map_err(|e| Error::new(magnus::exception::arg_error(), e.to_string()))?;
+        }
+        PropertyType::Octet => {
+            let v: u8 = TryConvert::try_convert(value)
+                .map_err(|_| property_error(name, "an Integer in range 0..255"))?;
+            encoder.write_u8(v);
+        }
+        PropertyType::Timestamp => {
+            let timestamp: i64 = if value.is_kind_of(ruby.class_time()) {
+                value.funcall("to_i", ())?
+            } else {
+                TryConvert::try_convert(value)
+                    .map_err(|_| property_error(name, "a Time or an Integer"))?
+            };
+            encoder.write_i64(timestamp);
+        }
+        PropertyType::Table => {
+            let hash = RHash::from_value(value).ok_or_else(|| property_error(name, "a Hash"))?;
+            table::write_table(ruby, hash, encoder).map_err(Error::from)?;
+        }
+    }
+    Ok(())
+}
+
+/// The size of a property as written by `write_property`, checking it the
+/// same way.
+fn property_size(
+    ruby: &Ruby,
+    name: &str,
+    property_type: PropertyType,
+    value: Value,
+) -> std::result::Result<usize, Error> {
+    let size = match property_type {
+        PropertyType::ShortString => {
+            let s: String =
+                TryConvert::try_convert(value).map_err(|_| property_error(name, "a String"))?;
+            if s.len() > 255 {
+                return Err(Error::new(
+                    magnus::exception::arg_error(),
+                    AmqpError::ShortStringTooLong(s.len()).to_string(),
+                ));
+            }
+            1 + s.len()
+        }
+        PropertyType::Octet => {
+            u8::try_convert(value)
+                .map_err(|_| property_error(name, "an Integer in range 0..255"))?;
+            1
+        }
+        PropertyType::Timestamp => {
+            if !value.is_kind_of(ruby.class_time()) {
+                i64::try_convert(value)
+                    .map_err(|_| property_error(name, "a Time or an Integer"))?;
+            }
+            8
+        }
+        PropertyType::Table => {
+            let hash = RHash::from_value(value).ok_or_else(|| property_error(name, "a Hash"))?;
+            table::table_size(ruby, hash, EncodeOptions::default()).map_err(Error::from)?
+        }
+    };
+    Ok(size)
+}
+
+/// Returns the values of `properties` in wire order, None for those not
+/// given or set to nil. Keys may be Symbols or Strings; anything else, an
+/// unknown property or one given twice raises ArgumentError.
+fn property_values(
+    properties: RHash,
+) -> std::result::Result<[Option<Value>; PROPERTIES.len()], Error> {
+    let arg_error = |message: String| Error::new(magnus::exception::arg_error(), message);
+
+    let mut values = [None; PROPERTIES.len()];
+    properties.foreach(|key: Value, value: Value| {
+        let index = table::with_key_bytes(key, |name| {
+            PROPERTIES
+                .iter()
+                .position(|(property, _)| property.as_bytes() == name)
+        })
+        .map_err(|_| arg_error(format!("Invalid property name {}", key.inspect())))?
+        .ok_or_else(|| arg_error(format!("Unknown property {}", key.inspect())))?;
+        if values[index].is_some() {
+            return Err(arg_error(format!(
+                "Property {} given twice",
+                PROPERTIES[index].0
+            )));
+        }
+        values[index] = Some(value);
+        Ok(ForEach::Continue)
+    })?;
+
+    Ok(values.map(|value| value.filter(|value| !value.is_nil())))
+}
+
+/// Writes the property flags and property list of a basic content header.
+/// Properties set to nil are left out.
+pub fn write_basic_properties(
+    ruby: &Ruby,
+    encoder: &mut Encoder,
+    properties: RHash,
+) -> std::result::Result<(), Error> {
+    let values = property_values(properties)?;
+    let flags_offset = encoder.len();
+    encoder.write_u16(0);
+
+    let mut flags: u16 = 0;
+    for (i, (&(name, property_type), value)) in PROPERTIES.iter().zip(values).enumerate() {
+        let Some(value) = value else {
+            continue;
+        };
+        write_property(ruby, encoder, name, property_type, value)?;
+        flags |= 1 << (15 - i);
+    }
+
+    encoder.patch_u16(flags_offset, flags);
+    Ok(())
+}
+
+/// The size of what `write_basic_properties` would write.
+pub fn basic_properties_size(ruby: &Ruby, properties: RHash) -> std::result::Result<usize, Error> {
+    let mut size = 2;
+    for (&(name, property_type), value) in PROPERTIES.iter().zip(property_values(properties)?) {
+        if let Some(value) = value {
+            size += property_size(ruby, name, property_type, value)?;
+        }
+    }
+    Ok(size)
+}
+
+/// Writes a complete content header frame payload for the basic class.
+pub fn write_content_header(
+    ruby: &Ruby,
+    encoder: &mut Encoder,
+    body_size: u64,
+    properties: RHash,
+) -> std::result::Result<(), Error> {
+    encoder.write_u16(BASIC_CLASS_ID);
+    encoder.write_u16(0);
+    encoder.write_u64(body_size);
+    write_basic_properties(ruby, encoder, properties)
+}
+
+fn rb_encode_properties(
+    ruby: &Ruby,
+    body_size: u64,
+    properties: RHash,
+) -> std::result::Result<RString, Error> {
+    let mut encoder = Encoder::with_capacity(64);
+    write_content_header(ruby, &mut encoder, body_size, properties)?;
+    Ok(RString::from_slice(encoder.as_slice()))
+}
+
+/// `encoded_properties_size(properties)`, the size of the content header
+/// payload `encode_properties` would return.
+fn rb_encoded_properties_size(ruby: &Ruby, properties: RHash) -> std::result::Result<usize, Error> {
+    Ok(12 + basic_properties_size(ruby, properties)?)
+}
+
+pub fn init(_ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let basic: RClass = protocol.const_get("Basic")?;
+    basic.define_singleton_method("encode_properties", function!(rb_encode_properties, 2))?;
+    basic.define_singleton_method(
+        "encoded_properties_size",
+        function!(rb_encoded_properties_size, 1),
+    )?;
+
+    Ok(())
+}
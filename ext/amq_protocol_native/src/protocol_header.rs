@@ -0,0 +1,100 @@
+//! AMQP protocol header parsing and version negotiation
+
+use magnus::{function, prelude::*, Error, Module, RArray, RString, Ruby};
+
+use crate::error::{AmqpError, Result};
+
+pub const PROTOCOL_NAME: &[u8; 4] = b"AMQP";
+pub const PROTOCOL_HEADER: [u8; 8] = [b'A', b'M', b'Q', b'P', 0, 0, 9, 1];
+pub const PROTOCOL_HEADER_SIZE: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolHeader {
+    pub protocol_id: u8,
+    pub major: u8,
+    pub minor: u8,
+    pub revision: u8,
+}
+
+impl ProtocolHeader {
+    pub fn is_supported(&self) -> bool {
+        [self.protocol_id, self.major, self.minor, self.revision] == PROTOCOL_HEADER[4..]
+    }
+}
+
+/// Returns true if `data` starts like a protocol header rather than a frame.
+pub fn is_protocol_header(data: &[u8]) -> bool {
+    data.starts_with(PROTOCOL_NAME)
+}
+
+/// Builds the error a server's protocol header should be reported with.
+/// Frame header reads only see 7 bytes, so the revision may be missing.
+pub fn version_mismatch(data: &[u8]) -> AmqpError {
+    let byte = |i: usize| data.get(i).copied();
+    AmqpError::ProtocolVersionMismatch {
+        protocol_id: byte(4).unwrap_or(0),
+        major: byte(5).unwrap_or(0),
+        minor: byte(6).unwrap_or(0),
+        revision: byte(7),
+    }
+}
+
+pub fn decode_protocol_header(data: &[u8]) -> Result<ProtocolHeader> {
+    if data.len() < PROTOCOL_HEADER_SIZE {
+        return Err(AmqpError::BufferTooShort {
+            needed: PROTOCOL_HEADER_SIZE,
+            available: data.len(),
+        });
+    }
+    if !is_protocol_header(data) {
+        return Err(AmqpError::DecodingError(
+            "Not an AMQP protocol header".into(),
+        ));
+    }
+
+    Ok(ProtocolHeader {
+        protocol_id: data[4],
+        major: data[5],
+        minor: data[6],
+        revision: data[7],
+    })
+}
+
+/// Fails with a version mismatch unless `data` is the AMQP 0-9-1 header.
+pub fn verify_protocol_header(data: &[u8]) -> Result<()> {
+    let header = decode_protocol_header(data)?;
+    if header.is_supported() {
+        Ok(())
+    } else {
+        Err(version_mismatch(data))
+    }
+}
+
+fn rb_decode(ruby: &Ruby, data: RString) -> std::result::Result<RArray, Error> {
+    let bytes = unsafe { data.as_slice() };
+    let header = decode_protocol_header(bytes).map_err(Error::from)?;
+
+    let array = ruby.ary_new();
+    array.push(header.protocol_id)?;
+    array.push(header.major)?;
+    array.push(header.minor)?;
+    array.push(header.revision)?;
+
+    Ok(array)
+}
+
+fn rb_verify(data: RString) -> std::result::Result<bool, Error> {
+    let bytes = unsafe { data.as_slice() };
+    verify_protocol_header(bytes).map_err(Error::from)?;
+    Ok(true)
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let header = protocol.define_class("ProtocolHeader", ruby.class_object())?;
+
+    header.const_set("SIZE", PROTOCOL_HEADER_SIZE)?;
+    header.define_singleton_method("decode", function!(rb_decode, 1))?;
+    header.define_singleton_method("verify", function!(rb_verify, 1))?;
+
+    Ok(())
+}
@@ -0,0 +1,293 @@
+//! Batched encoding of basic.publish commands into one contiguous buffer
+
+use std::cell::{Ref, RefCell, RefMut};
+
+use magnus::{
+    function, method, prelude::*, scan_args, typed_data::Obj, Error, Module, RArray, RHash,
+    RString, Ruby, Value,
+};
+
+use crate::frame::{
+    check_frame_max, max_body_payload, write_body_frames, write_frame_with, FrameType,
+};
+use crate::methods::write_basic_publish;
+use crate::nogvl;
+use crate::properties::write_content_header;
+use crate::types::Encoder;
+
+/// One message of a batch, with the flags of its basic.publish method.
+struct Message {
+    exchange: RString,
+    routing_key: RString,
+    properties: RHash,
+    body: RString,
+    mandatory: bool,
+    immediate: bool,
+}
+
+/// Frame limits for the commands of one channel.
+#[derive(Debug, Clone, Copy)]
+pub struct Framing {
+    pub channel: u16,
+    /// Largest frame payload allowed by frame_max, None when frame_max is 0
+    pub max_payload: Option<usize>,
+}
+
+impl Framing {
+    pub fn new(channel: u16, frame_max: u32) -> std::result::Result<Self, Error> {
+        check_frame_max(frame_max)?;
+        Ok(Self {
+            channel,
+            max_payload: max_body_payload(frame_max),
+        })
+    }
+
+    /// Writes the method, content header and body frames of one message.
+    /// The encoder is left as it was if anything fails.
+    fn write_publish(
+        &self,
+        ruby: &Ruby,
+        encoder: &mut Encoder,
+        message: Message,
+    ) -> std::result::Result<(), Error> {
+        let start = encoder.len();
+        let result = self.write_frames(ruby, encoder, message);
+        if result.is_err() {
+            encoder.truncate(start);
+        }
+        result
+    }
+
+    /// Writes a content header frame, which unlike the body cannot be split
+    /// across frames.
+    pub fn write_header(
+        &self,
+        ruby: &Ruby,
+        encoder: &mut Encoder,
+        body_size: u64,
+        properties: RHash,
+    ) -> std::result::Result<(), Error> {
+        let header_size =
+            write_frame_with(encoder, FrameType::Headers as u8, self.channel, |encoder| {
+                write_content_header(ruby, encoder, body_size, properties)
+            })?;
+        if let Some(max_payload) = self.max_payload {
+            if header_size > max_payload {
+                return Err(Error::new(
+                    magnus::exception::arg_error(),
+                    format!(
+                        "Content header of {} bytes does not fit into frame_max",
+                        header_size
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn write_frames(
+        &self,
+        ruby: &Ruby,
+        encoder: &mut Encoder,
+        message: Message,
+    ) -> std::result::Result<(), Error> {
+        write_frame_with(encoder, FrameType::Method as u8, self.channel, |encoder| {
+            write_basic_publish(
+                encoder,
+                message.exchange,
+                message.routing_key,
+                message.mandatory,
+                message.immediate,
+            )
+        })?;
+
+        // Encoding the properties can run Ruby code, which must not be able
+        // to change the body after its size went into the content header
+        let body = RString::new_frozen(message.body);
+        self.write_header(ruby, encoder, body.len() as u64, message.properties)?;
+
+        nogvl::with_str_bytes(body, |bytes| {
+            write_body_frames(encoder, self.channel, bytes, self.max_payload)
+        });
+
+        Ok(())
+    }
+}
+
+struct Batch {
+    encoder: Encoder,
+    count: usize,
+}
+
+#[magnus::wrap(class = "AMQ::Protocol::PublishBatch", free_immediately, size)]
+struct PublishBatch {
+    framing: Framing,
+    frame_max: u32,
+    inner: RefCell<Batch>,
+}
+
+impl PublishBatch {
+    fn new(channel: u16, frame_max: u32) -> std::result::Result<Self, Error> {
+        Ok(Self {
+            framing: Framing::new(channel, frame_max)?,
+            frame_max,
+            inner: RefCell::new(Batch {
+                encoder: Encoder::new(),
+                count: 0,
+            }),
+        })
+    }
+
+    fn channel(&self) -> u16 {
+        self.framing.channel
+    }
+
+    fn frame_max(&self) -> u32 {
+        self.frame_max
+    }
+
+    /// Borrows the batch, raising instead of panicking while it is being
+    /// written to, which can happen when Ruby code called by `add` (such as
+    /// a `to_amqp_field` method) or another thread uses the batch.
+    fn batch(&self) -> std::result::Result<Ref<'_, Batch>, Error> {
+        self.inner.try_borrow().map_err(|_| busy())
+    }
+
+    fn batch_mut(&self) -> std::result::Result<RefMut<'_, Batch>, Error> {
+        self.inner.try_borrow_mut().map_err(|_| busy())
+    }
+
+    /// Adds a message, taking `exchange, routing_key, properties, body` and
+    /// optional `mandatory:` and `immediate:` keywords.
+    fn add(
+        ruby: &Ruby,
+        rb_self: Obj<Self>,
+        args: &[Value],
+    ) -> std::result::Result<Obj<Self>, Error> {
+        let args = scan_args::scan_args::<(RString, RString, RHash, RString), (), (), (), RHash, ()>(
+            args,
+        )?;
+        let kwargs = scan_args::get_kwargs::<_, (), (Option<bool>, Option<bool>), ()>(
+            args.keywords,
+            &[],
+            &["mandatory", "immediate"],
+        )?;
+        let (exchange, routing_key, properties, body) = args.required;
+        let (mandatory, immediate) = kwargs.optional;
+        let message = Message {
+            exchange,
+            routing_key,
+            properties,
+            body,
+            mandatory: mandatory.unwrap_or(false),
+            immediate: immediate.unwrap_or(false),
+        };
+
+        {
+            let mut batch = rb_self.batch_mut()?;
+            rb_self
+                .framing
+                .write_publish(ruby, &mut batch.encoder, message)?;
+            batch.count += 1;
+        }
+        Ok(rb_self)
+    }
+
+    fn count(&self) -> std::result::Result<usize, Error> {
+        Ok(self.batch()?.count)
+    }
+
+    fn bytesize(&self) -> std::result::Result<usize, Error> {
+        Ok(self.batch()?.encoder.len())
+    }
+
+    fn is_empty(&self) -> std::result::Result<bool, Error> {
+        Ok(self.batch()?.count == 0)
+    }
+
+    fn to_s(&self) -> std::result::Result<RString, Error> {
+        Ok(RString::from_slice(self.batch()?.encoder.as_slice()))
+    }
+
+    fn take(&self) -> std::result::Result<RString, Error> {
+        let mut batch = self.batch_mut()?;
+        let string = RString::from_slice(batch.encoder.as_slice());
+        batch.encoder.clear();
+        batch.count = 0;
+        Ok(string)
+    }
+
+    fn clear(&self) -> std::result::Result<(), Error> {
+        let mut batch = self.batch_mut()?;
+        batch.encoder.clear();
+        batch.count = 0;
+        Ok(())
+    }
+}
+
+fn busy() -> Error {
+    Error::new(
+        magnus::exception::runtime_error(),
+        "PublishBatch is already being written to",
+    )
+}
+
+/// Converts one `[exchange, routing_key, properties, body]` entry of the
+/// Array given to `encode`, which may add `mandatory` and `immediate` flags.
+fn message_entry(entry: RArray) -> std::result::Result<Message, Error> {
+    if !(4..=6).contains(&entry.len()) {
+        return Err(Error::new(
+            magnus::exception::arg_error(),
+            format!(
+                "Expected [exchange, routing_key, properties, body, mandatory, immediate] with \
+                 the flags optional, got {} elements",
+                entry.len()
+            ),
+        ));
+    }
+    Ok(Message {
+        exchange: entry.entry(0)?,
+        routing_key: entry.entry(1)?,
+        properties: entry.entry(2)?,
+        body: entry.entry(3)?,
+        mandatory: entry.entry::<Option<bool>>(4)?.unwrap_or(false),
+        immediate: entry.entry::<Option<bool>>(5)?.unwrap_or(false),
+    })
+}
+
+/// Encodes an Array of `[exchange, routing_key, properties, body]` messages.
+fn rb_encode(
+    ruby: &Ruby,
+    channel: u16,
+    frame_max: u32,
+    messages: RArray,
+) -> std::result::Result<RString, Error> {
+    let framing = Framing::new(channel, frame_max)?;
+    let mut encoder = Encoder::with_capacity(256 * messages.len());
+
+    for i in 0..messages.len() {
+        let message = message_entry(messages.entry(i as isize)?)?;
+        framing.write_publish(ruby, &mut encoder, message)?;
+    }
+
+    Ok(RString::from_slice(encoder.as_slice()))
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let batch = protocol.define_class("PublishBatch", ruby.class_object())?;
+
+    batch.define_singleton_method("new", function!(PublishBatch::new, 2))?;
+    batch.define_singleton_method("encode", function!(rb_encode, 3))?;
+    batch.define_method("channel", method!(PublishBatch::channel, 0))?;
+    batch.define_method("frame_max", method!(PublishBatch::frame_max, 0))?;
+    batch.define_method("add", method!(PublishBatch::add, -1))?;
+    batch.define_method("count", method!(PublishBatch::count, 0))?;
+    batch.define_method("size", method!(PublishBatch::count, 0))?;
+    batch.define_method("bytesize", method!(PublishBatch::bytesize, 0))?;
+    batch.define_method("empty?", method!(PublishBatch::is_empty, 0))?;
+    batch.define_method("to_s", method!(PublishBatch::to_s, 0))?;
+    batch.define_method("take", method!(PublishBatch::take, 0))?;
+    batch.define_method("clear", method!(PublishBatch::clear, 0))?;
+
+    Ok(())
+}
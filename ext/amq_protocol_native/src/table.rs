@@ -1,10 +1,13 @@
 //! AMQP Field Table encoding and decoding
 
 use magnus::{
-    function, prelude::*, Error, Module, RArray, RHash, RString, Ruby, Symbol, TryConvert, Value,
+    encoding::Coderange, function, prelude::*, rb_sys::FromRawValue, scan_args, Error, Integer,
+    Module, RArray, RClass, RHash, RModule, RString, Ruby, Symbol, TryConvert, Value,
 };
 
+use crate::buffer;
 use crate::error::{AmqpError, Result};
+use crate::frozen_str;
 use crate::types::{Decoder, Encoder};
 
 mod type_tags {
@@ -18,68 +21,344 @@ mod type_tags {
     pub const DOUBLE: u8 = b'd';
     pub const FLOAT: u8 = b'f';
     pub const LONG: u8 = b'l';
+    /// 'l' is signed in RabbitMQ's dialect, and unsigned in the 0-9-1 spec,
+    /// which uses 'L' for signed values
+    pub const SPEC_LONG: u8 = b'L';
     pub const SHORT: u8 = b's';
+    /// 's' is a short string in the 0-9-1 spec, which uses 'U' for signed
+    /// 16-bit integers
+    pub const SPEC_SHORT: u8 = b'U';
     pub const BOOLEAN: u8 = b't';
     pub const BYTE_ARRAY: u8 = b'x';
     pub const VOID: u8 = b'V';
 }
 
-pub fn encode_table(ruby: &Ruby, hash: RHash) -> Result<Vec<u8>> {
-    let mut encoder = Encoder::new();
-    encode_table_inner(ruby, hash, &mut encoder)?;
-    Ok(encoder.into_bytes().to_vec())
+pub fn write_table(ruby: &Ruby, hash: RHash, encoder: &mut Encoder) -> Result<()> {
+    write_table_with(ruby, hash, EncodeOptions::default(), encoder)
 }
 
-fn encode_table_inner(ruby: &Ruby, hash: RHash, encoder: &mut Encoder) -> Result<()> {
-    let mut content_encoder = Encoder::new();
+pub fn write_table_with(
+    ruby: &Ruby,
+    hash: RHash,
+    options: EncodeOptions,
+    encoder: &mut Encoder,
+) -> Result<()> {
+    encode_table_inner(ruby, hash, options, encoder)
+}
 
-    hash.foreach(|key: Value, value: Value| {
-        let key_str: String = if key.is_kind_of(ruby.class_symbol()) {
-            let sym: Symbol = TryConvert::try_convert(key).map_err(|_| {
-                magnus::Error::new(
-                    magnus::exception::type_error(),
-                    "Expected symbol or string key",
-                )
-            })?;
-            sym.name()
-                .map_err(|_| {
-                    magnus::Error::new(magnus::exception::encoding_error(), "Invalid symbol name")
-                })?
-                .to_string()
-        } else {
-            let s: String = TryConvert::try_convert(key).map_err(|_| {
-                magnus::Error::new(
-                    magnus::exception::type_error(),
-                    "Expected symbol or string key",
-                )
-            })?;
-            s
-        };
+/// Which 64-bit integer types the peer uses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Dialect {
+    /// RabbitMQ's: 'l' is a signed 64-bit integer and there is no unsigned
+    /// one, 'L' being rejected, and 's' is a signed 16-bit integer
+    #[default]
+    RabbitMq,
+    /// The AMQP 0-9-1 spec's: 'l' is unsigned, 'L' signed, and 'U' is the
+    /// signed 16-bit integer
+    Spec,
+}
 
-        if key_str.len() > 255 {
-            return Err(magnus::Error::new(
-                magnus::exception::arg_error(),
-                format!("Table key too long: {} (max 255)", key_str.len()),
-            ));
+/// How Integers that no 64-bit type of the dialect holds are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BigIntegers {
+    /// Raise RangeError
+    #[default]
+    Raise,
+    /// As decimal strings
+    String,
+}
+
+/// Options for how Ruby values are encoded.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EncodeOptions {
+    pub dialect: Dialect,
+    pub big_integers: BigIntegers,
+    /// Encode Integers as the narrowest of 'b', 's', 'I' and 'l' that holds
+    /// them, rather than always as 'l'
+    pub compact_integers: bool,
+    /// Write the fields of every table in bytewise key order
+    pub canonical: bool,
+}
+
+impl EncodeOptions {
+    /// Parses the keyword arguments accepted by `Table.encode` and
+    /// `Table.encode_into`.
+    pub fn from_kwargs(kwargs: RHash) -> std::result::Result<Self, Error> {
+        type Options = (Option<Symbol>, Option<Symbol>, Option<bool>, Option<bool>);
+        let kwargs = scan_args::get_kwargs::<_, (), Options, ()>(
+            kwargs,
+            &[],
+            &["dialect", "big_integers", "compact_integers", "canonical"],
+        )?;
+        let (dialect, big_integers, compact_integers, canonical) = kwargs.optional;
+
+        Ok(Self {
+            dialect: parse_dialect(dialect)?,
+            big_integers: parse_choice(
+                "big_integers",
+                big_integers,
+                &[
+                    ("raise", BigIntegers::Raise),
+                    ("string", BigIntegers::String),
+                ],
+            )?
+            .unwrap_or_default(),
+            compact_integers: compact_integers.unwrap_or(false),
+            canonical: canonical.unwrap_or(false),
+        })
+    }
+}
+
+/// A step on the way from a table to one of the values nested in it.
+enum PathSegment<'a> {
+    Key(&'a [u8]),
+    Index(usize),
+}
+
+/// Prepends `segment` to a key path such as `x-death[0].queue`.
+fn prepend_segment(key_path: String, segment: Option<PathSegment>) -> String {
+    let segment = match segment {
+        None => return key_path,
+        Some(PathSegment::Key(key)) => String::from_utf8_lossy(key).into_owned(),
+        Some(PathSegment::Index(index)) => format!("[{}]", index),
+    };
+    if key_path.is_empty() || key_path.starts_with('[') {
+        segment + &key_path
+    } else {
+        segment + "." + &key_path
+    }
+}
+
+/// Wraps an error in `AmqpError::DecodeFailed` at `position`, or if it
+/// already is one, keeps its offset and prepends `segment` to its key path.
+fn locate(error: AmqpError, position: usize, segment: Option<PathSegment>) -> AmqpError {
+    let (error, offset, key_path) = match error {
+        AmqpError::DecodeFailed {
+            error,
+            offset,
+            key_path,
+        } => (error, offset, key_path),
+        error => (Box::new(error), position, String::new()),
+    };
+    AmqpError::DecodeFailed {
+        error,
+        offset,
+        key_path: prepend_segment(key_path, segment),
+    }
+}
+
+/// Wraps an error in `AmqpError::EncodeFailed` for `value`, or if it already
+/// is one, keeps its value and prepends `segment` to its key path.
+fn encode_failed(error: AmqpError, value: Value, segment: Option<PathSegment>) -> AmqpError {
+    let (error, value, key_path) = match error {
+        AmqpError::EncodeFailed {
+            error,
+            value,
+            key_path,
+        } => (error, value, key_path),
+        error => (Box::new(error), value, String::new()),
+    };
+    AmqpError::EncodeFailed {
+        error,
+        value,
+        key_path: prepend_segment(key_path, segment),
+    }
+}
+
+fn encode_table_inner(
+    ruby: &Ruby,
+    hash: RHash,
+    options: EncodeOptions,
+    encoder: &mut Encoder,
+) -> Result<()> {
+    let length_offset = encoder.reserve_length();
+
+    if options.canonical {
+        encode_sorted_fields(ruby, hash, options, encoder)?;
+    } else {
+        // Errors are kept out of the magnus::Error foreach expects, so they
+        // reach the caller with their key path intact
+        let mut failure = None;
+        hash.foreach(|key: Value, value: Value| {
+            let result = table_key(ruby, key)
+                .map_err(|e| encode_failed(e, key, None))
+                .and_then(|key| encode_field(ruby, &key, value, options, encoder));
+            match result {
+                Ok(()) => Ok(magnus::r_hash::ForEach::Continue),
+                Err(e) => {
+                    failure = Some(e);
+                    Ok(magnus::r_hash::ForEach::Stop)
+                }
+            }
+        })
+        .map_err(|e| AmqpError::EncodingError(e.to_string()))?;
+        if let Some(e) = failure {
+            return Err(e);
+        }
+    }
+
+    encoder.patch_length(length_offset);
+
+    Ok(())
+}
+
+fn encode_field(
+    ruby: &Ruby,
+    key: &str,
+    value: Value,
+    options: EncodeOptions,
+    encoder: &mut Encoder,
+) -> Result<()> {
+    encoder.write_u8(key.len() as u8);
+    encoder.write_bytes(key.as_bytes());
+    encode_field_value(ruby, value, options, encoder)
+        .map_err(|e| encode_failed(e, value, Some(PathSegment::Key(key.as_bytes()))))
+}
+
+/// Writes the fields of `hash` in bytewise key order, so tables with the
+/// same fields always encode to the same bytes.
+fn encode_sorted_fields(
+    ruby: &Ruby,
+    hash: RHash,
+    options: EncodeOptions,
+    encoder: &mut Encoder,
+) -> Result<()> {
+    let (entries, keys) = sorted_keys(ruby, hash)?;
+    for (key, i) in &keys {
+        encode_field(ruby, key, entry(entries, *i, 1)?, options, encoder)?;
+    }
+    Ok(())
+}
+
+/// Returns the `to_a` entries of `hash`, which keep the keys and values
+/// reachable, and its keys in bytewise order with their entry indices.
+/// Raises for keys that are the same once converted to Strings.
+fn sorted_keys(ruby: &Ruby, hash: RHash) -> Result<(RArray, Vec<(String, usize)>)> {
+    let entries: RArray = hash.funcall("to_a", ()).map_err(AmqpError::Raised)?;
+
+    let mut keys = Vec::with_capacity(entries.len());
+    for i in 0..entries.len() {
+        let key = entry(entries, i, 0)?;
+        keys.push((
+            table_key(ruby, key).map_err(|e| encode_failed(e, key, None))?,
+            i,
+        ));
+    }
+    // Strings compare bytewise
+    keys.sort_unstable();
+
+    if let Some(pair) = keys.windows(2).find(|pair| pair[0].0 == pair[1].0) {
+        let (key, i) = &pair[1];
+        return Err(duplicate_key(key, entry(entries, *i, 1)?));
+    }
+    Ok((entries, keys))
+}
+
+fn duplicate_key(key: &str, value: Value) -> AmqpError {
+    let error = AmqpError::EncodingError(format!(
+        "Duplicate key {} once Symbol keys are converted to Strings",
+        key
+    ));
+    encode_failed(error, value, Some(PathSegment::Key(key.as_bytes())))
+}
+
+/// Tracks which kinds of keys a table has, to tell whether converting them
+/// to Strings can make two of them equal.
+#[derive(Default)]
+struct KeyKinds {
+    symbols: bool,
+    strings: bool,
+    /// Keys the conversion may change, such as Strings in other encodings
+    other: bool,
+}
+
+impl KeyKinds {
+    fn add(&mut self, ruby: &Ruby, key: Value) {
+        let utf8 =
+            |encoding| encoding == ruby.utf8_encindex() || encoding == ruby.usascii_encindex();
+        if let Some(symbol) = Symbol::from_value(key) {
+            self.symbols = true;
+            self.other |= !utf8(symbol.enc_get());
+        } else if let Some(string) = RString::from_value(key) {
+            self.strings = true;
+            self.other |=
+                !utf8(string.enc_get()) && string.enc_coderange_scan() != Coderange::SevenBit;
+        } else {
+            self.other = true;
         }
-        content_encoder.write_u8(key_str.len() as u8);
-        content_encoder.write_bytes(key_str.as_bytes());
+    }
 
-        encode_field_value(ruby, value, &mut content_encoder)
-            .map_err(|e| magnus::Error::new(magnus::exception::runtime_error(), e.to_string()))?;
+    /// Whether two keys may convert to the same String, which distinct
+    /// Symbols, or distinct Strings, that the conversion keeps as they are
+    /// cannot.
+    fn may_collide(&self) -> bool {
+        self.other || (self.symbols && self.strings)
+    }
+}
 
-        Ok(magnus::r_hash::ForEach::Continue)
+/// Raises for keys of `hash` that are the same once converted to Strings,
+/// looking each one up in a Hash of the converted keys seen before it.
+fn check_duplicate_keys(ruby: &Ruby, hash: RHash) -> Result<()> {
+    let seen = ruby.hash_new_capa(hash.len());
+    let mut failure = None;
+    hash.foreach(|key: Value, value: Value| {
+        let result = table_key(ruby, key)
+            .map_err(|e| encode_failed(e, key, None))
+            .and_then(|key| {
+                let converted = ruby.str_new(&key);
+                if seen.get(converted).is_some() {
+                    return Err(duplicate_key(&key, value));
+                }
+                seen.aset(converted, true)
+                    .map_err(|e| AmqpError::EncodingError(e.to_string()))
+            });
+        match result {
+            Ok(()) => Ok(magnus::r_hash::ForEach::Continue),
+            Err(e) => {
+                failure = Some(e);
+                Ok(magnus::r_hash::ForEach::Stop)
+            }
+        }
     })
     .map_err(|e| AmqpError::EncodingError(e.to_string()))?;
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
 
-    let content = content_encoder.into_bytes();
-    encoder.write_u32(content.len() as u32);
-    encoder.write_bytes(&content);
+/// Element `j` of the `i`th `[key, value]` pair in `entries`.
+fn entry(entries: RArray, i: usize, j: isize) -> Result<Value> {
+    entries
+        .entry::<RArray>(i as isize)
+        .and_then(|pair| pair.entry(j))
+        .map_err(|_| AmqpError::EncodingError("Failed to get hash entry".into()))
+}
 
-    Ok(())
+fn table_key(ruby: &Ruby, key: Value) -> Result<String> {
+    let key = if key.is_kind_of(ruby.class_symbol()) {
+        Symbol::try_convert(key)
+            .and_then(|sym| sym.name().map(|name| name.into_owned()))
+            .map_err(|_| AmqpError::EncodingError("Invalid symbol name".into()))?
+    } else {
+        String::try_convert(key)
+            .map_err(|_| AmqpError::EncodingError("Expected symbol or string key".into()))?
+    };
+    if key.len() > 255 {
+        return Err(AmqpError::EncodingError(format!(
+            "Table key too long: {} (max 255)",
+            key.len()
+        )));
+    }
+    Ok(key)
 }
 
-fn encode_field_value(ruby: &Ruby, value: Value, encoder: &mut Encoder) -> Result<()> {
+fn encode_field_value(
+    ruby: &Ruby,
+    value: Value,
+    options: EncodeOptions,
+    encoder: &mut Encoder,
+) -> Result<()> {
     if value.is_nil() {
         encoder.write_u8(type_tags::VOID);
         return Ok(());
@@ -98,11 +377,8 @@ fn encode_field_value(ruby: &Ruby, value: Value, encoder: &mut Encoder) -> Resul
             .map_err(|_| AmqpError::EncodingError("Invalid symbol name".into()))?;
         encoder.write_u8(type_tags::STRING);
         encoder.write_long_string(s.as_bytes());
-    } else if value.is_kind_of(ruby.class_integer()) {
-        let i: i64 = TryConvert::try_convert(value)
-            .map_err(|_| AmqpError::EncodingError("Failed to convert integer".into()))?;
-        encoder.write_u8(type_tags::LONG);
-        encoder.write_i64(i);
+    } else if let Some(integer) = Integer::from_value(value) {
+        encode_integer(integer, options, encoder)?;
     } else if value.is_kind_of(ruby.class_float()) {
         let f: f64 = TryConvert::try_convert(value)
             .map_err(|_| AmqpError::EncodingError("Failed to convert float".into()))?;
@@ -119,55 +395,383 @@ fn encode_field_value(ruby: &Ruby, value: Value, encoder: &mut Encoder) -> Resul
         let hash: RHash = TryConvert::try_convert(value)
             .map_err(|_| AmqpError::EncodingError("Failed to convert hash".into()))?;
         encoder.write_u8(type_tags::HASH);
-        encode_table_inner(ruby, hash, encoder)?;
+        encode_table_inner(ruby, hash, options, encoder)?;
     } else if value.is_kind_of(ruby.class_array()) {
         let array: RArray = TryConvert::try_convert(value)
             .map_err(|_| AmqpError::EncodingError("Failed to convert array".into()))?;
         encoder.write_u8(type_tags::ARRAY);
-        encode_array(ruby, array, encoder)?;
+        encode_array(ruby, array, options, encoder)?;
     } else if value.is_kind_of(ruby.class_time()) {
         let timestamp: i64 = value
             .funcall("to_i", ())
             .map_err(|_| AmqpError::EncodingError("Failed to get timestamp".into()))?;
         encoder.write_u8(type_tags::TIME);
         encoder.write_i64(timestamp);
-    } else {
-        let class_name: String = value
-            .class()
-            .funcall("name", ())
-            .unwrap_or_else(|_| "unknown".to_string());
-        return Err(AmqpError::InvalidTableValue(
-            "unknown".to_string(),
-            class_name,
-        ));
+    } else if !encode_decoded_object(ruby, value, encoder)? {
+        let Some(field) = converted_field(value)? else {
+            return Err(AmqpError::InvalidTableValue(class_name(value)));
+        };
+        encode_field_value(ruby, field, options, encoder)?;
+    }
+
+    Ok(())
+}
+
+fn class_name(value: Value) -> String {
+    value
+        .class()
+        .funcall("name", ())
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+/// Returns what `to_amqp_field` returns for objects that define it, to be
+/// encoded in their place, or None for other objects.
+fn converted_field(value: Value) -> Result<Option<Value>> {
+    if !value
+        .respond_to("to_amqp_field", false)
+        .map_err(AmqpError::Raised)?
+    {
+        return Ok(None);
+    }
+    let field: Value = value
+        .funcall("to_amqp_field", ())
+        .map_err(AmqpError::Raised)?;
+    // Converting again could go on forever
+    if field
+        .respond_to("to_amqp_field", false)
+        .map_err(AmqpError::Raised)?
+    {
+        return Err(AmqpError::EncodingError(format!(
+            "{}#to_amqp_field returned a {}, which has to_amqp_field itself",
+            class_name(value),
+            class_name(field)
+        )));
     }
+    Ok(Some(field))
+}
+
+/// How an Integer is written, as decided by `integer_field`.
+enum IntegerField {
+    /// 'b', 's' or 'I', for `compact_integers`
+    Compact(i32),
+    Signed(i64),
+    Unsigned(u64),
+    String(String),
+}
 
+/// Picks the field type for an Integer: the dialect's signed 64-bit type (or
+/// a narrower one), in the spec dialect 'l' for those only a u64 holds, and
+/// otherwise a decimal string or RangeError depending on `big_integers`.
+fn integer_field(integer: Integer, options: EncodeOptions) -> Result<IntegerField> {
+    if let Ok(i) = integer.to_i64() {
+        return Ok(match i32::try_from(i) {
+            Ok(i) if options.compact_integers => IntegerField::Compact(i),
+            _ => IntegerField::Signed(i),
+        });
+    }
+    if options.dialect == Dialect::Spec {
+        if let Ok(u) = integer.to_u64() {
+            return Ok(IntegerField::Unsigned(u));
+        }
+    }
+    if options.big_integers == BigIntegers::String {
+        return Ok(IntegerField::String(integer.to_string()));
+    }
+    let range = match options.dialect {
+        Dialect::RabbitMq => "signed 64-bit",
+        Dialect::Spec => "signed or unsigned 64-bit",
+    };
+    Err(AmqpError::IntegerOutOfRange {
+        value: integer.to_string(),
+        range,
+    })
+}
+
+fn encode_integer(integer: Integer, options: EncodeOptions, encoder: &mut Encoder) -> Result<()> {
+    match integer_field(integer, options)? {
+        IntegerField::Compact(i) => write_compact_integer(i, options.dialect, encoder),
+        IntegerField::Signed(i) => {
+            encoder.write_u8(match options.dialect {
+                Dialect::RabbitMq => type_tags::LONG,
+                Dialect::Spec => type_tags::SPEC_LONG,
+            });
+            encoder.write_i64(i);
+        }
+        IntegerField::Unsigned(u) => {
+            encoder.write_u8(type_tags::LONG);
+            encoder.write_u64(u);
+        }
+        IntegerField::String(s) => {
+            encoder.write_u8(type_tags::STRING);
+            encoder.write_long_string(s.as_bytes());
+        }
+    }
     Ok(())
 }
 
-fn encode_array(ruby: &Ruby, array: RArray, encoder: &mut Encoder) -> Result<()> {
-    let mut content_encoder = Encoder::new();
+fn write_compact_integer(i: i32, dialect: Dialect, encoder: &mut Encoder) {
+    if let Ok(i) = i8::try_from(i) {
+        encoder.write_u8(type_tags::BYTE);
+        encoder.write_u8(i as u8);
+    } else if let Ok(i) = i16::try_from(i) {
+        encoder.write_u8(match dialect {
+            Dialect::RabbitMq => type_tags::SHORT,
+            Dialect::Spec => type_tags::SPEC_SHORT,
+        });
+        encoder.write_u16(i as u16);
+    } else {
+        encoder.write_u8(type_tags::INTEGER);
+        encoder.write_u32(i as u32);
+    }
+}
+
+/// Encodes the `Table::ByteArray` and `Table::RawValue` objects decoding
+/// can produce back to what was decoded. Returns false for other objects.
+fn encode_decoded_object(ruby: &Ruby, value: Value, encoder: &mut Encoder) -> Result<bool> {
+    let to_encoding_error = |e: Error| AmqpError::EncodingError(e.to_string());
+
+    if value.is_kind_of(table_class(ruby, "ByteArray").map_err(to_encoding_error)?) {
+        let bytes: RString = value.funcall("bytes", ()).map_err(to_encoding_error)?;
+        encoder.write_u8(type_tags::BYTE_ARRAY);
+        encoder.write_long_string(unsafe { bytes.as_slice() });
+        return Ok(true);
+    }
+    if value.is_kind_of(table_class(ruby, "RawValue").map_err(to_encoding_error)?) {
+        let type_tag: RString = value.funcall("type", ()).map_err(to_encoding_error)?;
+        let bytes: RString = value.funcall("bytes", ()).map_err(to_encoding_error)?;
+        let &[type_tag] = (unsafe { type_tag.as_slice() }) else {
+            return Err(AmqpError::EncodingError(
+                "RawValue type must be a single character".into(),
+            ));
+        };
+        encoder.write_u8(type_tag);
+        encoder.write_bytes(unsafe { bytes.as_slice() });
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+fn encode_array(
+    ruby: &Ruby,
+    array: RArray,
+    options: EncodeOptions,
+    encoder: &mut Encoder,
+) -> Result<()> {
+    let length_offset = encoder.reserve_length();
 
     for i in 0..array.len() {
         let value: Value = array
             .entry(i as isize)
             .map_err(|_| AmqpError::EncodingError("Failed to get array entry".into()))?;
-        encode_field_value(ruby, value, &mut content_encoder)?;
+        encode_field_value(ruby, value, options, encoder)
+            .map_err(|e| encode_failed(e, value, Some(PathSegment::Index(i))))?;
     }
 
-    let content = content_encoder.into_bytes();
-    encoder.write_u32(content.len() as u32);
-    encoder.write_bytes(&content);
+    encoder.patch_length(length_offset);
 
     Ok(())
 }
 
-pub fn decode_table(ruby: &Ruby, data: &[u8]) -> Result<RHash> {
+/// How TIME ('T') values are decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeAs {
+    #[default]
+    Time,
+    Integer,
+}
+
+/// How DECIMAL ('D') values are decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DecimalAs {
+    #[default]
+    Float,
+    BigDecimal,
+}
+
+/// How BYTE_ARRAY ('x') values are decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ByteArrayAs {
+    #[default]
+    String,
+    /// A `Table::ByteArray`, which encodes back to 'x'
+    Object,
+}
+
+/// What to do with field types the decoder does not support.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnknownType {
+    #[default]
+    Raise,
+    Skip,
+    /// Return a `Table::RawValue` with the type and undecoded bytes
+    Raw,
+}
+
+/// Options for the Ruby objects decoding produces.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DecodeOptions {
+    /// Return keys as Symbols instead of Strings
+    pub symbolize_keys: bool,
+    /// Freeze every decoded value, including nested tables and arrays
+    pub freeze: bool,
+    pub time_as: TimeAs,
+    pub decimal_as: DecimalAs,
+    pub byte_array_as: ByteArrayAs,
+    pub on_unknown_type: UnknownType,
+    /// Decides whether 'l' is signed and whether 'U' is decoded
+    pub dialect: Dialect,
+}
+
+/// Maps an option given as a Symbol to one of `choices`, None when not given.
+fn parse_choice<T: Copy>(
+    name: &str,
+    value: Option<Symbol>,
+    choices: &[(&str, T)],
+) -> std::result::Result<Option<T>, Error> {
+    let Some(value) = value else {
+        return Ok(None);
+    };
+    let value = value.name()?;
+    choices
+        .iter()
+        .find(|(choice, _)| *choice == value)
+        .map(|&(_, choice)| Some(choice))
+        .ok_or_else(|| {
+            let names: Vec<String> = choices.iter().map(|(n, _)| format!(":{}", n)).collect();
+            Error::new(
+                magnus::exception::arg_error(),
+                format!(
+                    "{} must be one of {}, got :{}",
+                    name,
+                    names.join(", "),
+                    value
+                ),
+            )
+        })
+}
+
+fn parse_dialect(dialect: Option<Symbol>) -> std::result::Result<Dialect, Error> {
+    Ok(parse_choice(
+        "dialect",
+        dialect,
+        &[("rabbitmq", Dialect::RabbitMq), ("spec", Dialect::Spec)],
+    )?
+    .unwrap_or_default())
+}
+
+impl DecodeOptions {
+    /// Parses the keyword arguments accepted by `Table.decode`,
+    /// `Table.fetch` and `Table::View.new`.
+    pub fn from_kwargs(kwargs: RHash) -> std::result::Result<Self, Error> {
+        type Options = (
+            Option<bool>,
+            Option<bool>,
+            Option<Symbol>,
+            Option<Symbol>,
+            Option<Symbol>,
+            Option<Symbol>,
+            Option<Symbol>,
+        );
+        let kwargs = scan_args::get_kwargs::<_, (), Options, ()>(
+            kwargs,
+            &[],
+            &[
+                "symbolize_keys",
+                "freeze",
+                "time_as",
+                "decimal_as",
+                "byte_array_as",
+                "on_unknown_type",
+                "dialect",
+            ],
+        )?;
+        let (symbolize_keys, freeze, time_as, decimal_as, byte_array_as, on_unknown_type, dialect) =
+            kwargs.optional;
+
+        Ok(Self {
+            symbolize_keys: symbolize_keys.unwrap_or(false),
+            freeze: freeze.unwrap_or(false),
+            time_as: parse_choice(
+                "time_as",
+                time_as,
+                &[("time", TimeAs::Time), ("integer", TimeAs::Integer)],
+            )?
+            .unwrap_or_default(),
+            decimal_as: parse_choice(
+                "decimal_as",
+                decimal_as,
+                &[
+                    ("float", DecimalAs::Float),
+                    ("big_decimal", DecimalAs::BigDecimal),
+                ],
+            )?
+            .unwrap_or_default(),
+            byte_array_as: parse_choice(
+                "byte_array_as",
+                byte_array_as,
+                &[
+                    ("string", ByteArrayAs::String),
+                    ("object", ByteArrayAs::Object),
+                ],
+            )?
+            .unwrap_or_default(),
+            on_unknown_type: parse_choice(
+                "on_unknown_type",
+                on_unknown_type,
+                &[
+                    ("raise", UnknownType::Raise),
+                    ("skip", UnknownType::Skip),
+                    ("raw", UnknownType::Raw),
+                ],
+            )?
+            .unwrap_or_default(),
+            dialect: parse_dialect(dialect)?,
+        })
+    }
+}
+
+/// Looks up a class defined under `AMQ::Protocol::Table` in Ruby.
+fn table_class(ruby: &Ruby, name: &str) -> std::result::Result<RClass, Error> {
+    let amq: RModule = ruby.class_object().const_get("AMQ")?;
+    let protocol: RModule = amq.const_get("Protocol")?;
+    let table: RClass = protocol.const_get("Table")?;
+    table.const_get(name)
+}
+
+/// Returns the frozen, deduplicated String for `s`, without allocating a
+/// new String when one already exists.
+pub fn interned_str(s: &str) -> RString {
+    unsafe {
+        let raw = rb_sys::rb_enc_interned_str(
+            s.as_ptr() as *const _,
+            s.len() as _,
+            rb_sys::rb_utf8_encoding(),
+        );
+        RString::from_value(Value::from_raw(raw)).unwrap()
+    }
+}
+
+pub fn decode_key(ruby: &Ruby, key: &[u8], options: DecodeOptions) -> Result<Value> {
+    let key = std::str::from_utf8(key)
+        .map_err(|e| AmqpError::DecodingError(format!("Invalid UTF-8 in key: {}", e)))?;
+    Ok(if options.symbolize_keys {
+        ruby.to_symbol(key).as_value()
+    } else {
+        interned_str(key).as_value()
+    })
+}
+
+pub fn decode_table_with(ruby: &Ruby, data: &[u8], options: DecodeOptions) -> Result<RHash> {
     let mut decoder = Decoder::new(data);
-    decode_table_inner(ruby, &mut decoder)
+    let hash = decode_table_inner(ruby, &mut decoder, options)
+        .map_err(|e| locate(e, decoder.position(), None))?;
+    if options.freeze {
+        hash.freeze();
+    }
+    Ok(hash)
 }
 
-fn decode_table_inner(ruby: &Ruby, decoder: &mut Decoder) -> Result<RHash> {
+fn decode_table_inner(ruby: &Ruby, decoder: &mut Decoder, options: DecodeOptions) -> Result<RHash> {
     let hash = ruby.hash_new();
     let table_length = decoder.read_u32()? as usize;
 
@@ -179,31 +783,228 @@ fn decode_table_inner(ruby: &Ruby, decoder: &mut Decoder) -> Result<RHash> {
 
     while decoder.position() < end_pos {
         let key = decoder.read_short_string_bytes()?;
-        let key_str = std::str::from_utf8(key)
-            .map_err(|e| AmqpError::DecodingError(format!("Invalid UTF-8 in key: {}", e)))?;
-
-        let type_tag = decoder.read_u8()?;
-        let value = decode_field_value(ruby, type_tag, decoder)?;
+        let Some((key, value)) = decode_table_field(ruby, key, decoder, options)
+            .map_err(|e| locate(e, decoder.position(), Some(PathSegment::Key(key))))?
+        else {
+            continue;
+        };
 
-        hash.aset(key_str, value)
+        hash.aset(key, value)
             .map_err(|e| AmqpError::DecodingError(format!("Failed to set hash key: {}", e)))?;
     }
 
     Ok(hash)
 }
 
-fn decode_field_value(ruby: &Ruby, type_tag: u8, decoder: &mut Decoder) -> Result<Value> {
+fn decode_table_field(
+    ruby: &Ruby,
+    key: &[u8],
+    decoder: &mut Decoder,
+    options: DecodeOptions,
+) -> Result<Option<(Value, Value)>> {
+    let key = decode_key(ruby, key, options)?;
+    let type_tag = decoder.read_u8()?;
+    Ok(decode_field_value(ruby, type_tag, decoder, options)?.map(|value| (key, value)))
+}
+
+/// A field of an encoded table, located without decoding its value.
+#[derive(Clone, Copy)]
+pub struct Field<'a> {
+    pub key: &'a [u8],
+    pub type_tag: u8,
+    /// Offset of the value within the table data
+    pub offset: usize,
+}
+
+/// Iterates over the fields of an encoded table, skipping over values.
+/// Fields of types the decoder does not support in the options' dialect are
+/// raised, skipped or returned as `on_unknown_type` says, as decoding the
+/// table would.
+pub struct Fields<'a> {
+    decoder: Decoder<'a>,
+    end: usize,
+    options: DecodeOptions,
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(data: &'a [u8], options: DecodeOptions) -> Result<Self> {
+        let mut decoder = Decoder::new(data);
+        let table_length = decoder.read_u32().map_err(|e| locate(e, 0, None))? as usize;
+        Ok(Self {
+            end: decoder.position() + table_length,
+            decoder,
+            options,
+        })
+    }
+
+    pub fn next_field(&mut self) -> Result<Option<Field<'a>>> {
+        while self.decoder.position() < self.end {
+            let decoder = &mut self.decoder;
+            let key = decoder
+                .read_short_string_bytes()
+                .map_err(|e| locate(e, decoder.position(), None))?;
+            let type_tag = decoder
+                .read_u8()
+                .map_err(|e| locate(e, decoder.position(), Some(PathSegment::Key(key))))?;
+            let offset = decoder.position();
+            skip_field_value(type_tag, decoder)
+                .map_err(|e| locate(e, decoder.position(), Some(PathSegment::Key(key))))?;
+
+            if unsupported_type_size(type_tag, self.options.dialect).is_some() {
+                match self.options.on_unknown_type {
+                    UnknownType::Raise => {
+                        let error = AmqpError::InvalidTableType(type_tag as char);
+                        return Err(locate(error, offset, Some(PathSegment::Key(key))));
+                    }
+                    UnknownType::Skip => continue,
+                    UnknownType::Raw => {}
+                }
+            }
+            return Ok(Some(Field {
+                key,
+                type_tag,
+                offset,
+            }));
+        }
+        Ok(None)
+    }
+}
+
+/// Finds the field named `key` in an encoded table. Of duplicate keys the
+/// last one wins, as it does when the whole table is decoded, so the table
+/// is always scanned to the end.
+pub fn find_field<'a>(
+    data: &'a [u8],
+    key: &[u8],
+    options: DecodeOptions,
+) -> Result<Option<Field<'a>>> {
+    let mut fields = Fields::new(data, options)?;
+    let mut found = None;
+    while let Some(field) = fields.next_field()? {
+        if field.key == key {
+            found = Some(field);
+        }
+    }
+    Ok(found)
+}
+
+/// Decodes the value of a field found in `data`, returning None if it was
+/// skipped as an unknown type.
+pub fn decode_field(
+    ruby: &Ruby,
+    data: &[u8],
+    field: &Field,
+    options: DecodeOptions,
+) -> Result<Option<Value>> {
+    let mut decoder = Decoder::at(data, field.offset);
+    decode_field_value(ruby, field.type_tag, &mut decoder, options)
+        .map_err(|e| locate(e, decoder.position(), Some(PathSegment::Key(field.key))))
+}
+
+/// Size of the field types defined by AMQP 0-9-1 that are not decoded in
+/// `dialect`.
+fn unsupported_type_size(type_tag: u8, dialect: Dialect) -> Option<usize> {
     match type_tag {
-        type_tags::STRING | type_tags::BYTE_ARRAY => {
+        b'B' => Some(1),
+        type_tags::SPEC_SHORT if dialect == Dialect::Spec => None,
+        b'U' | b'u' => Some(2),
+        b'i' => Some(4),
+        _ => None,
+    }
+}
+
+fn skip_field_value(type_tag: u8, decoder: &mut Decoder) -> Result<()> {
+    let len = match type_tag {
+        type_tags::STRING | type_tags::BYTE_ARRAY | type_tags::HASH | type_tags::ARRAY => {
+            decoder.read_u32()? as usize
+        }
+        type_tags::LONG | type_tags::SPEC_LONG | type_tags::TIME | type_tags::DOUBLE => 8,
+        type_tags::INTEGER | type_tags::FLOAT => 4,
+        type_tags::DECIMAL => 5,
+        type_tags::SHORT | type_tags::SPEC_SHORT => 2,
+        type_tags::BYTE | type_tags::BOOLEAN => 1,
+        type_tags::VOID => 0,
+        _ => unsupported_type_size(type_tag, Dialect::RabbitMq)
+            .ok_or(AmqpError::InvalidTableType(type_tag as char))?,
+    };
+    decoder.skip(len)
+}
+
+/// Runs `f` with the bytes of a String or Symbol table key.
+pub fn with_key_bytes<R>(key: Value, f: impl FnOnce(&[u8]) -> R) -> std::result::Result<R, Error> {
+    if let Some(symbol) = Symbol::from_value(key) {
+        return Ok(f(symbol.name()?.as_bytes()));
+    }
+    let key = RString::try_convert(key)?;
+    Ok(f(unsafe { key.as_slice() }))
+}
+
+/// Decodes a value, returning None if it was skipped as an unknown type.
+fn decode_field_value(
+    ruby: &Ruby,
+    type_tag: u8,
+    decoder: &mut Decoder,
+    options: DecodeOptions,
+) -> Result<Option<Value>> {
+    let value = match decode_field_value_inner(ruby, type_tag, decoder, options) {
+        // Types without a known size raise whatever the option, as nothing
+        // after them could be located
+        Err(AmqpError::InvalidTableType(_)) if options.on_unknown_type != UnknownType::Raise => {
+            let Some(len) = unsupported_type_size(type_tag, options.dialect) else {
+                return Err(AmqpError::InvalidTableType(type_tag as char));
+            };
+            let bytes = decoder.read_bytes(len)?;
+            if options.on_unknown_type == UnknownType::Skip {
+                return Ok(None);
+            }
+            raw_value(ruby, type_tag, bytes)?
+        }
+        result => result?,
+    };
+    if options.freeze {
+        value.freeze();
+    }
+    Ok(Some(value))
+}
+
+fn raw_value(ruby: &Ruby, type_tag: u8, bytes: &[u8]) -> Result<Value> {
+    table_class(ruby, "RawValue")
+        .and_then(|class| {
+            class.new_instance((RString::from_slice(&[type_tag]), RString::from_slice(bytes)))
+        })
+        .map_err(|e| AmqpError::DecodingError(format!("Failed to create RawValue: {}", e)))
+}
+
+fn decode_field_value_inner(
+    ruby: &Ruby,
+    type_tag: u8,
+    decoder: &mut Decoder,
+    options: DecodeOptions,
+) -> Result<Value> {
+    match type_tag {
+        type_tags::STRING => {
             let bytes = decoder.read_long_string()?;
             let s = RString::from_slice(bytes);
             Ok(s.as_value())
         }
+        type_tags::BYTE_ARRAY => {
+            let bytes = RString::from_slice(decoder.read_long_string()?);
+            if options.byte_array_as == ByteArrayAs::String {
+                return Ok(bytes.as_value());
+            }
+            table_class(ruby, "ByteArray")
+                .and_then(|class| class.new_instance((bytes,)))
+                .map_err(|e| AmqpError::DecodingError(format!("Failed to create ByteArray: {}", e)))
+        }
         type_tags::INTEGER => {
             let v = decoder.read_i32()?;
             Ok(ruby.integer_from_i64(v as i64).as_value())
         }
-        type_tags::LONG => {
+        type_tags::LONG if options.dialect == Dialect::Spec => {
+            let v = decoder.read_u64()?;
+            Ok(ruby.integer_from_u64(v).as_value())
+        }
+        type_tags::LONG | type_tags::SPEC_LONG => {
             let v = decoder.read_i64()?;
             Ok(ruby.integer_from_i64(v).as_value())
         }
@@ -211,12 +1012,19 @@ fn decode_field_value(ruby: &Ruby, type_tag: u8, decoder: &mut Decoder) -> Resul
             let v = decoder.read_i16()?;
             Ok(ruby.integer_from_i64(v as i64).as_value())
         }
+        type_tags::SPEC_SHORT if options.dialect == Dialect::Spec => {
+            let v = decoder.read_i16()?;
+            Ok(ruby.integer_from_i64(v as i64).as_value())
+        }
         type_tags::BYTE => {
             let v = decoder.read_i8()?;
             Ok(ruby.integer_from_i64(v as i64).as_value())
         }
         type_tags::TIME => {
             let timestamp = decoder.read_i64()?;
+            if options.time_as == TimeAs::Integer {
+                return Ok(ruby.integer_from_i64(timestamp).as_value());
+            }
             let time_class = ruby.class_time();
             let time: Value = time_class
                 .funcall("at", (timestamp,))
@@ -226,6 +1034,9 @@ fn decode_field_value(ruby: &Ruby, type_tag: u8, decoder: &mut Decoder) -> Resul
         type_tags::DECIMAL => {
             let scale = decoder.read_u8()?;
             let value = decoder.read_u32()?;
+            if options.decimal_as == DecimalAs::BigDecimal {
+                return big_decimal(ruby, scale, value);
+            }
             let decimal = (value as f64) / (10_u32.pow(scale as u32) as f64);
             Ok(ruby.float_from_f64(decimal).as_value())
         }
@@ -246,11 +1057,11 @@ fn decode_field_value(ruby: &Ruby, type_tag: u8, decoder: &mut Decoder) -> Resul
             })
         }
         type_tags::HASH => {
-            let hash = decode_table_inner(ruby, decoder)?;
+            let hash = decode_table_inner(ruby, decoder, options)?;
             Ok(hash.as_value())
         }
         type_tags::ARRAY => {
-            let array = decode_array(ruby, decoder)?;
+            let array = decode_array(ruby, decoder, options)?;
             Ok(array.as_value())
         }
         type_tags::VOID => Ok(ruby.qnil().as_value()),
@@ -258,7 +1069,16 @@ fn decode_field_value(ruby: &Ruby, type_tag: u8, decoder: &mut Decoder) -> Resul
     }
 }
 
-fn decode_array(ruby: &Ruby, decoder: &mut Decoder) -> Result<RArray> {
+fn big_decimal(ruby: &Ruby, scale: u8, value: u32) -> Result<Value> {
+    ruby.require("bigdecimal")
+        .and_then(|_| {
+            ruby.module_kernel()
+                .funcall("BigDecimal", (format!("{}e-{}", value, scale),))
+        })
+        .map_err(|e| AmqpError::DecodingError(format!("Failed to create BigDecimal: {}", e)))
+}
+
+fn decode_array(ruby: &Ruby, decoder: &mut Decoder, options: DecodeOptions) -> Result<RArray> {
     let array = ruby.ary_new();
     let array_length = decoder.read_u32()? as usize;
 
@@ -268,9 +1088,16 @@ fn decode_array(ruby: &Ruby, decoder: &mut Decoder) -> Result<RArray> {
 
     let end_pos = decoder.position() + array_length;
 
+    let mut index = 0;
     while decoder.position() < end_pos {
-        let type_tag = decoder.read_u8()?;
-        let value = decode_field_value(ruby, type_tag, decoder)?;
+        let value = decoder
+            .read_u8()
+            .and_then(|type_tag| decode_field_value(ruby, type_tag, decoder, options))
+            .map_err(|e| locate(e, decoder.position(), Some(PathSegment::Index(index))))?;
+        index += 1;
+        let Some(value) = value else {
+            continue;
+        };
         array
             .push(value)
             .map_err(|e| AmqpError::DecodingError(format!("Failed to push to array: {}", e)))?;
@@ -279,14 +1106,441 @@ fn decode_array(ruby: &Ruby, decoder: &mut Decoder) -> Result<RArray> {
     Ok(array)
 }
 
-fn rb_encode(ruby: &Ruby, hash: RHash) -> std::result::Result<RString, Error> {
-    let bytes = encode_table(ruby, hash).map_err(Error::from)?;
-    Ok(RString::from_slice(&bytes))
+/// Returns the number of bytes `write_table_with` would write for `hash`,
+/// walking it without encoding anything.
+pub fn table_size(ruby: &Ruby, hash: RHash, options: EncodeOptions) -> Result<usize> {
+    // The order canonical encoding writes the keys in does not change the
+    // size, but duplicate keys must still raise
+    if options.canonical {
+        let mut kinds = KeyKinds::default();
+        hash.foreach(|key: Value, _: Value| {
+            kinds.add(ruby, key);
+            Ok(magnus::r_hash::ForEach::Continue)
+        })
+        .map_err(|e| AmqpError::EncodingError(e.to_string()))?;
+        if kinds.may_collide() {
+            check_duplicate_keys(ruby, hash)?;
+        }
+    }
+    let mut size = 4;
+    let mut failure = None;
+    hash.foreach(|key: Value, value: Value| {
+        let result = table_key(ruby, key)
+            .map_err(|e| encode_failed(e, key, None))
+            .and_then(|key| {
+                let value_size = field_value_size(ruby, value, options)
+                    .map_err(|e| encode_failed(e, value, Some(PathSegment::Key(key.as_bytes()))))?;
+                Ok(1 + key.len() + value_size)
+            });
+        match result {
+            Ok(field_size) => {
+                size += field_size;
+                Ok(magnus::r_hash::ForEach::Continue)
+            }
+            Err(e) => {
+                failure = Some(e);
+                Ok(magnus::r_hash::ForEach::Stop)
+            }
+        }
+    })
+    .map_err(|e| AmqpError::EncodingError(e.to_string()))?;
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(size),
+    }
+}
+
+/// The size of a value as written by `encode_field_value`, including its
+/// type tag.
+fn field_value_size(ruby: &Ruby, value: Value, options: EncodeOptions) -> Result<usize> {
+    if value.is_nil() {
+        return Ok(1);
+    }
+
+    let size = if value.is_kind_of(ruby.class_string()) {
+        5 + string_field_len(ruby, value)?
+    } else if let Some(symbol) = Symbol::from_value(value) {
+        let name = symbol
+            .name()
+            .map_err(|_| AmqpError::EncodingError("Invalid symbol name".into()))?;
+        5 + name.len()
+    } else if let Some(integer) = Integer::from_value(value) {
+        integer_size(integer, options)?
+    } else if value.is_kind_of(ruby.class_float()) || value.is_kind_of(ruby.class_time()) {
+        9
+    } else if value.is_kind_of(ruby.class_true_class())
+        || value.is_kind_of(ruby.class_false_class())
+    {
+        2
+    } else if let Some(hash) = RHash::from_value(value) {
+        1 + table_size(ruby, hash, options)?
+    } else if let Some(array) = RArray::from_value(value) {
+        let mut size = 5;
+        for i in 0..array.len() {
+            let value: Value = array
+                .entry(i as isize)
+                .map_err(|_| AmqpError::EncodingError("Failed to get array entry".into()))?;
+            size += field_value_size(ruby, value, options)
+                .map_err(|e| encode_failed(e, value, Some(PathSegment::Index(i))))?;
+        }
+        size
+    } else if let Some(size) = decoded_object_size(ruby, value)? {
+        size
+    } else if let Some(field) = converted_field(value)? {
+        field_value_size(ruby, field, options)?
+    } else {
+        return Err(AmqpError::InvalidTableValue(class_name(value)));
+    };
+    Ok(size)
+}
+
+/// The length of a String after the conversion to UTF-8 encoding does,
+/// which only has to be carried out for Strings it would transcode.
+fn string_field_len(ruby: &Ruby, value: Value) -> Result<usize> {
+    let failed = || AmqpError::EncodingError("Failed to convert string".into());
+    let string = RString::from_value(value).ok_or_else(failed)?;
+    let encoding = string.enc_get();
+    let coderange = string.enc_coderange_scan();
+
+    if coderange == Coderange::SevenBit
+        || (encoding == ruby.utf8_encindex() && coderange == Coderange::Valid)
+    {
+        return Ok(string.len());
+    }
+    // Kept as they are, so they only have to be valid UTF-8
+    if encoding == ruby.utf8_encindex()
+        || encoding == ruby.usascii_encindex()
+        || encoding == ruby.ascii8bit_encindex()
+    {
+        return std::str::from_utf8(unsafe { string.as_slice() })
+            .map(str::len)
+            .map_err(|_| failed());
+    }
+    let s: String = TryConvert::try_convert(value).map_err(|_| failed())?;
+    Ok(s.len())
+}
+
+fn integer_size(integer: Integer, options: EncodeOptions) -> Result<usize> {
+    Ok(match integer_field(integer, options)? {
+        IntegerField::Compact(i) if i8::try_from(i).is_ok() => 2,
+        IntegerField::Compact(i) if i16::try_from(i).is_ok() => 3,
+        IntegerField::Compact(_) => 5,
+        IntegerField::Signed(_) | IntegerField::Unsigned(_) => 9,
+        IntegerField::String(s) => 5 + s.len(),
+    })
+}
+
+/// The size of the objects `encode_decoded_object` writes, None for others.
+fn decoded_object_size(ruby: &Ruby, value: Value) -> Result<Option<usize>> {
+    let to_encoding_error = |e: Error| AmqpError::EncodingError(e.to_string());
+
+    if value.is_kind_of(table_class(ruby, "ByteArray").map_err(to_encoding_error)?) {
+        let bytes: RString = value.funcall("bytes", ()).map_err(to_encoding_error)?;
+        return Ok(Some(5 + bytes.len()));
+    }
+    if value.is_kind_of(table_class(ruby, "RawValue").map_err(to_encoding_error)?) {
+        let type_tag: RString = value.funcall("type", ()).map_err(to_encoding_error)?;
+        let bytes: RString = value.funcall("bytes", ()).map_err(to_encoding_error)?;
+        if type_tag.len() != 1 {
+            return Err(AmqpError::EncodingError(
+                "RawValue type must be a single character".into(),
+            ));
+        }
+        return Ok(Some(1 + bytes.len()));
+    }
+    Ok(None)
 }
 
-fn rb_decode(ruby: &Ruby, data: RString) -> std::result::Result<RHash, Error> {
+/// Decodes a table as an Array of `[key, type, value]` triples in wire
+/// order, keeping duplicate keys. Nested tables are Arrays of triples too,
+/// and arrays Arrays of `[type, value]` pairs. Values whose Ruby form would
+/// not encode back to the same bytes are `Table::RawValue`s.
+pub fn decode_ordered(ruby: &Ruby, data: &[u8]) -> Result<RArray> {
+    let mut decoder = Decoder::new(data);
+    decode_ordered_table(ruby, &mut decoder).map_err(|e| locate(e, decoder.position(), None))
+}
+
+fn decode_ordered_table(ruby: &Ruby, decoder: &mut Decoder) -> Result<RArray> {
+    let triples = ruby.ary_new();
+    let length = decoder.read_u32()? as usize;
+    let end_pos = decoder.position() + length;
+
+    while decoder.position() < end_pos {
+        let key = decoder.read_short_string_bytes()?;
+        // Binary, so keys that are not valid UTF-8 survive too
+        let triple = decoder
+            .read_u8()
+            .and_then(|type_tag| {
+                let value = decode_ordered_value(ruby, type_tag, decoder)?;
+                Ok((
+                    RString::from_slice(key),
+                    RString::from_slice(&[type_tag]),
+                    value,
+                ))
+            })
+            .map_err(|e| locate(e, decoder.position(), Some(PathSegment::Key(key))))?;
+        triples
+            .push(triple)
+            .map_err(|e| AmqpError::DecodingError(format!("Failed to push to array: {}", e)))?;
+    }
+    check_end(decoder, end_pos)?;
+
+    Ok(triples)
+}
+
+fn decode_ordered_array(ruby: &Ruby, decoder: &mut Decoder) -> Result<RArray> {
+    let pairs = ruby.ary_new();
+    let length = decoder.read_u32()? as usize;
+    let end_pos = decoder.position() + length;
+
+    let mut index = 0;
+    while decoder.position() < end_pos {
+        let pair = decoder
+            .read_u8()
+            .and_then(|type_tag| {
+                let value = decode_ordered_value(ruby, type_tag, decoder)?;
+                Ok((RString::from_slice(&[type_tag]), value))
+            })
+            .map_err(|e| locate(e, decoder.position(), Some(PathSegment::Index(index))))?;
+        index += 1;
+        pairs
+            .push(pair)
+            .map_err(|e| AmqpError::DecodingError(format!("Failed to push to array: {}", e)))?;
+    }
+    check_end(decoder, end_pos)?;
+
+    Ok(pairs)
+}
+
+/// Raises unless the last field of a table or array ended exactly where
+/// its length says, as one running past it would not encode back to the
+/// same bytes.
+fn check_end(decoder: &Decoder, end_pos: usize) -> Result<()> {
+    if decoder.position() != end_pos {
+        return Err(AmqpError::DecodingError(format!(
+            "Fields end at byte {} but the length says {}",
+            decoder.position(),
+            end_pos
+        )));
+    }
+    Ok(())
+}
+
+fn decode_ordered_value(ruby: &Ruby, type_tag: u8, decoder: &mut Decoder) -> Result<Value> {
+    match type_tag {
+        type_tags::HASH => Ok(decode_ordered_table(ruby, decoder)?.as_value()),
+        type_tags::ARRAY => Ok(decode_ordered_array(ruby, decoder)?.as_value()),
+        // A Float would lose the scale
+        type_tags::DECIMAL => raw_value(ruby, type_tag, decoder.read_bytes(5)?),
+        type_tags::BOOLEAN => match decoder.read_bytes(1)? {
+            &[0] => Ok(ruby.qfalse().as_value()),
+            &[1] => Ok(ruby.qtrue().as_value()),
+            bytes => raw_value(ruby, type_tag, bytes),
+        },
+        _ => {
+            let options = DecodeOptions {
+                on_unknown_type: UnknownType::Raw,
+                ..DecodeOptions::default()
+            };
+            // Never None, as unknown types are returned raw
+            Ok(decode_field_value(ruby, type_tag, decoder, options)?
+                .unwrap_or_else(|| ruby.qnil().as_value()))
+        }
+    }
+}
+
+/// Encodes an Array of `[key, type, value]` triples as produced by
+/// `decode_ordered`, writing each value as the type it is given with.
+pub fn write_ordered(ruby: &Ruby, triples: RArray, encoder: &mut Encoder) -> Result<()> {
+    let length_offset = encoder.reserve_length();
+
+    for i in 0..triples.len() {
+        let triple: Value = triples
+            .entry(i as isize)
+            .map_err(|_| AmqpError::EncodingError("Failed to get array entry".into()))?;
+        let (key, type_tag, value) =
+            <(Value, RString, Value)>::try_convert(triple).map_err(|e| {
+                let error = format!("Expected a [key, type, value] triple: {}", e);
+                encode_failed(AmqpError::EncodingError(error), triple, None)
+            })?;
+        // Written as is, as decode_ordered returns them
+        let key_bytes = with_key_bytes(key, |bytes| bytes.to_vec()).map_err(|_| {
+            let error = AmqpError::EncodingError("Expected symbol or string key".into());
+            encode_failed(error, key, None)
+        })?;
+        if key_bytes.len() > 255 {
+            let error = AmqpError::EncodingError(format!(
+                "Table key too long: {} (max 255)",
+                key_bytes.len()
+            ));
+            return Err(encode_failed(error, key, None));
+        }
+        encoder.write_u8(key_bytes.len() as u8);
+        encoder.write_bytes(&key_bytes);
+        encode_typed_value(ruby, type_tag, value, encoder)
+            .map_err(|e| encode_failed(e, value, Some(PathSegment::Key(&key_bytes))))?;
+    }
+
+    encoder.patch_length(length_offset);
+
+    Ok(())
+}
+
+fn write_ordered_array(ruby: &Ruby, pairs: RArray, encoder: &mut Encoder) -> Result<()> {
+    let length_offset = encoder.reserve_length();
+
+    for i in 0..pairs.len() {
+        let pair: Value = pairs
+            .entry(i as isize)
+            .map_err(|_| AmqpError::EncodingError("Failed to get array entry".into()))?;
+        let (type_tag, value) = <(RString, Value)>::try_convert(pair).map_err(|e| {
+            let error = format!("Expected a [type, value] pair: {}", e);
+            encode_failed(
+                AmqpError::EncodingError(error),
+                pair,
+                Some(PathSegment::Index(i)),
+            )
+        })?;
+        encode_typed_value(ruby, type_tag, value, encoder)
+            .map_err(|e| encode_failed(e, value, Some(PathSegment::Index(i))))?;
+    }
+
+    encoder.patch_length(length_offset);
+
+    Ok(())
+}
+
+fn typed<T: TryConvert>(value: Value, type_tag: u8) -> Result<T> {
+    T::try_convert(value).map_err(|e| {
+        AmqpError::EncodingError(format!(
+            "Invalid value for type '{}': {}",
+            type_tag as char, e
+        ))
+    })
+}
+
+/// Writes `value` as the field type `type_tag`. `Table::RawValue` and
+/// `Table::ByteArray` values are written as they were decoded.
+fn encode_typed_value(
+    ruby: &Ruby,
+    type_tag: RString,
+    value: Value,
+    encoder: &mut Encoder,
+) -> Result<()> {
+    let &[type_tag] = (unsafe { type_tag.as_slice() }) else {
+        return Err(AmqpError::EncodingError(
+            "Field type must be a single character".into(),
+        ));
+    };
+    if encode_decoded_object(ruby, value, encoder)? {
+        return Ok(());
+    }
+
+    encoder.write_u8(type_tag);
+    match type_tag {
+        type_tags::STRING | type_tags::BYTE_ARRAY => {
+            let bytes: RString = typed(value, type_tag)?;
+            encoder.write_long_string(unsafe { bytes.as_slice() });
+        }
+        type_tags::BYTE => encoder.write_u8(typed::<i8>(value, type_tag)? as u8),
+        type_tags::SHORT | type_tags::SPEC_SHORT => {
+            encoder.write_u16(typed::<i16>(value, type_tag)? as u16)
+        }
+        type_tags::INTEGER => encoder.write_u32(typed::<i32>(value, type_tag)? as u32),
+        type_tags::LONG | type_tags::SPEC_LONG => encoder.write_i64(typed(value, type_tag)?),
+        type_tags::FLOAT => encoder.write_u32(typed::<f32>(value, type_tag)?.to_bits()),
+        type_tags::DOUBLE => encoder.write_f64(typed(value, type_tag)?),
+        type_tags::BOOLEAN if value.is_kind_of(ruby.class_true_class()) => encoder.write_u8(1),
+        type_tags::BOOLEAN if value.is_kind_of(ruby.class_false_class()) => encoder.write_u8(0),
+        type_tags::TIME => {
+            let timestamp: i64 = if value.is_kind_of(ruby.class_time()) {
+                value.funcall("to_i", ()).map_err(AmqpError::Raised)?
+            } else {
+                typed(value, type_tag)?
+            };
+            encoder.write_i64(timestamp);
+        }
+        type_tags::VOID if value.is_nil() => {}
+        type_tags::HASH => write_ordered(ruby, typed(value, type_tag)?, encoder)?,
+        type_tags::ARRAY => write_ordered_array(ruby, typed(value, type_tag)?, encoder)?,
+        _ => {
+            return Err(AmqpError::EncodingError(format!(
+                "Cannot encode a {} as type '{}'",
+                class_name(value),
+                type_tag as char
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// `encode(hash, **options)`, see `EncodeOptions::from_kwargs` for the
+/// options.
+fn rb_encode(ruby: &Ruby, args: &[Value]) -> std::result::Result<RString, Error> {
+    let args = scan_args::scan_args::<(RHash,), (), (), (), RHash, ()>(args)?;
+    let (hash,) = args.required;
+    let options = EncodeOptions::from_kwargs(args.keywords)?;
+    let mut encoder = Encoder::new();
+    write_table_with(ruby, hash, options, &mut encoder).map_err(Error::from)?;
+    Ok(RString::from_slice(encoder.as_slice()))
+}
+
+/// `encode_into(buffer, hash, **options)`
+fn rb_encode_into(ruby: &Ruby, args: &[Value]) -> std::result::Result<Value, Error> {
+    let args = scan_args::scan_args::<(Value, RHash), (), (), (), RHash, ()>(args)?;
+    let (buffer, hash) = args.required;
+    let options = EncodeOptions::from_kwargs(args.keywords)?;
+    buffer::with_encoder(buffer, |encoder| {
+        write_table_with(ruby, hash, options, encoder).map_err(Error::from)
+    })?;
+    Ok(buffer)
+}
+
+/// `decode(data, **options)`, see `DecodeOptions::from_kwargs` for the
+/// options.
+///
+/// String keys are always interned, so decoding the same header names over
+/// and over does not allocate a new String for each key.
+fn rb_decode(ruby: &Ruby, args: &[Value]) -> std::result::Result<RHash, Error> {
+    let args = scan_args::scan_args::<(RString,), (), (), (), RHash, ()>(args)?;
+    let (data,) = args.required;
+    let options = DecodeOptions::from_kwargs(args.keywords)?;
+    let bytes = unsafe { data.as_slice() };
+    decode_table_with(ruby, bytes, options).map_err(Error::from)
+}
+
+/// `fetch(data, key, **options)`, decoding only the value of `key`.
+/// Returns nil if the table has no such key.
+fn rb_fetch(ruby: &Ruby, args: &[Value]) -> std::result::Result<Value, Error> {
+    let args = scan_args::scan_args::<(RString, Value), (), (), (), RHash, ()>(args)?;
+    let (data, key) = args.required;
+    let options = DecodeOptions::from_kwargs(args.keywords)?;
     let bytes = unsafe { data.as_slice() };
-    decode_table(ruby, bytes).map_err(Error::from)
+    let field = with_key_bytes(key, |key| find_field(bytes, key, options))?.map_err(Error::from)?;
+    let value = match field {
+        Some(field) => decode_field(ruby, bytes, &field, options).map_err(Error::from)?,
+        None => None,
+    };
+    Ok(value.unwrap_or_else(|| ruby.qnil().as_value()))
+}
+
+/// `encoded_size(hash, **options)`, the size of what `encode` would return.
+fn rb_encoded_size(ruby: &Ruby, args: &[Value]) -> std::result::Result<usize, Error> {
+    let args = scan_args::scan_args::<(RHash,), (), (), (), RHash, ()>(args)?;
+    let (hash,) = args.required;
+    let options = EncodeOptions::from_kwargs(args.keywords)?;
+    table_size(ruby, hash, options).map_err(Error::from)
+}
+
+fn rb_decode_ordered(ruby: &Ruby, data: RString) -> std::result::Result<RArray, Error> {
+    let bytes = unsafe { data.as_slice() };
+    decode_ordered(ruby, bytes).map_err(Error::from)
+}
+
+fn rb_encode_ordered(ruby: &Ruby, triples: RArray) -> std::result::Result<RString, Error> {
+    let mut encoder = Encoder::new();
+    write_ordered(ruby, triples, &mut encoder).map_err(Error::from)?;
+    Ok(RString::from_slice(encoder.as_slice()))
 }
 
 fn rb_length(data: RString) -> std::result::Result<u32, Error> {
@@ -304,27 +1558,32 @@ fn rb_length(data: RString) -> std::result::Result<u32, Error> {
 pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
     let table = protocol.define_class("Table", ruby.class_object())?;
 
-    table.define_singleton_method("encode", function!(rb_encode, 1))?;
-    table.define_singleton_method("decode", function!(rb_decode, 1))?;
+    table.define_singleton_method("encode", function!(rb_encode, -1))?;
+    table.define_singleton_method("encode_into", function!(rb_encode_into, -1))?;
+    table.define_singleton_method("decode", function!(rb_decode, -1))?;
+    table.define_singleton_method("fetch", function!(rb_fetch, -1))?;
+    table.define_singleton_method("encoded_size", function!(rb_encoded_size, -1))?;
+    table.define_singleton_method("decode_ordered", function!(rb_decode_ordered, 1))?;
+    table.define_singleton_method("encode_ordered", function!(rb_encode_ordered, 1))?;
     table.define_singleton_method("length", function!(rb_length, 1))?;
 
     let type_constants = protocol.define_module("TypeConstants")?;
-    type_constants.const_set("TYPE_STRING", "S")?;
-    type_constants.const_set("TYPE_INTEGER", "I")?;
-    type_constants.const_set("TYPE_TIME", "T")?;
-    type_constants.const_set("TYPE_DECIMAL", "D")?;
-    type_constants.const_set("TYPE_HASH", "F")?;
-    type_constants.const_set("TYPE_ARRAY", "A")?;
-    type_constants.const_set("TYPE_BYTE", "b")?;
-    type_constants.const_set("TYPE_64BIT_FLOAT", "d")?;
-    type_constants.const_set("TYPE_32BIT_FLOAT", "f")?;
-    type_constants.const_set("TYPE_SIGNED_64BIT", "l")?;
-    type_constants.const_set("TYPE_SIGNED_16BIT", "s")?;
-    type_constants.const_set("TYPE_BOOLEAN", "t")?;
-    type_constants.const_set("TYPE_BYTE_ARRAY", "x")?;
-    type_constants.const_set("TYPE_VOID", "V")?;
-    type_constants.const_set("BOOLEAN_TRUE", "\x01")?;
-    type_constants.const_set("BOOLEAN_FALSE", "\x00")?;
+    type_constants.const_set("TYPE_STRING", frozen_str("S"))?;
+    type_constants.const_set("TYPE_INTEGER", frozen_str("I"))?;
+    type_constants.const_set("TYPE_TIME", frozen_str("T"))?;
+    type_constants.const_set("TYPE_DECIMAL", frozen_str("D"))?;
+    type_constants.const_set("TYPE_HASH", frozen_str("F"))?;
+    type_constants.const_set("TYPE_ARRAY", frozen_str("A"))?;
+    type_constants.const_set("TYPE_BYTE", frozen_str("b"))?;
+    type_constants.const_set("TYPE_64BIT_FLOAT", frozen_str("d"))?;
+    type_constants.const_set("TYPE_32BIT_FLOAT", frozen_str("f"))?;
+    type_constants.const_set("TYPE_SIGNED_64BIT", frozen_str("l"))?;
+    type_constants.const_set("TYPE_SIGNED_16BIT", frozen_str("s"))?;
+    type_constants.const_set("TYPE_BOOLEAN", frozen_str("t"))?;
+    type_constants.const_set("TYPE_BYTE_ARRAY", frozen_str("x"))?;
+    type_constants.const_set("TYPE_VOID", frozen_str("V"))?;
+    type_constants.const_set("BOOLEAN_TRUE", frozen_str("\x01"))?;
+    type_constants.const_set("BOOLEAN_FALSE", frozen_str("\x00"))?;
 
     Ok(())
 }
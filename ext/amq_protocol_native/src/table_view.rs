@@ -0,0 +1,139 @@
+//! Lazy access to encoded field tables
+//!
+//! A view keeps the encoded bytes and only decodes the values asked for,
+//! scanning past the others. Reading one or two headers out of a large
+//! table this way allocates nothing but the values returned.
+
+use magnus::{
+    function, gc, method, prelude::*, scan_args, typed_data::Obj, value::Opaque, DataTypeFunctions,
+    Error, Module, RArray, RClass, RHash, RString, Ruby, TypedData, Value,
+};
+
+use crate::table::{
+    decode_field, decode_key, find_field, with_key_bytes, DecodeOptions, Field, Fields,
+};
+
+#[derive(TypedData)]
+#[magnus(class = "AMQ::Protocol::Table::View", free_immediately, mark, size)]
+struct View {
+    /// A frozen string, shared with the one given to new where possible
+    data: Opaque<RString>,
+    options: DecodeOptions,
+}
+
+impl DataTypeFunctions for View {
+    fn mark(&self, marker: &gc::Marker) {
+        marker.mark(self.data);
+    }
+}
+
+impl View {
+    /// `new(data, **options)`, taking the same options as `Table.decode`.
+    fn new(args: &[Value]) -> std::result::Result<Self, Error> {
+        let args = scan_args::scan_args::<(RString,), (), (), (), RHash, ()>(args)?;
+        let (data,) = args.required;
+        Ok(Self {
+            data: RString::new_frozen(data).into(),
+            options: DecodeOptions::from_kwargs(args.keywords)?,
+        })
+    }
+
+    /// Locates the fields of `bytes` without decoding any values, keeping
+    /// one per key as `Table.decode` does: where the key first appears, with
+    /// the value it was last given.
+    fn unique_fields<'a>(
+        bytes: &'a [u8],
+        options: DecodeOptions,
+    ) -> std::result::Result<Vec<Field<'a>>, Error> {
+        let mut all = Vec::new();
+        let mut fields = Fields::new(bytes, options)?;
+        while let Some(field) = fields.next_field()? {
+            all.push(field);
+        }
+
+        let mut unique = Vec::with_capacity(all.len());
+        for (i, field) in all.iter().enumerate() {
+            if all[..i].iter().any(|earlier| earlier.key == field.key) {
+                continue;
+            }
+            let last = all[i..].iter().rev().find(|later| later.key == field.key);
+            unique.extend(last.copied());
+        }
+        Ok(unique)
+    }
+
+    fn get(ruby: &Ruby, rb_self: &Self, key: Value) -> std::result::Result<Value, Error> {
+        let data = ruby.get_inner(rb_self.data);
+        let bytes = unsafe { data.as_slice() };
+        let value = match with_key_bytes(key, |key| find_field(bytes, key, rb_self.options))?? {
+            Some(field) => decode_field(ruby, bytes, &field, rb_self.options)?,
+            None => None,
+        };
+        Ok(value.unwrap_or_else(|| ruby.qnil().as_value()))
+    }
+
+    fn has_key(ruby: &Ruby, rb_self: &Self, key: Value) -> std::result::Result<bool, Error> {
+        let data = ruby.get_inner(rb_self.data);
+        let bytes = unsafe { data.as_slice() };
+        Ok(with_key_bytes(key, |key| find_field(bytes, key, rb_self.options))??.is_some())
+    }
+
+    fn keys(ruby: &Ruby, rb_self: &Self) -> std::result::Result<RArray, Error> {
+        // Keys live in a Ruby Array, where the GC can see them
+        let keys = ruby.ary_new();
+        let data = ruby.get_inner(rb_self.data);
+        for field in Self::unique_fields(unsafe { data.as_slice() }, rb_self.options)? {
+            keys.push(decode_key(ruby, field.key, rb_self.options)?)?;
+        }
+        Ok(keys)
+    }
+
+    fn size(ruby: &Ruby, rb_self: &Self) -> std::result::Result<usize, Error> {
+        let data = ruby.get_inner(rb_self.data);
+        Ok(Self::unique_fields(unsafe { data.as_slice() }, rb_self.options)?.len())
+    }
+
+    /// Yields each key and value, decoding values one at a time.
+    fn each(ruby: &Ruby, rb_self: Obj<Self>) -> std::result::Result<Value, Error> {
+        if !ruby.block_given() {
+            return Ok(rb_self.enumeratorize("each", ()).as_value());
+        }
+
+        // The string is frozen and pinned by mark, so the bytes stay valid
+        // while the block runs
+        let data = ruby.get_inner(rb_self.data);
+        let bytes = unsafe { data.as_slice() };
+        for field in Self::unique_fields(bytes, rb_self.options)? {
+            let key = decode_key(ruby, field.key, rb_self.options)?;
+            if let Some(value) = decode_field(ruby, bytes, &field, rb_self.options)? {
+                let _: Value = ruby.yield_values((key, value))?;
+            }
+        }
+        Ok(rb_self.as_value())
+    }
+
+    fn bytesize(ruby: &Ruby, rb_self: &Self) -> usize {
+        ruby.get_inner(rb_self.data).len()
+    }
+
+    fn to_s(ruby: &Ruby, rb_self: &Self) -> RString {
+        ruby.get_inner(rb_self.data)
+    }
+}
+
+pub fn init(ruby: &Ruby, protocol: &impl Module) -> std::result::Result<(), Error> {
+    let table: RClass = protocol.const_get("Table")?;
+    let view = table.define_class("View", ruby.class_object())?;
+
+    view.define_singleton_method("new", function!(View::new, -1))?;
+    view.define_method("[]", method!(View::get, 1))?;
+    view.define_method("key?", method!(View::has_key, 1))?;
+    view.define_method("include?", method!(View::has_key, 1))?;
+    view.define_method("keys", method!(View::keys, 0))?;
+    view.define_method("size", method!(View::size, 0))?;
+    view.define_method("each", method!(View::each, 0))?;
+    view.define_method("bytesize", method!(View::bytesize, 0))?;
+    view.define_method("to_s", method!(View::to_s, 0))?;
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! Compact set of delivery tags / publish sequence numbers
+//!
+//! Tags are stored as disjoint, non-adjacent inclusive ranges keyed by their
+//! start, so millions of in-flight tags with few gaps cost a handful of
+//! B-tree nodes and point operations are O(log n) in the number of ranges.
+
+use std::collections::BTreeMap;
+
+use magnus::{Error, RArray, Ruby};
+
+#[derive(Debug, Default, Clone)]
+pub struct TagSet {
+    ranges: BTreeMap<u64, u64>,
+    len: u64,
+}
+
+impl TagSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[inline]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.ranges.clear();
+        self.len = 0;
+    }
+
+    pub fn first(&self) -> Option<u64> {
+        self.ranges.first_key_value().map(|(&start, _)| start)
+    }
+
+    /// Returns the range containing `tag`, if any.
+    fn range_of(&self, tag: u64) -> Option<(u64, u64)> {
+        self.ranges
+            .range(..=tag)
+            .next_back()
+            .filter(|(_, &end)| end >= tag)
+            .map(|(&start, &end)| (start, end))
+    }
+
+    pub fn contains(&self, tag: u64) -> bool {
+        self.range_of(tag).is_some()
+    }
+
+    /// Adds `tag`, merging it with adjacent ranges. Returns false if the tag
+    /// was already present.
+    pub fn insert(&mut self, tag: u64) -> bool {
+        let prev = self
+            .ranges
+            .range(..=tag)
+            .next_back()
+            .map(|(&start, &end)| (start, end));
+
+        if let Some((_, end)) = prev {
+            if end >= tag {
+                return false;
+            }
+        }
+
+        let mut start = tag;
+        let mut end = tag;
+
+        if let Some((prev_start, prev_end)) = prev {
+            if prev_end.checked_add(1) == Some(tag) {
+                self.ranges.remove(&prev_start);
+                start = prev_start;
+            }
+        }
+
+        if let Some(next_start) = tag.checked_add(1) {
+            if let Some(next_end) = self.ranges.remove(&next_start) {
+                end = next_end;
+            }
+        }
+
+        self.ranges.insert(start, end);
+        self.len += 1;
+        true
+    }
+
+    /// Removes `tag`, splitting its range if needed. Returns false if the tag
+    /// was not present.
+    pub fn remove(&mut self, tag: u64) -> bool {
+        let Some((start, end)) = self.range_of(tag) else {
+            return false;
+        };
+
+        self.ranges.remove(&start);
+        if start < tag {
+            self.ranges.insert(start, tag - 1);
+        }
+        if tag < end {
+            self.ranges.insert(tag + 1, end);
+        }
+        self.len -= 1;
+        true
+    }
+
+    /// Removes every tag less than or equal to `tag` and returns the removed
+    /// ranges in ascending order.
+    pub fn remove_up_to(&mut self, tag: u64) -> Vec<(u64, u64)> {
+        let mut removed = Vec::new();
+
+        while let Some((&start, &end)) = self.ranges.first_key_value() {
+            if start > tag {
+                break;
+            }
+            self.ranges.remove(&start);
+            if end > tag {
+                self.ranges.insert(tag + 1, end);
+                removed.push((start, tag));
+                self.len -= tag - start + 1;
+                break;
+            }
+            removed.push((start, end));
+            self.len -= end - start + 1;
+        }
+
+        removed
+    }
+
+    /// Iterates over the stored ranges in ascending order.
+    pub fn ranges(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.ranges.iter().map(|(&start, &end)| (start, end))
+    }
+}
+
+/// Converts inclusive tag ranges into a Ruby array of `Range` objects.
+pub fn ranges_to_array(
+    ruby: &Ruby,
+    ranges: impl IntoIterator<Item = (u64, u64)>,
+) -> std::result::Result<RArray, Error> {
+    let array = ruby.ary_new();
+    for (start, end) in ranges {
+        array.push(ruby.range_new(start, end, false)?)?;
+    }
+    Ok(array)
+}
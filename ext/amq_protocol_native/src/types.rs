@@ -1,7 +1,7 @@
 //! AMQP 0.9.1 encoding/decoding primitives
 
 use crate::error::{AmqpError, Result};
-use bytes::{BufMut, Bytes, BytesMut};
+use bytes::{BufMut, BytesMut};
 
 pub struct Encoder {
     buf: BytesMut,
@@ -35,6 +35,11 @@ impl Encoder {
         self.buf.put_u32(v);
     }
 
+    #[inline]
+    pub fn write_u64(&mut self, v: u64) {
+        self.buf.put_u64(v);
+    }
+
     #[inline]
     pub fn write_i64(&mut self, v: i64) {
         self.buf.put_i64(v);
@@ -64,15 +69,46 @@ impl Encoder {
         self.buf.put_slice(data);
     }
 
-    pub fn into_bytes(self) -> Bytes {
-        self.buf.freeze()
+    /// Writes a placeholder for a `u32` length prefix and returns its offset,
+    /// to be filled in by `patch_length` once the contents are written.
+    pub fn reserve_length(&mut self) -> usize {
+        let offset = self.buf.len();
+        self.buf.put_u32(0);
+        offset
+    }
+
+    /// Sets the length reserved at `offset` to the number of bytes written
+    /// after it.
+    pub fn patch_length(&mut self, offset: usize) {
+        let len = (self.buf.len() - offset - 4) as u32;
+        self.buf[offset..offset + 4].copy_from_slice(&len.to_be_bytes());
     }
 
-    #[allow(dead_code)]
     pub fn len(&self) -> usize {
         self.buf.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.capacity()
+    }
+
+    /// Overwrites the `u16` previously written at `offset`.
+    pub fn patch_u16(&mut self, offset: usize, v: u16) {
+        self.buf[offset..offset + 2].copy_from_slice(&v.to_be_bytes());
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.buf.truncate(len);
+    }
+
+    pub fn clear(&mut self) {
+        self.buf.clear();
+    }
+
     pub fn as_slice(&self) -> &[u8] {
         &self.buf
     }
@@ -94,6 +130,12 @@ impl<'a> Decoder<'a> {
         Self { data, pos: 0 }
     }
 
+    /// Starts reading at `pos`, keeping positions relative to the start of
+    /// `data`.
+    pub fn at(data: &'a [u8], pos: usize) -> Self {
+        Self { data, pos }
+    }
+
     #[inline]
     pub fn remaining(&self) -> usize {
         self.data.len() - self.pos
@@ -221,6 +263,19 @@ impl<'a> Decoder<'a> {
         Ok(s)
     }
 
+    pub fn read_bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        self.ensure(n)?;
+        let bytes = &self.data[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    pub fn skip(&mut self, n: usize) -> Result<()> {
+        self.ensure(n)?;
+        self.pos += n;
+        Ok(())
+    }
+
     pub fn read_short_string_bytes(&mut self) -> Result<&'a [u8]> {
         let len = self.read_u8()? as usize;
         self.ensure(len)?;
@@ -1,5 +1,6 @@
 # frozen_string_literal: true
 
+require "io/wait"
 require_relative "protocol/version"
 
 # Load the native extension
@@ -17,14 +18,15 @@ end
 module AMQ
   module Protocol
     # Re-export the METHODS hash for compatibility
-    # This will be populated by the native extension at load time
+    # This will be populated by the native extension at load time.
+    # Frozen, like all other constants, so that Ractors can read it.
     METHODS ||= begin
       Method.methods.each_with_object({}) do |klass, hash|
         hash[klass.index] = klass if klass.respond_to?(:index)
       end
     rescue
       {}
-    end
+    end.freeze
 
     class Method
       class << self
@@ -169,6 +171,12 @@ module AMQ
         @properties
       end
 
+      # Returns a BodyAccumulator sized for the body announced by this header,
+      # writing the body to sink if one is given
+      def body_accumulator(sink: nil)
+        BodyAccumulator.from_header(@payload, sink: sink)
+      end
+
       def decode_payload
         @decoded_payload ||= begin
           @klass_id, @weight = @payload.unpack("nn")
@@ -203,6 +211,54 @@ module AMQ
       end
     end
 
+    class Table
+      # A byte array ('x') field value, returned by Table.decode with
+      # byte_array_as: :object. Encodes back to a byte array rather than a
+      # string.
+      ByteArray = Struct.new(:bytes)
+
+      # A field value of a type the decoder does not support, returned by
+      # Table.decode with on_unknown_type: :raw. Encodes back to the same
+      # type and bytes.
+      RawValue = Struct.new(:type, :bytes)
+
+      class View
+        include Enumerable
+
+        def fetch(key, *default)
+          return self[key] if key?(key)
+          return yield(key) if block_given?
+          return default.first unless default.empty?
+
+          raise KeyError.new("key not found: #{key.inspect}", receiver: self, key: key)
+        end
+
+        def to_h
+          each_with_object({}) { |(key, value), hash| hash[key] = value }
+        end
+      end
+    end
+
+    class BodyStream
+      include Enumerable
+
+      # Yields the content header frame followed by each body frame
+      def each
+        return enum_for(:each) unless block_given?
+
+        yield header_frame
+        while (frame = next_frame)
+          yield frame
+        end
+        self
+      end
+
+      # Writes all frames to io, returning the number of bytes written
+      def write_to(io)
+        sum { |frame| io.write(frame) }
+      end
+    end
+
     # Frame class lookup
     Frame::CLASSES = {
       Frame::TYPES[:method] => MethodFrame,
@@ -214,10 +270,57 @@ module AMQ
     # Exception classes
     class Error < StandardError; end
 
+    # Extended into exceptions raised while decoding a table. offset is the
+    # byte at which decoding stopped, key_path the keys and array indices
+    # leading to the failing value (e.g. "x-death[0].queue"), or nil if the
+    # table itself is malformed.
+    module DecodingErrorLocation
+      attr_reader :offset, :key_path
+    end
+
+    # The keys and array indices leading to a table value that could not be
+    # encoded, as in DecodingErrorLocation, and the value itself. key_path is
+    # nil for a bad key in the table itself.
+    module EncodingErrorLocation
+      attr_reader :key_path, :value
+    end
+
+    # Raised when a table contains a value or key that cannot be encoded. An
+    # ArgumentError, as encoding errors were before it was introduced.
+    # Integers that do not fit in 64 bits raise a RangeError extended with
+    # EncodingErrorLocation instead.
+    class TableEncodingError < ArgumentError
+      include EncodingErrorLocation
+
+      def initialize(message = nil, key_path: nil, value: nil)
+        @key_path = key_path
+        @value = value
+        super(message)
+      end
+    end
+
+    # Errors reported by the server via channel.close (soft) or
+    # connection.close (hard). See Channel::Close.decode and
+    # Connection::Close.decode.
+    module ServerError
+      attr_reader :reply_code, :reply_text, :class_id, :method_id, :method_name
+
+      def initialize(message = nil, reply_code: nil, reply_text: nil, class_id: nil, method_id: nil, method_name: nil)
+        @reply_code = reply_code || (self.class::VALUE if self.class.const_defined?(:VALUE))
+        @reply_text = reply_text
+        @class_id = class_id
+        @method_id = method_id
+        @method_name = method_name
+        super(message || reply_text)
+      end
+    end
+
     class SoftError < Error
+      include ServerError
     end
 
     class HardError < Error
+      include ServerError
     end
 
     class FrameTypeError < Error
@@ -232,6 +335,29 @@ module AMQ
       end
     end
 
+    # Raised when the server replies to the protocol header with its own,
+    # meaning it does not support AMQP 0-9-1
+    class ProtocolVersionMismatch < Error
+      attr_reader :protocol_id, :major, :minor, :revision
+
+      def initialize(protocol_id, major, minor, revision = nil)
+        @protocol_id = protocol_id
+        @major = major
+        @minor = minor
+        @revision = revision
+        super("Server does not support AMQP 0-9-1, it supports AMQP #{server_version}")
+      end
+
+      def server_version
+        [major, minor, revision].compact.join("-")
+      end
+    end
+
+    # Raised by DeliveryTracker before the broker would close the channel with 406
+    class DeliveryTagError < Error; end
+    class UnknownDeliveryTagError < DeliveryTagError; end
+    class DoubleAckError < DeliveryTagError; end
+
     # Standard AMQP errors
     class ContentTooLarge < SoftError; VALUE = 311; end
     class NoRoute < SoftError; VALUE = 312; end
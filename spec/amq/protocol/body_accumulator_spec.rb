@@ -0,0 +1,131 @@
+# frozen_string_literal: true
+
+require "objspace"
+require "stringio"
+require "tempfile"
+
+RSpec.describe AMQ::Protocol::BodyAccumulator do
+  it "assembles body frame payloads into one binary string" do
+    accumulator = described_class.new(11)
+    accumulator << "hello" << " "
+    accumulator.append("world")
+
+    expect(accumulator).to be_complete
+    body = accumulator.take
+    expect(body).to eq("hello world")
+    expect(body.encoding).to eq(Encoding::BINARY)
+  end
+
+  it "tracks progress" do
+    accumulator = described_class.new(10)
+    accumulator << "abcd"
+
+    expect(accumulator.body_size).to eq(10)
+    expect(accumulator.bytesize).to eq(4)
+    expect(accumulator.remaining).to eq(6)
+    expect(accumulator).not_to be_complete
+  end
+
+  it "preallocates the whole body" do
+    body_size = 1024 * 1024
+    accumulator = described_class.new(body_size)
+    (body_size / 4096).times { accumulator << ("x" * 4096) }
+
+    body = accumulator.take
+    expect(body.bytesize).to eq(body_size)
+    expect(ObjectSpace.memsize_of(body)).to be < body_size + 4096
+  end
+
+  it "does not reserve memory for a huge declared body size up front" do
+    accumulator = described_class.new(2**40)
+    accumulator << "abc"
+
+    expect(accumulator.remaining).to eq(2**40 - 3)
+    expect(ObjectSpace.memsize_of(accumulator.take)).to be < 16 * 1024 * 1024
+  end
+
+  it "rejects more bytes than the content header declared" do
+    accumulator = described_class.new(4)
+    accumulator << "abc"
+
+    expect { accumulator << "de" }.to raise_error(AMQ::Protocol::FrameError, /declared 4/)
+    expect(accumulator.bytesize).to eq(3)
+  end
+
+  it "refuses to return an incomplete body" do
+    accumulator = described_class.new(4)
+    accumulator << "abc"
+
+    expect { accumulator.take }.to raise_error(AMQ::Protocol::FrameError, /3 of 4/)
+  end
+
+  it "returns the body only once" do
+    accumulator = described_class.new(0)
+
+    expect(accumulator.take).to eq("")
+    expect { accumulator.take }.to raise_error(RuntimeError, /already been taken/)
+    expect { accumulator << "" }.to raise_error(RuntimeError, /already been taken/)
+  end
+
+  it "is created from a content header payload" do
+    header = AMQ::Protocol::Basic.encode_properties(5, {content_type: "text/plain"})
+    accumulator = described_class.from_header(header)
+
+    expect(accumulator.body_size).to eq(5)
+    expect { described_class.from_header("\x00" * 8) }.to raise_error(RuntimeError, /too short/)
+  end
+
+  it "is created from a content header frame" do
+    payload = AMQ::Protocol::Basic.encode_properties(5, {content_type: "text/plain"})
+    header = AMQ::Protocol::HeaderFrame.new(payload, 1)
+    accumulator = header.body_accumulator
+    accumulator << AMQ::Protocol::BodyFrame.new("hello", 1).decode_payload
+
+    expect(accumulator.take).to eq("hello")
+  end
+
+  context "with a sink" do
+    let(:sink) { StringIO.new(+"".b) }
+
+    it "writes payloads to the sink as they arrive" do
+      accumulator = described_class.new(11, sink: sink)
+      accumulator << "hello "
+      expect(sink.string).to eq("hello ")
+
+      accumulator << "world"
+      expect(accumulator.take).to equal(sink)
+      expect(sink.string).to eq("hello world")
+    end
+
+    it "streams a content header's body into a file" do
+      Tempfile.create("body") do |file|
+        file.binmode
+        header = AMQ::Protocol::HeaderFrame.new(AMQ::Protocol::Basic.encode_properties(8192, {}), 1)
+        accumulator = header.body_accumulator(sink: file)
+        accumulator << ("a" * 4096) << ("b" * 4096)
+        accumulator.take.flush
+
+        expect(File.binread(file.path)).to eq(("a" * 4096) + ("b" * 4096))
+      end
+    end
+
+    it "verifies the bytes written against the body size" do
+      accumulator = described_class.new(6, sink: sink)
+      accumulator << "abc"
+
+      expect { accumulator.take }.to raise_error(AMQ::Protocol::FrameError, /3 of 6/)
+      expect { accumulator << "defg" }.to raise_error(AMQ::Protocol::FrameError, /declared 6/)
+    end
+
+    it "counts what the sink reports as written" do
+      short_writer = Class.new do
+        def write(data) = data.bytesize - 1
+      end
+      accumulator = described_class.new(4, sink: short_writer.new)
+      accumulator << "abcd"
+
+      expect(accumulator.bytesize).to eq(3)
+      expect { accumulator.take }.to raise_error(AMQ::Protocol::FrameError, /3 of 4/)
+    end
+  end
+end
@@ -0,0 +1,121 @@
+# frozen_string_literal: true
+
+require "stringio"
+require "tempfile"
+
+RSpec.describe AMQ::Protocol::BodyStream do
+  def decode(data)
+    frames, consumed = AMQ::Protocol::Frame.scan(data, 0)
+    expect(consumed).to eq(data.bytesize)
+    frames
+  end
+
+  let(:body) { Random.new(42).bytes(10_000) }
+
+  it "writes the content header with the declared size first" do
+    stream = described_class.new(1, 4096, body.bytesize, {content_type: "application/octet-stream"}, StringIO.new(body))
+
+    expect(stream.header_frame).to eq(
+      AMQ::Protocol::Frame.encode(
+        :headers,
+        AMQ::Protocol::Basic.encode_properties(body.bytesize, {content_type: "application/octet-stream"}),
+        1
+      )
+    )
+    expect(stream.bytes_sent).to eq(0)
+  end
+
+  it "splits an IO into body frames sized to frame_max" do
+    stream = described_class.new(1, 4096, body.bytesize, {}, StringIO.new(body))
+    frames = decode(stream.to_a.join)
+
+    expect(frames.map(&:first)).to eq(%i[headers body body body])
+    expect(frames.drop(1).map { |_, _, payload| payload.bytesize }).to eq([4088, 4088, 1824])
+    expect(frames.drop(1).map(&:last).join).to eq(body)
+    expect(stream).to be_complete
+  end
+
+  it "produces frames lazily" do
+    io = StringIO.new(body)
+    stream = described_class.new(1, 4096, body.bytesize, {}, io)
+
+    stream.next_frame
+    expect(io.pos).to eq(4088)
+    expect(stream.bytes_sent).to eq(4088)
+  end
+
+  it "reads files" do
+    Tempfile.create("body") do |file|
+      file.binmode
+      file.write(body)
+      file.rewind
+
+      out = StringIO.new(+"".b)
+      stream = described_class.new(3, 131_072, file.size, {}, file)
+      expect(stream.write_to(out)).to eq(out.string.bytesize)
+
+      frames = decode(out.string)
+      expect(frames.map { |type, channel, _| [type, channel] }).to eq([[:headers, 3], [:body, 3]])
+      expect(frames.last.last).to eq(body)
+    end
+  end
+
+  it "regroups Enumerator chunks into full frames" do
+    chunks = body.bytes.each_slice(1000).map { |bytes| bytes.pack("C*") }
+    stream = described_class.new(1, 4096, body.bytesize, {}, chunks.each)
+    frames = decode(stream.to_a.join)
+
+    expect(frames.drop(1).map { |_, _, payload| payload.bytesize }).to eq([4088, 4088, 1824])
+    expect(frames.drop(1).map(&:last).join).to eq(body)
+  end
+
+  it "accepts any Enumerable" do
+    stream = described_class.new(1, 0, 6, {}, ["abc", "", "def"])
+
+    expect(decode(stream.to_a.join).last).to eq([:body, 1, "abcdef"])
+  end
+
+  it "sends no body frames for an empty body" do
+    stream = described_class.new(1, 4096, 0, {}, StringIO.new(""))
+
+    expect(stream.to_a).to eq([stream.header_frame])
+  end
+
+  it "raises FrameError when the source ends early" do
+    stream = described_class.new(1, 4096, 10, {}, StringIO.new("short"))
+
+    expect(stream.next_frame).not_to be_nil
+    expect { stream.next_frame }.to raise_error(AMQ::Protocol::FrameError, /5 of 10/)
+  end
+
+  it "raises FrameError when an Enumerator yields more than declared" do
+    stream = described_class.new(1, 4096, 3, {}, ["ab", "cd"].each)
+
+    expect { stream.next_frame }.to raise_error(AMQ::Protocol::FrameError, /declared 3/)
+  end
+
+  it "splits chunks an IO returns beyond the length asked for into frames that fit frame_max" do
+    remaining = body.dup
+    io = Object.new
+    io.define_singleton_method(:read) { |_length| remaining.slice!(0, 6000) }
+
+    stream = described_class.new(1, 4096, body.bytesize, {}, io)
+    frames = decode(stream.to_a.join)
+
+    expect(frames.drop(1).map { |_, _, payload| payload.bytesize }).to eq([4088, 1912, 4000])
+    expect(frames.drop(1).map(&:last).join).to eq(body)
+    expect(stream).to be_complete
+  end
+
+  it "raises FrameError when an IO returns more than declared" do
+    io = Object.new
+    io.define_singleton_method(:read) { |_length| "abcdef" }
+    stream = described_class.new(1, 4096, 3, {}, io)
+
+    expect { stream.next_frame }.to raise_error(AMQ::Protocol::FrameError, /declared 3/)
+  end
+
+  it "validates frame_max" do
+    expect { described_class.new(1, 100, 0, {}, []) }.to raise_error(ArgumentError)
+  end
+end
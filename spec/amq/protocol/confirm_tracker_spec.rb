@@ -0,0 +1,75 @@
+# frozen_string_literal: true
+
+RSpec.describe AMQ::Protocol::ConfirmTracker do
+  subject(:tracker) { described_class.new }
+
+  describe "#publish" do
+    it "assigns sequence numbers starting at 1" do
+      expect(tracker.next_publish_seq_no).to eq(1)
+      expect(tracker.publish).to eq(1)
+      expect(tracker.publish).to eq(2)
+      expect(tracker.next_publish_seq_no).to eq(3)
+    end
+
+    it "tracks published sequence numbers as outstanding" do
+      3.times { tracker.publish }
+
+      expect(tracker.outstanding_count).to eq(3)
+      expect(tracker.outstanding).to eq([1..3])
+      expect(tracker.outstanding?(2)).to eq(true)
+      expect(tracker.all_confirmed?).to eq(false)
+    end
+  end
+
+  describe "#ack" do
+    before { 5.times { tracker.publish } }
+
+    it "confirms a single publish" do
+      expect(tracker.ack(3, false)).to eq([3..3])
+      expect(tracker.outstanding).to eq([1..2, 4..5])
+      expect(tracker.outstanding?(3)).to eq(false)
+    end
+
+    it "confirms every outstanding publish up to the tag with multiple=true" do
+      tracker.ack(2, false)
+
+      expect(tracker.ack(4, true)).to eq([1..1, 3..4])
+      expect(tracker.outstanding).to eq([5..5])
+      expect(tracker.outstanding_count).to eq(1)
+    end
+
+    it "ignores tags that are not outstanding" do
+      tracker.ack(2, false)
+
+      expect(tracker.ack(2, false)).to eq([])
+      expect(tracker.ack(42, false)).to eq([])
+      expect(tracker.outstanding_count).to eq(4)
+    end
+
+    it "reports when everything has been confirmed" do
+      tracker.ack(5, true)
+
+      expect(tracker.all_confirmed?).to eq(true)
+      expect(tracker.outstanding).to eq([])
+    end
+  end
+
+  describe "#nack" do
+    before { 5.times { tracker.publish } }
+
+    it "reports nacked publishes and stops tracking them" do
+      expect(tracker.nack(2, true)).to eq([1..2])
+      expect(tracker.nack(4, false)).to eq([4..4])
+      expect(tracker.outstanding).to eq([3..3, 5..5])
+    end
+  end
+
+  it "handles a large number of in-flight publishes" do
+    100_000.times { tracker.publish }
+
+    tracker.ack(50_000, false)
+    expect(tracker.outstanding).to eq([1..49_999, 50_001..100_000])
+    expect(tracker.ack(99_999, true)).to eq([1..49_999, 50_001..99_999])
+    expect(tracker.outstanding_count).to eq(1)
+  end
+end
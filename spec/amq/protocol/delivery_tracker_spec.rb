@@ -0,0 +1,90 @@
+# frozen_string_literal: true
+
+RSpec.describe AMQ::Protocol::DeliveryTracker do
+  subject(:tracker) { described_class.new(3) }
+
+  # Returns [class_id, method_id, delivery_tag, flags] for each method frame
+  def decode(frames)
+    frames.map do |frame|
+      type, channel, size = AMQ::Protocol::Frame.decode_header(frame[0, 7])
+      expect(type).to eq(:method)
+      expect(channel).to eq(3)
+      frame[7, size].unpack("nnQ>C")
+    end
+  end
+
+  before { (1..10).each { |tag| tracker.deliver(tag) } }
+
+  it "tracks delivered tags as unsettled" do
+    expect(tracker.channel).to eq(3)
+    expect(tracker.unsettled_count).to eq(10)
+    expect(tracker.pending_count).to eq(0)
+  end
+
+  it "coalesces contiguous acks into a single multiple ack" do
+    [3, 1, 2, 4].each { |tag| tracker.ack(tag) }
+
+    expect(tracker.pending_count).to eq(4)
+    expect(decode(tracker.flush)).to eq([[60, 80, 4, 1]])
+    expect(tracker.pending_count).to eq(0)
+    expect(tracker.unsettled_count).to eq(6)
+  end
+
+  it "switches between acks and nacks along the settled prefix" do
+    tracker.ack(1)
+    tracker.ack(2)
+    tracker.nack(3, true)
+    tracker.nack(4, true)
+    tracker.nack(5, false)
+
+    expect(decode(tracker.flush)).to eq([
+      [60, 80, 2, 1],
+      [60, 120, 4, 3],
+      [60, 120, 5, 2]
+    ])
+  end
+
+  it "sends decisions after an unsettled delivery individually" do
+    tracker.ack(1)
+    tracker.ack(3)
+    tracker.ack(4)
+
+    expect(decode(tracker.flush)).to eq([
+      [60, 80, 1, 0],
+      [60, 80, 3, 0],
+      [60, 80, 4, 0]
+    ])
+  end
+
+  it "does not cover previously flushed tags twice" do
+    tracker.ack(1)
+    tracker.flush
+    tracker.ack(2)
+    tracker.ack(3)
+
+    expect(decode(tracker.flush)).to eq([[60, 80, 3, 1]])
+  end
+
+  it "returns no frames when nothing is pending" do
+    expect(tracker.flush).to eq([])
+  end
+
+  it "raises on double ack" do
+    tracker.ack(5)
+
+    expect { tracker.ack(5) }.to raise_error(AMQ::Protocol::DoubleAckError)
+    expect { tracker.nack(5, false) }.to raise_error(AMQ::Protocol::DoubleAckError)
+
+    tracker.flush
+    expect { tracker.ack(5) }.to raise_error(AMQ::Protocol::DoubleAckError)
+  end
+
+  it "raises on unknown delivery tags" do
+    expect { tracker.ack(11) }.to raise_error(AMQ::Protocol::UnknownDeliveryTagError)
+    expect { tracker.ack(0) }.to raise_error(AMQ::Protocol::UnknownDeliveryTagError)
+  end
+
+  it "rejects delivery tags that go backwards" do
+    expect { tracker.deliver(10) }.to raise_error(ArgumentError)
+  end
+end
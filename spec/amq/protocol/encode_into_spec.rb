@@ -0,0 +1,128 @@
+# frozen_string_literal: true
+
+RSpec.describe "encode_into" do
+  let(:payload) { "test payload".b }
+
+  describe AMQ::Protocol::Frame do
+    it "appends an encoded frame to a String" do
+      buffer = +"prefix".b
+      result = described_class.encode_into(buffer, :method, payload, 1)
+
+      expect(result).to equal(buffer)
+      expect(buffer).to eq("prefix".b + described_class.encode(:method, payload, 1))
+    end
+
+    it "appends several frames to a WriteBuffer" do
+      buffer = AMQ::Protocol::WriteBuffer.new
+      described_class.encode_into(buffer, :method, payload, 1)
+      described_class.encode_into(buffer, 8, "", 0)
+
+      expected = described_class.encode(:method, payload, 1) + described_class.encode(8, "", 0)
+      expect(buffer.bytesize).to eq(expected.bytesize)
+      expect(buffer.to_s).to eq(expected)
+    end
+
+    it "raises on a frozen String" do
+      expect {
+        described_class.encode_into("".b.freeze, :method, payload, 1)
+      }.to raise_error(FrozenError)
+    end
+
+    it "raises on a String that is not ASCII-8BIT" do
+      buffer = +"prefix"
+      expect {
+        described_class.encode_into(buffer, :method, payload, 1)
+      }.to raise_error(ArgumentError, /ASCII-8BIT/)
+      expect(buffer).to eq("prefix")
+    end
+
+    it "raises on an unsupported buffer" do
+      expect {
+        described_class.encode_into([], :method, payload, 1)
+      }.to raise_error(TypeError)
+    end
+
+    it "leaves the buffer untouched when the channel is invalid" do
+      buffer = +"".b
+      expect {
+        described_class.encode_into(buffer, :method, payload, 70_000)
+      }.to raise_error(RuntimeError)
+      expect(buffer).to be_empty
+    end
+  end
+
+  describe AMQ::Protocol::Table do
+    let(:table) { { "x-message-ttl" => 60_000, "nested" => { "key" => "value" } } }
+
+    it "appends the same bytes as .encode" do
+      buffer = +"".b
+      described_class.encode_into(buffer, table)
+
+      expect(buffer).to eq(described_class.encode(table))
+    end
+
+    it "leaves a WriteBuffer untouched when encoding fails" do
+      buffer = AMQ::Protocol::WriteBuffer.new
+      described_class.encode_into(buffer, { "a" => 1 })
+
+      expect {
+        described_class.encode_into(buffer, { "b" => Object.new })
+      }.to raise_error(ArgumentError)
+      expect(buffer.to_s).to eq(described_class.encode({ "a" => 1 }))
+    end
+
+    it "raises when a to_amqp_field hook uses the WriteBuffer being written to" do
+      buffer = AMQ::Protocol::WriteBuffer.new
+      field = Object.new
+      field.define_singleton_method(:to_amqp_field) { buffer.bytesize }
+
+      expect {
+        described_class.encode_into(buffer, { "size" => field })
+      }.to raise_error(RuntimeError, /already being written to/)
+      expect(buffer).to be_empty
+    end
+  end
+
+  describe "method classes" do
+    it "appends the same bytes as .encode" do
+      args = ["my-queue", false, true, false, false, false, { "x-queue-type" => "quorum" }]
+      buffer = +"".b
+      AMQ::Protocol::Queue::Declare.encode_into(buffer, *args)
+
+      expect(buffer).to eq(AMQ::Protocol::Queue::Declare.encode(*args))
+    end
+
+    it "supports methods without arguments" do
+      buffer = AMQ::Protocol::WriteBuffer.new
+      AMQ::Protocol::Tx::Select.encode_into(buffer)
+      AMQ::Protocol::Basic::Ack.encode_into(buffer, 42, true)
+
+      expect(buffer.to_s).to eq(
+        AMQ::Protocol::Tx::Select.encode + AMQ::Protocol::Basic::Ack.encode(42, true)
+      )
+    end
+  end
+
+  describe AMQ::Protocol::WriteBuffer do
+    subject(:buffer) { described_class.new }
+
+    it "starts empty" do
+      expect(buffer).to be_empty
+      expect(buffer.bytesize).to eq(0)
+    end
+
+    it "returns its contents and empties itself on #take" do
+      AMQ::Protocol::Frame.encode_into(buffer, :heartbeat, "", 0)
+
+      expect(buffer.take).to eq(AMQ::Protocol::Frame.encode(:heartbeat, "", 0))
+      expect(buffer).to be_empty
+    end
+
+    it "discards its contents on #clear" do
+      AMQ::Protocol::Frame.encode_into(buffer, :heartbeat, "", 0)
+      buffer.clear
+
+      expect(buffer.bytesize).to eq(0)
+    end
+  end
+end
@@ -0,0 +1,204 @@
+# frozen_string_literal: true
+
+require "socket"
+
+RSpec.describe AMQ::Protocol::FrameIO do
+  let(:sockets) { UNIXSocket.pair }
+  let(:client) { described_class.new(sockets[0], 4096) }
+  let(:server) { described_class.new(sockets[1], 4096) }
+
+  after { sockets.each(&:close) }
+
+  it "exposes the wrapped IO and frame_max" do
+    expect(client.io).to equal(sockets[0])
+    expect(client.frame_max).to eq(4096)
+
+    client.frame_max = 131_072
+    expect(client.frame_max).to eq(131_072)
+  end
+
+  it "writes and reads whole frames" do
+    payload = AMQ::Protocol::Basic::Publish.encode("", "queue", false, false)
+
+    expect(client.write_frame(:method, payload, 1)).to eq(payload.bytesize + 8)
+    client.write_frame(:heartbeat, "", 0)
+
+    expect(server.read_frame).to eq([:method, 1, payload])
+    expect(server.read_frame).to eq([:heartbeat, 0, ""])
+    expect(server.buffered_bytes).to eq(0)
+  end
+
+  it "writes already encoded frames" do
+    batch = AMQ::Protocol::PublishBatch.new(1, 4096)
+    batch.add("", "a", {}, "first").add("", "b", {}, "second")
+    client.write(batch.take)
+
+    frames = 6.times.map { server.read_frame }
+    expect(frames.map(&:first)).to eq(%i[method headers body method headers body])
+    expect(frames.values_at(2, 5).map(&:last)).to eq(["first", "second"])
+  end
+
+  it "writes the protocol header" do
+    client.write_protocol_header
+    expect(sockets[1].read(8)).to eq("AMQP\x00\x00\x09\x01".b)
+  end
+
+  it "assembles frames that arrive in pieces" do
+    frame = AMQ::Protocol::Frame.encode(:body, "x" * 1000, 3)
+    writer = Thread.new do
+      frame.each_char.each_slice(97) do |chunk|
+        sockets[0].write(chunk.join)
+        sleep 0.001
+      end
+    end
+
+    expect(server.read_frame).to eq([:body, 3, "x" * 1000])
+    writer.join
+  end
+
+  it "returns nil at the end of the stream between frames" do
+    client.write_frame(:heartbeat, "", 0)
+    sockets[0].close_write
+
+    expect(server.read_frame).to eq([:heartbeat, 0, ""])
+    expect(server.read_frame).to be_nil
+  end
+
+  it "raises EOFError when the stream ends in the middle of a frame" do
+    sockets[0].write(AMQ::Protocol::Frame.encode(:heartbeat, "", 0)[0, 5])
+    sockets[0].close_write
+
+    expect { server.read_frame }.to raise_error(EOFError)
+  end
+
+  it "enforces frame_max in both directions" do
+    expect { client.write_frame(:body, "x" * 5000, 1) }.to raise_error(AMQ::Protocol::FrameError)
+
+    sockets[0].write(AMQ::Protocol::Frame.encode(:body, "x" * 5000, 1))
+    expect { server.read_frame }.to raise_error(AMQ::Protocol::FrameError)
+  end
+
+  it "writes payloads larger than the socket buffer" do
+    client.frame_max = 0
+    server.frame_max = 0
+    payload = Random.new(7).bytes(4 * 1024 * 1024)
+
+    writer = Thread.new { client.write_frame(:body, payload, 1) }
+    expect(server.read_frame).to eq([:body, 1, payload])
+    expect(writer.value).to eq(payload.bytesize + 8)
+  end
+
+  context "with a fiber scheduler", if: RUBY_VERSION >= "3.1" do
+    # Just enough of a scheduler to run fibers that wait on IO
+    let(:scheduler_class) do
+      Class.new do
+        def initialize
+          @waiting = {}
+          @ready = []
+        end
+
+        def io_wait(io, events, _timeout)
+          @waiting[Fiber.current] = [io, events]
+          Fiber.yield
+          events
+        end
+
+        def fiber(&block)
+          fiber = Fiber.new(blocking: false, &block)
+          fiber.resume
+          fiber
+        end
+
+        def block(_blocker, _timeout = nil)
+          Fiber.yield
+          true
+        end
+
+        def unblock(_blocker, fiber)
+          @ready << fiber
+        end
+
+        def close
+          until @waiting.empty? && @ready.empty?
+            @ready.shift.resume until @ready.empty?
+            next if @waiting.empty?
+
+            readers = @waiting.select { |_, (_, events)| events & IO::READABLE != 0 }
+            writers = @waiting.select { |_, (_, events)| events & IO::WRITABLE != 0 }
+            readable, writable = IO.select(readers.values.map(&:first), writers.values.map(&:first))
+
+            @waiting.select { |_, (io, _)| readable.include?(io) || writable.include?(io) }.each_key do |fiber|
+              @waiting.delete(fiber)
+              fiber.resume
+            end
+          end
+        end
+
+        def kernel_sleep(*) = raise(NotImplementedError)
+      end
+    end
+
+    it "lets other fibers run while waiting for a frame" do
+      events = []
+
+      Thread.new do
+        Fiber.set_scheduler(scheduler_class.new)
+
+        Fiber.schedule do
+          events << :reading
+          events << server.read_frame
+        end
+        Fiber.schedule do
+          events << :writing
+          client.write_frame(:heartbeat, "", 0)
+        end
+      end.join
+
+      expect(events).to eq([:reading, :writing, [:heartbeat, 0, ""]])
+    end
+
+    it "does not interleave frames written by fibers that wait on a full socket" do
+      client.frame_max = 0
+      server.frame_max = 0
+      # Larger than the socket buffer, so each write waits part way through
+      payloads = { 1 => "a" * (2 * 1024 * 1024), 2 => "b" * (2 * 1024 * 1024) }
+      reader = Thread.new { 2.times.map { server.read_frame } }
+
+      Thread.new do
+        Fiber.set_scheduler(scheduler_class.new)
+
+        payloads.each do |channel, payload|
+          Fiber.schedule { client.write_frame(:body, payload, channel) }
+        end
+      end.join
+
+      expect(reader.value).to contain_exactly([:body, 1, payloads[1]], [:body, 2, payloads[2]])
+    end
+
+    it "reads each frame whole in one of several fibers waiting on the socket" do
+      frames = [
+        AMQ::Protocol::Frame.encode(:body, "a" * 3000, 1),
+        AMQ::Protocol::Frame.encode(:body, "b" * 3000, 2)
+      ].join
+      writer = Thread.new do
+        frames.each_char.each_slice(997) do |chunk|
+          sockets[0].write(chunk.join)
+          sleep 0.001
+        end
+      end
+      results = []
+
+      Thread.new do
+        Fiber.set_scheduler(scheduler_class.new)
+
+        2.times do |reader|
+          Fiber.schedule { results << [reader, server.read_frame] }
+        end
+      end.join
+      writer.join
+
+      expect(results).to eq([[0, [:body, 1, "a" * 3000]], [1, [:body, 2, "b" * 3000]]])
+      expect(server.buffered_bytes).to eq(0)
+    end
+  end
+end
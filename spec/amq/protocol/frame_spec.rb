@@ -53,6 +53,145 @@ RSpec.describe AMQ::Protocol::Frame do
     end
   end
 
+  describe "large payloads" do
+    let(:payload) { Random.new(42).bytes(1024 * 1024) }
+
+    it "encodes a frame the same way as small payloads" do
+      result = described_class.encode(:body, payload, 5)
+
+      expect(result.bytesize).to eq(payload.bytesize + 8)
+      expect(result[0, 7].unpack("CnN")).to eq([3, 5, payload.bytesize])
+      expect(result[7, payload.bytesize]).to eq(payload)
+      expect(result[-1].ord).to eq(0xCE)
+    end
+
+    it "leaves the payload modifiable afterwards" do
+      payload = +"x" * (256 * 1024)
+      described_class.encode(:body, payload, 5)
+
+      expect { payload << "y" }.not_to raise_error
+    end
+
+    it "encodes the same String from several threads at once" do
+      payload = +"x" * (1024 * 1024)
+
+      results = 8.times.map do |channel|
+        Thread.new { 20.times.map { described_class.encode_body(payload, channel + 1, 131_072) } }
+      end.map(&:value)
+
+      results.each_with_index do |frames, channel|
+        expect(frames.uniq).to eq([described_class.encode_body(payload, channel + 1, 131_072)])
+      end
+      expect(payload).not_to be_frozen
+    end
+  end
+
+  describe ".encode_body" do
+    it "splits a body into frames that fit frame_max" do
+      body = Random.new(1).bytes(10_000)
+      result = described_class.encode_body(body, 2, 4096)
+
+      frames, consumed = described_class.scan(result, 4096)
+      expect(consumed).to eq(result.bytesize)
+      expect(frames.map { |type, channel, _| [type, channel] }).to eq([[:body, 2]] * 3)
+      expect(frames.map { |_, _, payload| payload.bytesize }).to eq([4088, 4088, 1824])
+      expect(frames.map(&:last).join).to eq(body)
+    end
+
+    it "uses a single frame when frame_max is 0" do
+      expect(described_class.encode_body("body", 1, 0)).to eq(described_class.encode(:body, "body", 1))
+    end
+
+    it "returns an empty String for an empty body" do
+      expect(described_class.encode_body("", 1, 4096)).to eq("")
+    end
+
+    it "rejects a frame_max below the protocol minimum" do
+      [1, 8, 4095].each do |frame_max|
+        expect { described_class.encode_body("body", 1, frame_max) }
+          .to raise_error(ArgumentError, "frame_max must be 0 or at least 4096 but was #{frame_max}")
+      end
+    end
+  end
+
+  describe ".scan" do
+    let(:frames) do
+      described_class.encode(:method, "abc", 1) + described_class.encode(:heartbeat, "", 0)
+    end
+
+    it "returns complete frames and the number of bytes they span" do
+      result, consumed = described_class.scan(frames + "\x03\x00".b, 0)
+
+      expect(result).to eq([[:method, 1, "abc"], [:heartbeat, 0, ""]])
+      expect(consumed).to eq(frames.bytesize)
+    end
+
+    it "stops before a frame missing its end octet" do
+      result, consumed = described_class.scan(frames[0..-2], 0)
+
+      expect(result).to eq([[:method, 1, "abc"]])
+      expect(consumed).to eq(11)
+    end
+
+    it "raises FrameError for a corrupt frame end" do
+      corrupt = frames.dup
+      corrupt.setbyte(10, 0)
+
+      expect { described_class.scan(corrupt, 0) }.to raise_error(AMQ::Protocol::FrameError)
+    end
+
+    it "raises FrameError for frames larger than frame_max" do
+      large = described_class.encode(:body, "x" * 5000, 1)
+
+      expect { described_class.scan(large, 4096) }.to raise_error(AMQ::Protocol::FrameError, /frame_max/)
+    end
+
+    it "reports the offset of the error within the data" do
+      corrupt = frames.dup
+      corrupt.setbyte(11, 9)
+
+      expect { described_class.scan(corrupt, 0) }.to raise_error(ArgumentError, /at byte 11/) do |error|
+        expect(error).to be_a(AMQ::Protocol::DecodingErrorLocation)
+        expect(error.offset).to eq(11)
+        expect(error.key_path).to be_nil
+      end
+
+      corrupt = frames.dup
+      corrupt.setbyte(frames.bytesize - 1, 0)
+      expect { described_class.scan(corrupt, 0) }
+        .to raise_error(AMQ::Protocol::FrameError, "Frame end octet missing at byte #{frames.bytesize - 1}") do |error|
+          expect(error.offset).to eq(frames.bytesize - 1)
+        end
+    end
+  end
+
+  describe ".validate" do
+    it "accepts a single well-formed frame" do
+      expect(described_class.validate(described_class.encode(:method, "abc", 1), 4096)).to eq(true)
+    end
+
+    it "rejects incomplete frames and trailing bytes" do
+      frame = described_class.encode(:method, "abc", 1)
+
+      expect { described_class.validate(frame[0..-2], 0) }.to raise_error(RuntimeError)
+      expect { described_class.validate(frame + "x", 0) }.to raise_error(RuntimeError, /trailing/)
+    end
+
+    it "reports the offset of the error within the frame" do
+      frame = described_class.encode(:method, "abc", 1)
+
+      expect { described_class.validate(frame[0..-2], 0) }.to raise_error(RuntimeError) do |error|
+        expect(error.offset).to eq(10)
+      end
+      expect { described_class.validate(frame + "x", 0) }.to raise_error(RuntimeError) do |error|
+        expect(error.offset).to eq(11)
+      end
+      expect { described_class.validate(frame, 8) }.to raise_error(AMQ::Protocol::FrameError) do |error|
+        expect(error.offset).to eq(0)
+      end
+    end
+  end
+
   describe ".encode_to_array" do
     it "returns an array of components" do
       payload = "test"
@@ -83,6 +222,14 @@ RSpec.describe AMQ::Protocol::Frame do
         described_class.decode_header("")
       }.to raise_error(RuntimeError)
     end
+
+    it "reports errors at byte 0" do
+      header = [9, 1, 4].pack("CnN")
+
+      expect { described_class.decode_header(header) }.to raise_error(ArgumentError, /at byte 0/) do |error|
+        expect(error.offset).to eq(0)
+      end
+    end
   end
 end
 
@@ -0,0 +1,70 @@
+# frozen_string_literal: true
+
+RSpec.describe AMQ::Protocol::HeartbeatMonitor do
+  describe ".negotiate" do
+    it "uses the smaller of two non-zero values" do
+      expect(described_class.negotiate(60, 30)).to eq(30)
+      expect(described_class.negotiate(10, 60)).to eq(10)
+    end
+
+    it "uses the other value when one side proposes 0" do
+      expect(described_class.negotiate(0, 60)).to eq(60)
+      expect(described_class.negotiate(60, 0)).to eq(60)
+      expect(described_class.negotiate(0, 0)).to eq(0)
+    end
+  end
+
+  context "with a negotiated heartbeat" do
+    subject(:monitor) { described_class.new(10, 100.0) }
+
+    it "is enabled" do
+      expect(monitor.heartbeat).to eq(10)
+      expect(monitor.enabled?).to eq(true)
+    end
+
+    it "requires a heartbeat after half the interval without outbound traffic" do
+      expect(monitor.next_heartbeat_at).to eq(105.0)
+      expect(monitor.heartbeat_due?(104.9)).to eq(false)
+      expect(monitor.heartbeat_due?(105.0)).to eq(true)
+    end
+
+    it "postpones the heartbeat when bytes are sent" do
+      monitor.sent(103.0)
+
+      expect(monitor.next_heartbeat_at).to eq(108.0)
+      expect(monitor.heartbeat_due?(105.0)).to eq(false)
+    end
+
+    it "considers the peer dead after two missed intervals" do
+      expect(monitor.peer_deadline).to eq(120.0)
+      expect(monitor.peer_dead?(120.0)).to eq(false)
+      expect(monitor.peer_dead?(120.5)).to eq(true)
+    end
+
+    it "keeps the peer alive when bytes are received" do
+      monitor.received(115.0)
+
+      expect(monitor.peer_deadline).to eq(135.0)
+      expect(monitor.peer_dead?(125.0)).to eq(false)
+    end
+
+    it "ignores timestamps older than the last recorded activity" do
+      monitor.received(115.0)
+      monitor.received(110.0)
+
+      expect(monitor.peer_deadline).to eq(135.0)
+    end
+  end
+
+  context "with heartbeats disabled" do
+    subject(:monitor) { described_class.new(0, 100.0) }
+
+    it "never requires heartbeats or declares the peer dead" do
+      expect(monitor.enabled?).to eq(false)
+      expect(monitor.next_heartbeat_at).to be_nil
+      expect(monitor.peer_deadline).to be_nil
+      expect(monitor.heartbeat_due?(1_000_000.0)).to eq(false)
+      expect(monitor.peer_dead?(1_000_000.0)).to eq(false)
+    end
+  end
+end
@@ -42,6 +42,39 @@ RSpec.describe "AMQ::Protocol method classes" do
         expect(result).to be_a(String)
         expect(result[0, 4].unpack("nn")).to eq([10, 50])
       end
+
+      it "decodes a hard error into the matching error class" do
+        payload = AMQ::Protocol::Connection::Close.encode(
+          530, "NOT_ALLOWED - access to vhost 'x' refused", 10, 40
+        )[4..]
+
+        error = AMQ::Protocol::Connection::Close.decode(payload)
+
+        expect(error).to be_a(AMQ::Protocol::NotAllowed)
+        expect(error).to be_a(AMQ::Protocol::HardError)
+        expect(error.reply_code).to eq(530)
+        expect(error.reply_text).to eq("NOT_ALLOWED - access to vhost 'x' refused")
+        expect(error.message).to eq(error.reply_text)
+        expect(error.class_id).to eq(10)
+        expect(error.method_id).to eq(40)
+        expect(error.method_name).to eq("connection.open")
+      end
+
+      it "falls back to HardError for unknown reply codes" do
+        payload = AMQ::Protocol::Connection::Close.encode(599, "UNKNOWN", 0, 0)[4..]
+
+        error = AMQ::Protocol::Connection::Close.decode(payload)
+
+        expect(error.class).to eq(AMQ::Protocol::HardError)
+        expect(error.reply_code).to eq(599)
+        expect(error.method_name).to be_nil
+      end
+
+      it "decodes a normal shutdown to nil" do
+        payload = AMQ::Protocol::Connection::Close.encode(200, "Normal shutdown", 0, 0)[4..]
+
+        expect(AMQ::Protocol::Connection::Close.decode(payload)).to be_nil
+      end
     end
 
     describe "::CloseOk" do
@@ -71,6 +104,36 @@ RSpec.describe "AMQ::Protocol method classes" do
         expect(result).to be_a(String)
         expect(result[0, 4].unpack("nn")).to eq([20, 40])
       end
+
+      it "decodes a soft error into the matching error class" do
+        payload = AMQ::Protocol::Channel::Close.encode(
+          404, "NOT_FOUND - no queue 'missing' in vhost '/'", 50, 10
+        )[4..]
+
+        error = AMQ::Protocol::Channel::Close.decode(payload)
+
+        expect(error).to be_a(AMQ::Protocol::NotFound)
+        expect(error).to be_a(AMQ::Protocol::SoftError)
+        expect(error.reply_code).to eq(404)
+        expect(error.reply_text).to eq("NOT_FOUND - no queue 'missing' in vhost '/'")
+        expect(error.method_name).to eq("queue.declare")
+        expect { raise error }.to raise_error(AMQ::Protocol::NotFound)
+      end
+
+      it "maps precondition failures" do
+        payload = AMQ::Protocol::Channel::Close.encode(406, "PRECONDITION_FAILED", 60, 80)[4..]
+
+        error = AMQ::Protocol::Channel::Close.decode(payload)
+
+        expect(error).to be_a(AMQ::Protocol::PreconditionFailed)
+        expect(error.method_name).to eq("basic.ack")
+      end
+
+      it "falls back to SoftError for unknown reply codes" do
+        payload = AMQ::Protocol::Channel::Close.encode(499, "UNKNOWN", 0, 0)[4..]
+
+        expect(AMQ::Protocol::Channel::Close.decode(payload).class).to eq(AMQ::Protocol::SoftError)
+      end
     end
 
     describe "::CloseOk" do
@@ -0,0 +1,81 @@
+# frozen_string_literal: true
+
+RSpec.describe "AMQ::Protocol::Basic.encode_properties" do
+  def encode(body_size, properties)
+    AMQ::Protocol::Basic.encode_properties(body_size, properties)
+  end
+
+  it "encodes the class id, weight and body size" do
+    result = encode(1234, {})
+
+    expect(result.encoding).to eq(Encoding::BINARY)
+    expect(result.unpack("nnQ>n")).to eq([60, 0, 1234, 0])
+    expect(result.bytesize).to eq(14)
+  end
+
+  it "sets a flag bit for each present property in wire order" do
+    result = encode(0, { content_type: "text/plain", delivery_mode: 2, app_id: "test" })
+    flags = result[12, 2].unpack1("n")
+
+    expect(flags).to eq((1 << 15) | (1 << 12) | (1 << 3))
+    expect(result[14..]).to eq("\x0Atext/plain\x02\x04test".b)
+  end
+
+  it "leaves out nil properties" do
+    expect(encode(0, { content_type: nil })).to eq(encode(0, {}))
+  end
+
+  it "accepts String keys" do
+    expect(encode(0, { "content_type" => "text/plain", "delivery_mode" => 2 }))
+      .to eq(encode(0, { content_type: "text/plain", delivery_mode: 2 }))
+  end
+
+  it "rejects unknown and repeated property names" do
+    expect { encode(0, { unknown: "x" }) }.to raise_error(ArgumentError, "Unknown property :unknown")
+    expect { encode(0, { "contentType" => "x" }) }.to raise_error(ArgumentError, /Unknown property "contentType"/)
+    expect { encode(0, { 1 => "x" }) }.to raise_error(ArgumentError, /Invalid property name 1/)
+    expect { encode(0, { :app_id => "a", "app_id" => "b" }) }.to raise_error(ArgumentError, /app_id given twice/)
+  end
+
+  it "encodes headers as a field table" do
+    headers = { "x-retry" => 1 }
+    result = encode(0, { headers: headers })
+
+    expect(result[14..]).to eq(AMQ::Protocol::Table.encode(headers))
+  end
+
+  it "encodes a Time timestamp as seconds" do
+    result = encode(0, { timestamp: Time.at(1_700_000_000) })
+
+    expect(result[12, 2].unpack1("n")).to eq(1 << 6)
+    expect(result[14, 8].unpack1("Q>")).to eq(1_700_000_000)
+    expect(encode(0, { timestamp: 1_700_000_000 })).to eq(result)
+  end
+
+  it "rejects values of the wrong type" do
+    expect { encode(0, { priority: 300 }) }.to raise_error(TypeError)
+    expect { encode(0, { headers: "nope" }) }.to raise_error(TypeError)
+  end
+end
+
+RSpec.describe "AMQ::Protocol::Basic.encoded_properties_size" do
+  it "returns the size of the encoded content header payload" do
+    [
+      {},
+      { content_type: "text/plain", delivery_mode: 2, app_id: "test", timestamp: Time.at(5) },
+      { headers: { "x-death" => [{ "count" => 1, "queue" => "q" }], "id" => 2**63 - 1 }, priority: 9 },
+      { content_type: "caf\xE9".force_encoding(Encoding::ISO_8859_1) }
+    ].each do |properties|
+      expect(AMQ::Protocol::Basic.encoded_properties_size(properties))
+        .to eq(AMQ::Protocol::Basic.encode_properties(0, properties).bytesize)
+    end
+  end
+
+  it "rejects the same values as encode_properties" do
+    expect { AMQ::Protocol::Basic.encoded_properties_size({ priority: 300 }) }.to raise_error(TypeError)
+    expect { AMQ::Protocol::Basic.encoded_properties_size({ unknown: 1 }) }.to raise_error(ArgumentError)
+    expect { AMQ::Protocol::Basic.encoded_properties_size({ app_id: "a" * 256 }) }.to raise_error(ArgumentError)
+    expect { AMQ::Protocol::Basic.encoded_properties_size({ headers: { "a" => Object.new } }) }
+      .to raise_error(AMQ::Protocol::TableEncodingError)
+  end
+end
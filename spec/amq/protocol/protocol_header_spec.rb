@@ -0,0 +1,60 @@
+# frozen_string_literal: true
+
+RSpec.describe AMQ::Protocol::ProtocolHeader do
+  let(:amqp_091) { "AMQP\x00\x00\x09\x01".b }
+  let(:amqp_10) { "AMQP\x00\x01\x00\x00".b }
+
+  describe ".decode" do
+    it "decodes the protocol id and version" do
+      expect(described_class.decode(amqp_091)).to eq([0, 0, 9, 1])
+      expect(described_class.decode(amqp_10)).to eq([0, 1, 0, 0])
+    end
+
+    it "matches the preamble sent by clients" do
+      expect(described_class.decode(AMQ::Protocol::PREAMBLE.b)).to eq([0, 0, 9, 1])
+    end
+
+    it "raises for data that is not a protocol header" do
+      expect {
+        described_class.decode("HTTP/1.1".b)
+      }.to raise_error(RuntimeError)
+    end
+
+    it "raises for truncated headers" do
+      expect {
+        described_class.decode("AMQP".b)
+      }.to raise_error(RuntimeError)
+    end
+  end
+
+  describe ".verify" do
+    it "accepts the AMQP 0-9-1 header" do
+      expect(described_class.verify(amqp_091)).to eq(true)
+    end
+
+    it "raises a version mismatch carrying the server's version" do
+      expect {
+        described_class.verify(amqp_10)
+      }.to raise_error(AMQ::Protocol::ProtocolVersionMismatch) { |e|
+        expect(e.protocol_id).to eq(0)
+        expect(e.major).to eq(1)
+        expect(e.minor).to eq(0)
+        expect(e.revision).to eq(0)
+        expect(e.server_version).to eq("1-0-0")
+        expect(e.message).to include("1-0-0")
+      }
+    end
+  end
+
+  describe "Frame.decode_header" do
+    it "raises a version mismatch instead of an invalid frame type" do
+      expect {
+        AMQ::Protocol::Frame.decode_header(amqp_10[0, 7])
+      }.to raise_error(AMQ::Protocol::ProtocolVersionMismatch) { |e|
+        expect(e.major).to eq(1)
+        expect(e.minor).to eq(0)
+        expect(e.revision).to be_nil
+      }
+    end
+  end
+end
@@ -0,0 +1,153 @@
+# frozen_string_literal: true
+
+RSpec.describe AMQ::Protocol::PublishBatch do
+  let(:channel) { 3 }
+  let(:frame_max) { 4096 }
+  let(:properties) { { content_type: "application/json", delivery_mode: 2 } }
+
+  def expected_frames(exchange, routing_key, properties, body, mandatory = false, immediate = false)
+    frames = AMQ::Protocol::Frame.encode(
+      :method,
+      AMQ::Protocol::Basic::Publish.encode(exchange, routing_key, mandatory, immediate),
+      channel
+    )
+    frames += AMQ::Protocol::Frame.encode(
+      :headers,
+      AMQ::Protocol::Basic.encode_properties(body.bytesize, properties),
+      channel
+    )
+    body.b.scan(/.{1,#{frame_max - 8}}/m).each do |chunk|
+      frames += AMQ::Protocol::Frame.encode(:body, chunk, channel)
+    end
+    frames
+  end
+
+  describe ".encode" do
+    it "encodes every message into one binary String" do
+      messages = [
+        ["amq.direct", "a", properties, "first"],
+        ["", "queue", {}, "second"]
+      ]
+
+      result = described_class.encode(channel, frame_max, messages)
+
+      expect(result.encoding).to eq(Encoding::BINARY)
+      expect(result).to eq(messages.map { |m| expected_frames(*m) }.join)
+    end
+
+    it "splits bodies into frames no larger than frame_max" do
+      body = "x" * 10_000
+      result = described_class.encode(channel, frame_max, [["", "q", {}, body]])
+
+      frames = []
+      offset = 0
+      while offset < result.bytesize
+        type, _channel, size = result[offset, 7].unpack("CnN")
+        frames << [type, size]
+        expect(size + 8).to be <= frame_max
+        offset += 7 + size + 1
+      end
+
+      expect(frames.map(&:first)).to eq([1, 2, 3, 3, 3])
+      expect(frames.last(3).sum { |_, size| size }).to eq(10_000)
+    end
+
+    it "does not emit body frames for an empty body" do
+      result = described_class.encode(channel, frame_max, [["", "q", {}, ""]])
+      expect(result).to eq(expected_frames("", "q", {}, ""))
+    end
+
+    it "takes optional mandatory and immediate flags after the body" do
+      messages = [
+        ["", "a", {}, "first", true],
+        ["", "b", {}, "second", false, true],
+        ["", "c", {}, "third", true, true]
+      ]
+
+      expect(described_class.encode(channel, frame_max, messages)).to eq(
+        messages.map { |m| expected_frames(*m) }.join
+      )
+    end
+
+    it "rejects messages with missing or extra elements" do
+      expect {
+        described_class.encode(channel, frame_max, [["", "q", {}]])
+      }.to raise_error(ArgumentError, /got 3 elements/)
+      expect {
+        described_class.encode(channel, frame_max, [["", "q", {}, "body", true, true, true]])
+      }.to raise_error(ArgumentError, /got 7 elements/)
+    end
+
+    it "rejects a frame_max below the protocol minimum" do
+      expect { described_class.encode(channel, 512, []) }.to raise_error(ArgumentError)
+    end
+  end
+
+  describe "#add" do
+    subject(:batch) { described_class.new(channel, frame_max) }
+
+    it "accumulates messages until taken" do
+      batch.add("amq.direct", "a", properties, "first").add("", "b", {}, "second")
+
+      expect(batch.count).to eq(2)
+      expect(batch.bytesize).to eq(batch.to_s.bytesize)
+      expect(batch.take).to eq(
+        expected_frames("amq.direct", "a", properties, "first") +
+          expected_frames("", "b", {}, "second")
+      )
+      expect(batch).to be_empty
+      expect(batch.bytesize).to eq(0)
+    end
+
+    it "sets mandatory and immediate from keywords" do
+      batch.add("", "a", {}, "first", mandatory: true).add("", "b", {}, "second", immediate: true)
+
+      expect(batch.take).to eq(
+        expected_frames("", "a", {}, "first", true, false) +
+          expected_frames("", "b", {}, "second", false, true)
+      )
+    end
+
+    it "encodes the body as it was when the message was added" do
+      body = +"original"
+      field = Object.new
+      field.define_singleton_method(:to_amqp_field) { body.replace("changed while encoding"); "v" }
+
+      batch.add("", "a", { headers: { "hook" => field } }, body)
+
+      expect(batch.take).to eq(
+        expected_frames("", "a", { headers: { "hook" => "v" } }, "original")
+      )
+    end
+
+    it "does not keep partially encoded messages" do
+      batch.add("", "a", {}, "first")
+
+      expect {
+        batch.add("", "b", { headers: { "bad" => Object.new } }, "second")
+      }.to raise_error(ArgumentError)
+      expect(batch.count).to eq(1)
+      expect(batch.to_s).to eq(expected_frames("", "a", {}, "first"))
+    end
+
+    it "raises when a to_amqp_field hook uses the batch being added to" do
+      field = Object.new
+      field.define_singleton_method(:to_amqp_field) { batch.bytesize }
+
+      expect {
+        batch.add("", "a", { headers: { "size" => field } }, "body")
+      }.to raise_error(RuntimeError, /already being written to/)
+      expect(batch).to be_empty
+      expect(batch.bytesize).to eq(0)
+    end
+
+    it "rejects content headers that do not fit into frame_max" do
+      headers = (1..100).to_h { |i| ["header-#{i}", "v" * 50] }
+
+      expect {
+        batch.add("", "a", { headers: headers }, "body")
+      }.to raise_error(ArgumentError, /frame_max/)
+      expect(batch).to be_empty
+    end
+  end
+end
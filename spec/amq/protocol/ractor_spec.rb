@@ -0,0 +1,77 @@
+# frozen_string_literal: true
+
+RSpec.describe "Ractor support", if: defined?(Ractor) do
+  around do |example|
+    experimental = Warning[:experimental]
+    Warning[:experimental] = false
+    example.run
+  ensure
+    Warning[:experimental] = experimental
+  end
+
+  def ractor_result(ractor)
+    ractor.respond_to?(:value) ? ractor.value : ractor.take
+  end
+
+  it "exports shareable constants" do
+    [
+      AMQ::Protocol::Frame::TYPES,
+      AMQ::Protocol::Frame::TYPES_REVERSE,
+      AMQ::Protocol::Frame::TYPES_OPTIONS,
+      AMQ::Protocol::Frame::FINAL_OCTET,
+      AMQ::Protocol::Frame::CLASSES,
+      AMQ::Protocol::PREAMBLE,
+      AMQ::Protocol::PACK_UINT64_BE,
+      AMQ::Protocol::METHODS,
+      AMQ::Protocol::Basic::PROPERTIES,
+      *AMQ::Protocol::TypeConstants.constants.map { |name| AMQ::Protocol::TypeConstants.const_get(name) }
+    ].each do |constant|
+      expect(Ractor.shareable?(constant)).to eq(true), "expected #{constant.inspect} to be shareable"
+    end
+  end
+
+  it "encodes and decodes tables inside a Ractor" do
+    ractor = Ractor.new do
+      table = { "x-message-ttl" => 60_000, "nested" => { "list" => [1, "two", true] } }
+      AMQ::Protocol::Table.decode(AMQ::Protocol::Table.encode(table))
+    end
+
+    expect(ractor_result(ractor)).to eq(
+      { "x-message-ttl" => 60_000, "nested" => { "list" => [1, "two", true] } }
+    )
+  end
+
+  it "encodes and decodes frames inside a Ractor" do
+    ractor = Ractor.new do
+      payload = AMQ::Protocol::Basic::Publish.encode("amq.direct", "key", false, false)
+      frame = AMQ::Protocol::Frame.encode(:method, payload, 1)
+      type, channel, size = AMQ::Protocol::Frame.decode_header(frame[0, 7])
+
+      [type, channel, size, AMQ::Protocol::Frame::TYPES[type], frame == AMQ::Protocol::Frame.encode(:method, payload, 1)]
+    end
+
+    expect(ractor_result(ractor)).to eq([:method, 1, 22, 1, true])
+  end
+
+  it "runs encoders in several Ractors at once" do
+    ractors = 4.times.map do |i|
+      Ractor.new(i) do |channel|
+        100.times.map { AMQ::Protocol::Frame.encode(:heartbeat, "", channel) }.uniq
+      end
+    end
+
+    expect(ractors.map { |r| ractor_result(r) }).to eq(
+      4.times.map { |i| [AMQ::Protocol::Frame.encode(:heartbeat, "", i)] }
+    )
+  end
+
+  it "raises protocol errors inside a Ractor" do
+    ractor = Ractor.new do
+      AMQ::Protocol::ProtocolHeader.verify("AMQP\x00\x01\x00\x00".b)
+    rescue AMQ::Protocol::ProtocolVersionMismatch => e
+      e.server_version
+    end
+
+    expect(ractor_result(ractor)).to eq("1-0-0")
+  end
+end
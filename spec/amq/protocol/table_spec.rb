@@ -38,6 +38,35 @@ RSpec.describe AMQ::Protocol::Table do
       expect(result).to be_a(String)
     end
 
+    it "prefixes nested tables and arrays with their byte length" do
+      result = described_class.encode({ "a" => { "b" => [true] } })
+
+      expect(result.bytes).to eq([
+        0, 0, 0, 16,          # outer table
+        1, 97, 70,            # "a" => table
+        0, 0, 0, 9,           # inner table
+        1, 98, 65,            # "b" => array
+        0, 0, 0, 2, 116, 1    # array of one boolean
+      ])
+    end
+
+    it "round-trips deeply nested x-death headers" do
+      headers = {
+        "x-death" => (1..5).map do |i|
+          {
+            "count" => i,
+            "queue" => "retry.#{i}",
+            "routing-keys" => ["orders.#{i}", { "nested" => [[i]] }]
+          }
+        end
+      }
+
+      encoded = described_class.encode(headers)
+
+      expect(encoded.unpack1("N")).to eq(encoded.bytesize - 4)
+      expect(described_class.decode(encoded)).to eq(headers)
+    end
+
     it "encodes symbol keys" do
       result = described_class.encode({ key: "value" })
       expect(result).to be_a(String)
@@ -107,4 +136,576 @@ RSpec.describe AMQ::Protocol::Table do
       expect(length).to be >= 0
     end
   end
+
+  describe ".fetch" do
+    let(:encoded) do
+      described_class.encode({ "a" => 1, "x-death" => [{ "queue" => "q" }], "b" => "two" })
+    end
+
+    it "decodes a single value" do
+      expect(described_class.fetch(encoded, "x-death")).to eq([{ "queue" => "q" }])
+      expect(described_class.fetch(encoded, :b)).to eq("two")
+    end
+
+    it "returns nil for missing keys" do
+      expect(described_class.fetch(encoded, "c")).to be_nil
+    end
+
+    it "returns the last value of a duplicate key, as decode does" do
+      duplicated = [16, 1, "k", "b", 1, 1, "k", "b", 2, 1, "z", "S", 1, "x"].pack("NCa*a*cCa*a*cCa*a*Na*")
+
+      expect(described_class.decode(duplicated)["k"]).to eq(2)
+      expect(described_class.fetch(duplicated, "k")).to eq(2)
+      expect(AMQ::Protocol::Table::View.new(duplicated)["k"]).to eq(2)
+    end
+
+    it "raises for unknown field types it has to skip" do
+      corrupt = encoded.dup
+      corrupt.setbyte(6, "Z".ord)
+
+      expect { described_class.fetch(corrupt, "b") }.to raise_error(ArgumentError, /Invalid table type/)
+    end
+  end
+
+  describe "integers outside the signed 64-bit range" do
+    it "raises RangeError in the RabbitMQ dialect" do
+      [2**63, 2**64, -(2**63) - 1].each do |value|
+        expect { described_class.encode({ "a" => [value] }) }.to raise_error(RangeError, /#{value}/) do |error|
+          expect(error).to be_a(AMQ::Protocol::EncodingErrorLocation)
+          expect(error.key_path).to eq("a[0]")
+          expect(error.value).to eq(value)
+        end
+      end
+      expect { described_class.encode({ "a" => 2**63 }, dialect: :rabbitmq) }.to raise_error(RangeError)
+    end
+
+    it "encodes unsigned 64-bit values as 'l' and signed ones as 'L' in the spec dialect" do
+      encoded = described_class.encode({ "id" => 2**64 - 1, "neg" => -1 }, dialect: :spec)
+
+      expect(encoded.byteslice(4, 4)).to eq("\x02idl".b)
+      expect(encoded.byteslice(8, 8)).to eq("\xFF".b * 8)
+      expect(encoded.byteslice(16, 5)).to eq("\x03negL".b)
+      expect(described_class.decode(encoded, dialect: :spec)).to eq({ "id" => 2**64 - 1, "neg" => -1 })
+      expect(described_class.decode(encoded)).to eq({ "id" => -1, "neg" => -1 })
+      expect { described_class.encode({ "a" => 2**64 }, dialect: :spec) }.to raise_error(RangeError)
+    end
+
+    it "decodes 'L' as a signed value" do
+      encoded = [11, 1, "a", "L", -2].pack("NCa*a*q>")
+
+      expect(described_class.decode(encoded)).to eq({ "a" => -2 })
+      expect(described_class.decode(encoded, dialect: :spec)).to eq({ "a" => -2 })
+    end
+
+    it "encodes them as decimal strings with big_integers: :string" do
+      table = { "id" => 2**64 - 1, "huge" => -(2**70), "small" => 5 }
+      encoded = described_class.encode(table, big_integers: :string)
+
+      expect(described_class.decode(encoded)).to eq(
+        { "id" => (2**64 - 1).to_s, "huge" => (-(2**70)).to_s, "small" => 5 }
+      )
+    end
+
+    it "accepts the same options in encode_into" do
+      buffer = +"".b
+      described_class.encode_into(buffer, { "id" => 2**64 }, big_integers: :string)
+
+      expect(described_class.decode(buffer)).to eq({ "id" => (2**64).to_s })
+      expect { described_class.encode({}, big_integers: :bogus) }.to raise_error(ArgumentError)
+      expect { described_class.encode({}, dialect: :bogus) }.to raise_error(ArgumentError)
+    end
+  end
+
+  describe "compact_integers: true" do
+    it "encodes each Integer as the narrowest signed type that holds it" do
+      values = [0, -128, 127, 128, -32_769, 2**31, 2**63 - 1]
+      encoded = described_class.encode({ "v" => values }, compact_integers: true)
+
+      expect(encoded.byteslice(11..).bytes).to eq([
+        98, 0,                      # b 0
+        98, 128,                    # b -128
+        98, 127,                    # b 127
+        115, 0, 128,                # s 128
+        73, 255, 255, 127, 255,     # I -32769
+        108, 0, 0, 0, 0, 128, 0, 0, 0, # l 2**31
+        108, 127, 255, 255, 255, 255, 255, 255, 255 # l 2**63 - 1
+      ])
+    end
+
+    it "decodes back to the same Integers" do
+      table = { "count" => 3, "nested" => { "big" => 70_000, "neg" => -5 }, "id" => 2**63 - 1 }
+
+      encoded = described_class.encode(table, compact_integers: true)
+
+      expect(encoded.bytesize).to be < described_class.encode(table).bytesize
+      expect(described_class.decode(encoded)).to eq(table)
+    end
+
+    it "uses 'U' for 16-bit values in the spec dialect, where 's' is a short string" do
+      encoded = described_class.encode({ "s" => -300 }, compact_integers: true, dialect: :spec)
+
+      expect(encoded.byteslice(4..)).to eq("\x01sU\xFE\xD4".b)
+      expect(described_class.decode(encoded, dialect: :spec)).to eq({ "s" => -300 })
+      expect(described_class.fetch(encoded, "s", dialect: :spec)).to eq(-300)
+      expect(AMQ::Protocol::Table::View.new(encoded, dialect: :spec).to_h).to eq({ "s" => -300 })
+    end
+  end
+
+  describe "to_amqp_field" do
+    let(:uuid_class) do
+      Struct.new(:value) do
+        def to_amqp_field
+          value
+        end
+      end
+    end
+
+    it "encodes objects as the field value they convert to" do
+      require "set"
+      uuid = uuid_class.new("9b2e4c1a")
+      tags = Set[1, 2]
+      def tags.to_amqp_field
+        to_a
+      end
+
+      encoded = described_class.encode({ "id" => uuid, "nested" => { "tags" => tags, "ids" => [uuid] } })
+
+      expect(described_class.decode(encoded)).to eq(
+        { "id" => "9b2e4c1a", "nested" => { "tags" => [1, 2], "ids" => ["9b2e4c1a"] } }
+      )
+    end
+
+    it "accepts typed wrappers" do
+      decimal = Object.new
+      def decimal.to_amqp_field
+        AMQ::Protocol::Table::RawValue.new("D", "\x02\x00\x00\x30\x39".b)
+      end
+
+      expect(described_class.decode(described_class.encode({ "d" => decimal }))["d"]).to be_within(1e-9).of(123.45)
+    end
+
+    it "re-raises exceptions from the hook" do
+      failing = Object.new
+      def failing.to_amqp_field
+        raise NotImplementedError, "nope"
+      end
+
+      expect { described_class.encode({ "a" => failing }) }.to raise_error(NotImplementedError, "nope")
+    end
+
+    it "does not convert the returned value again" do
+      looping = uuid_class.new(nil)
+      looping.value = looping
+
+      expect { described_class.encode({ "a" => looping }) }
+        .to raise_error(AMQ::Protocol::TableEncodingError, /to_amqp_field itself/) do |error|
+          expect(error.key_path).to eq("a")
+          expect(error.value).to be(looping)
+        end
+    end
+  end
+
+  describe ".encoded_size" do
+    let(:uuid_class) do
+      Struct.new(:value) do
+        def to_amqp_field
+          value
+        end
+      end
+    end
+
+    let(:table) do
+      {
+        "string" => "value",
+        symbol: :sym,
+        "numbers" => [0, -200, 70_000, 2**40, 2**63 - 1, 1.5],
+        "flags" => [true, false, nil],
+        "time" => Time.at(5),
+        "x-death" => [{ "count" => 1, "queue" => "retry", "nested" => { "deep" => [[]] } }],
+        "raw" => AMQ::Protocol::Table::RawValue.new("Z", "\x01\x02".b),
+        "bytes" => AMQ::Protocol::Table::ByteArray.new("\x00".b),
+        "id" => uuid_class.new("9b2e4c1a")
+      }
+    end
+
+    it "returns the size of the encoded table" do
+      expect(described_class.encoded_size({})).to eq(4)
+      expect(described_class.encoded_size(table)).to eq(described_class.encode(table).bytesize)
+    end
+
+    it "takes the encoding options into account" do
+      [
+        { compact_integers: true }, { big_integers: :string }, { canonical: true }, { dialect: :spec }
+      ].each do |options|
+        expect(described_class.encoded_size(table, **options)).to eq(described_class.encode(table, **options).bytesize)
+      end
+    end
+
+    it "measures Strings after their conversion to UTF-8" do
+      latin1 = "caf\xE9".force_encoding(Encoding::ISO_8859_1)
+      table = { latin1 => latin1, "nested" => [latin1] }
+
+      expect(described_class.encoded_size(table)).to eq(described_class.encode(table).bytesize)
+    end
+
+    it "measures Strings that the conversion keeps as they are" do
+      table = { "a" => "caf\xC3\xA9".b, "b" => "plain".encode(Encoding::ISO_8859_1), "c" => "caf\u00E9" }
+
+      expect(described_class.encoded_size(table)).to eq(described_class.encode(table).bytesize)
+      expect { described_class.encoded_size({ "a" => "\xFF".b }) }.to raise_error(AMQ::Protocol::TableEncodingError)
+    end
+
+    it "raises as encode does" do
+      invalid = "\xFF".force_encoding(Encoding::UTF_8)
+      expect { described_class.encode({ "a" => invalid }) }.to raise_error(AMQ::Protocol::TableEncodingError)
+      expect { described_class.encoded_size({ "a" => invalid }) }.to raise_error(AMQ::Protocol::TableEncodingError)
+      expect { described_class.encoded_size({ invalid => 1 }) }.to raise_error(AMQ::Protocol::TableEncodingError)
+      expect { described_class.encoded_size({ "a" => [Object.new] }) }
+        .to raise_error(AMQ::Protocol::TableEncodingError) do |error|
+          expect(error.key_path).to eq("a[0]")
+        end
+      expect { described_class.encoded_size({ "a" => 2**63 }) }.to raise_error(RangeError)
+      expect { described_class.encoded_size({ "k" => 1, k: 2 }, canonical: true) }
+        .to raise_error(AMQ::Protocol::TableEncodingError, /Duplicate key k/)
+      expect(described_class.encoded_size({ "k" => 1, k: 2 })).to eq(described_class.encode({ "k" => 1, k: 2 }).bytesize)
+      latin1 = "caf\xE9".force_encoding(Encoding::ISO_8859_1)
+      expect { described_class.encoded_size({ "caf\u00E9" => 1, latin1 => 2 }, canonical: true) }
+        .to raise_error(AMQ::Protocol::TableEncodingError, /Duplicate key café/)
+      expect(described_class.encoded_size({ "k" => 1, "l" => 2, m: 3 }, canonical: true)).to eq(
+        described_class.encode({ "k" => 1, "l" => 2, m: 3 }, canonical: true).bytesize
+      )
+    end
+  end
+
+  describe "canonical: true" do
+    it "encodes equal tables built in different orders to the same bytes" do
+      a = { "b" => 1, :a => { "y" => [{ "q" => 1, "p" => 2 }], "x" => nil }, "B" => true }
+      b = { "B" => true, "a" => { "x" => nil, y: [{ p: 2, q: 1 }] }, b: 1 }
+
+      expect(described_class.encode(a, canonical: true)).to eq(described_class.encode(b, canonical: true))
+      expect(described_class.encode(a)).not_to eq(described_class.encode(b))
+    end
+
+    it "sorts keys bytewise at every level" do
+      encoded = described_class.encode({ "b" => { "z" => 1, "é" => 2, "Z" => 3 }, "a" => 4 }, canonical: true)
+
+      expect(described_class.decode(encoded).keys).to eq(%w[a b])
+      expect(described_class.decode(encoded)["b"].keys).to eq(%w[Z z é])
+    end
+
+    it "rejects keys that are equal once Symbols are converted to Strings" do
+      expect { described_class.encode({ "n" => { :k => 1, "k" => 2 } }, canonical: true) }
+        .to raise_error(AMQ::Protocol::TableEncodingError, /Duplicate key k/) do |error|
+          expect(error.key_path).to eq("n.k")
+        end
+    end
+  end
+
+  describe ".decode_ordered and .encode_ordered" do
+    def table(fields)
+      [fields.bytesize].pack("N") + fields
+    end
+
+    let(:encoded) do
+      table(
+        "\x01aS\x00\x00\x00\x01x".b +
+        "\x01aS\x00\x00\x00\x01y".b +
+        "\x01dD\x02\x00\x00\x30\x39".b +
+        "\x01tt\x02".b +
+        "\x01nF".b + table("\x01bb\xFF".b) +
+        "\x01lA\x00\x00\x00\x0A".b + "I\x00\x00\x00\x05".b + "f\x3F\xC0\x00\x00".b +
+        "\x01zU\x01\x02".b
+      )
+    end
+
+    it "decodes fields as [key, type, value] triples in wire order" do
+      expect(described_class.decode_ordered(encoded)).to eq([
+        ["a", "S", "x"],
+        ["a", "S", "y"],
+        ["d", "D", AMQ::Protocol::Table::RawValue.new("D", "\x02\x00\x00\x30\x39".b)],
+        ["t", "t", AMQ::Protocol::Table::RawValue.new("t", "\x02".b)],
+        ["n", "F", [["b", "b", -1]]],
+        ["l", "A", [["I", 5], ["f", 1.5]]],
+        ["z", "U", AMQ::Protocol::Table::RawValue.new("U", "\x01\x02".b)]
+      ])
+    end
+
+    it "encodes decoded triples back to the same bytes" do
+      expect(described_class.encode_ordered(described_class.decode_ordered(encoded))).to eq(encoded)
+
+      headers = described_class.encode({ "x-death" => [{ "count" => 1, "time" => Time.at(5), "x" => nil }] })
+      expect(described_class.encode_ordered(described_class.decode_ordered(headers))).to eq(headers)
+    end
+
+    it "returns keys as binary Strings that encode back as they were" do
+      encoded = table("\x02\xFF\xFEb\x01".b + "\x02\xC3\xA9b\x02".b)
+      fields = described_class.decode_ordered(encoded)
+
+      expect(fields.map(&:first)).to eq(["\xFF\xFE".b, "é".b])
+      expect(fields.map(&:first).map(&:encoding)).to all(eq(Encoding::BINARY))
+      expect(described_class.encode_ordered(fields)).to eq(encoded)
+    end
+
+    it "raises for fields that run past the table or array length" do
+      field = "\x01ab\x05".b
+      overrun_table = [field.bytesize - 1].pack("N") + field
+      overrun_array = table("\x01lA".b + [1].pack("N") + "s\x00\x01".b)
+
+      expect { described_class.decode_ordered(overrun_table) }.to raise_error(RuntimeError, /Fields end at byte 8/)
+      expect { described_class.decode_ordered(overrun_array) }.to raise_error(RuntimeError, /Fields end at byte/)
+    end
+
+    it "encodes each value as the given type" do
+      encoded = described_class.encode_ordered([["k", "b", 3], ["k", "s", 4], ["t", "t", true]])
+
+      expect(encoded).to eq(table("\x01kb\x03\x01ks\x00\x04\x01tt\x01".b))
+    end
+
+    it "raises TableEncodingError for values that do not fit their type" do
+      expect { described_class.encode_ordered([["n", "F", [["k", "b", 300]]]]) }
+        .to raise_error(AMQ::Protocol::TableEncodingError, /type 'b'/) do |error|
+          expect(error.key_path).to eq("n.k")
+          expect(error.value).to eq(300)
+        end
+      expect { described_class.encode_ordered([["k", "S"]]) }
+        .to raise_error(AMQ::Protocol::TableEncodingError, /triple/)
+    end
+  end
+
+  describe "encoding errors" do
+    it "reports the key path and value that could not be encoded" do
+      value = Object.new
+      table = { "x-death" => [{ "count" => 1, "queue" => value }] }
+
+      expect { described_class.encode(table) }.to raise_error(AMQ::Protocol::TableEncodingError) do |error|
+        expect(error).to be_an(ArgumentError)
+        expect(error.key_path).to eq("x-death[0].queue")
+        expect(error.value).to be(value)
+        expect(error.message).to eq("Unsupported table value of class Object (x-death[0].queue)")
+      end
+    end
+
+    it "reports invalid keys" do
+      expect { described_class.encode({ "a" => { 1 => "one" } }) }
+        .to raise_error(AMQ::Protocol::TableEncodingError, /Expected symbol or string key/) do |error|
+          expect(error.key_path).to eq("a")
+          expect(error.value).to eq(1)
+        end
+      expect { described_class.encode({ "k" * 256 => 1 }) }
+        .to raise_error(AMQ::Protocol::TableEncodingError, /Table key too long/) do |error|
+          expect(error.key_path).to be_nil
+        end
+    end
+
+    it "raises from properties and method arguments" do
+      expect { AMQ::Protocol::Basic.encode_properties(0, { headers: { "h" => [Object.new] } }) }
+        .to raise_error(AMQ::Protocol::TableEncodingError) do |error|
+          expect(error.key_path).to eq("h[0]")
+        end
+    end
+  end
+
+  describe "decoding error locations" do
+    let(:encoded) { described_class.encode({ "x-death" => [{ "count" => 1, "queue" => "retry" }] }) }
+
+    it "reports the offset and key path of a truncated nested value" do
+      truncated = encoded.byteslice(0, encoded.bytesize - 2)
+
+      expect { described_class.decode(truncated) }.to raise_error(RuntimeError, /too short/) do |error|
+        expect(error).to be_a(AMQ::Protocol::DecodingErrorLocation)
+        expect(error.offset).to eq(encoded.bytesize - 5)
+        expect(error.key_path).to eq("x-death[0].queue")
+        expect(error.message).to end_with("at byte #{encoded.bytesize - 5} (x-death[0].queue)")
+      end
+    end
+
+    it "keeps the original exception class" do
+      corrupt = encoded.dup
+      corrupt.setbyte(encoded.index("queue") + 5, "Z".ord)
+
+      expect { described_class.decode(corrupt) }.to raise_error(ArgumentError, /Invalid table type/) do |error|
+        expect(error.key_path).to eq("x-death[0].queue")
+        expect(error.offset).to eq(encoded.index("queue") + 6)
+      end
+    end
+
+    it "has no key path when the table itself is malformed" do
+      expect { described_class.decode("\x00\x00".b) }.to raise_error(RuntimeError) do |error|
+        expect(error.offset).to eq(0)
+        expect(error.key_path).to be_nil
+      end
+    end
+
+    it "reports offsets within the whole table for fetch and views" do
+      corrupt = encoded.dup
+      corrupt.setbyte(encoded.index("queue") + 5, "Z".ord)
+
+      expect { described_class.fetch(corrupt, "x-death") }.to raise_error(ArgumentError) do |error|
+        expect(error.offset).to eq(encoded.index("queue") + 6)
+        expect(error.key_path).to eq("x-death[0].queue")
+      end
+      expect { AMQ::Protocol::Table::View.new(corrupt).to_h }.to raise_error(ArgumentError) do |error|
+        expect(error.key_path).to eq("x-death[0].queue")
+      end
+    end
+  end
+
+  describe ".decode options" do
+    let(:encoded) do
+      described_class.encode({ "name" => "value", "nested" => { "list" => ["a", { "b" => 1 }] } })
+    end
+
+    it "returns interned frozen string keys" do
+      first = described_class.decode(encoded).keys.first
+      second = described_class.decode(encoded).keys.first
+
+      expect(first).to be_frozen
+      expect(first).to equal(second)
+      expect(first).to equal(-"name")
+    end
+
+    it "symbolizes keys at every level" do
+      result = described_class.decode(encoded, symbolize_keys: true)
+
+      expect(result).to eq({ name: "value", nested: { list: ["a", { b: 1 }] } })
+    end
+
+    it "freezes every decoded value" do
+      result = described_class.decode(encoded, freeze: true)
+
+      expect(result).to be_frozen
+      expect(result["name"]).to be_frozen
+      expect(result["nested"]).to be_frozen
+      expect(result["nested"]["list"]).to be_frozen
+      expect(result["nested"]["list"][0]).to be_frozen
+      expect(result["nested"]["list"][1]).to be_frozen
+    end
+
+    it "leaves values unfrozen by default" do
+      result = described_class.decode(encoded)
+
+      expect(result).not_to be_frozen
+      expect(result["name"]).not_to be_frozen
+    end
+
+    it "rejects unknown options" do
+      expect { described_class.decode(encoded, bogus: true) }.to raise_error(ArgumentError)
+    end
+
+    it "does not allocate key strings" do
+      integers = described_class.encode((1..50).to_h { |i| ["key-#{i}", i] })
+      described_class.decode(integers)
+      before = GC.stat(:total_allocated_objects)
+      described_class.decode(integers)
+      allocated = GC.stat(:total_allocated_objects) - before
+
+      expect(allocated).to be <= 3
+    end
+  end
+
+  describe ".decode value options" do
+    def table(fields)
+      [fields.bytesize].pack("N") + fields
+    end
+
+    it "decodes timestamps as integers with time_as: :integer" do
+      encoded = described_class.encode({ "t" => Time.at(1_700_000_000) })
+
+      expect(described_class.decode(encoded)["t"]).to eq(Time.at(1_700_000_000))
+      expect(described_class.decode(encoded, time_as: :integer)["t"]).to eq(1_700_000_000)
+    end
+
+    it "decodes decimals as BigDecimal with decimal_as: :big_decimal" do
+      require "bigdecimal"
+      encoded = table("\x01dD\x02\x00\x00\x30\x39".b)
+
+      expect(described_class.decode(encoded)["d"]).to be_within(1e-9).of(123.45)
+      expect(described_class.decode(encoded, decimal_as: :big_decimal)["d"]).to eq(BigDecimal("123.45"))
+    end
+
+    it "decodes byte arrays as objects that encode back to byte arrays" do
+      encoded = table("\x01xx\x00\x00\x00\x03\x01\x02\x03".b)
+
+      expect(described_class.decode(encoded)["x"]).to eq("\x01\x02\x03".b)
+
+      result = described_class.decode(encoded, byte_array_as: :object)
+      expect(result["x"]).to eq(AMQ::Protocol::Table::ByteArray.new("\x01\x02\x03".b))
+      expect(described_class.encode(result)).to eq(encoded)
+    end
+
+    context "with field types the decoder does not support" do
+      let(:encoded) do
+        table("\x01aB\x07".b + "\x01bS\x00\x00\x00\x02ok".b + "\x01cU\x01\x02".b)
+      end
+
+      it "raises by default" do
+        expect { described_class.decode(encoded) }.to raise_error(ArgumentError, /Invalid table type/)
+        expect { described_class.decode(encoded, on_unknown_type: :raise) }.to raise_error(ArgumentError)
+      end
+
+      it "leaves them out with on_unknown_type: :skip" do
+        expect(described_class.decode(encoded, on_unknown_type: :skip)).to eq({ "b" => "ok" })
+      end
+
+      it "returns raw values that encode back to the same bytes with on_unknown_type: :raw" do
+        result = described_class.decode(encoded, on_unknown_type: :raw)
+
+        expect(result).to eq(
+          "a" => AMQ::Protocol::Table::RawValue.new("B", "\x07".b),
+          "b" => "ok",
+          "c" => AMQ::Protocol::Table::RawValue.new("U", "\x01\x02".b)
+        )
+        expect(described_class.encode(result)).to eq(encoded)
+      end
+
+      it "treats them the same way in views and fetch" do
+        skipping = AMQ::Protocol::Table::View.new(encoded, on_unknown_type: :skip)
+        expect(skipping.keys).to eq(["b"])
+        expect(skipping.size).to eq(1)
+        expect(skipping.key?("a")).to be(false)
+        expect(described_class.fetch(encoded, "c", on_unknown_type: :skip)).to be_nil
+
+        raw = AMQ::Protocol::Table::View.new(encoded, on_unknown_type: :raw)
+        expect(raw.keys).to eq(%w[a b c])
+        expect(raw["c"]).to eq(AMQ::Protocol::Table::RawValue.new("U", "\x01\x02".b))
+
+        expect { AMQ::Protocol::Table::View.new(encoded).keys }.to raise_error(ArgumentError, /Invalid table type/)
+      end
+
+      it "decodes 'U' as a signed 16-bit integer in the spec dialect" do
+        options = { on_unknown_type: :skip, dialect: :spec }
+
+        expect(described_class.decode(encoded, **options)).to eq({ "b" => "ok", "c" => 258 })
+        expect(AMQ::Protocol::Table::View.new(encoded, **options).keys).to eq(%w[b c])
+      end
+
+      it "raises for types of unknown size whatever the option" do
+        unknown = table("\x01zZ\x01\x02".b + "\x01bS\x00\x00\x00\x02ok".b)
+
+        %i[raise skip raw].each do |option|
+          expect { described_class.decode(unknown, on_unknown_type: option) }
+            .to raise_error(ArgumentError, /Invalid table type: Z/)
+          expect { described_class.fetch(unknown, "z", on_unknown_type: option) }.to raise_error(ArgumentError)
+          expect { AMQ::Protocol::Table::View.new(unknown, on_unknown_type: option).keys }
+            .to raise_error(ArgumentError)
+        end
+      end
+    end
+
+    it "applies the same options to fetch and views" do
+      encoded = described_class.encode({ "t" => Time.at(5), "nested" => { "k" => "v" } })
+
+      expect(described_class.fetch(encoded, "t", time_as: :integer)).to eq(5)
+
+      view = AMQ::Protocol::Table::View.new(encoded, symbolize_keys: true, freeze: true)
+      expect(view.keys).to eq(%i[t nested])
+      expect(view["nested"]).to eq({ k: "v" })
+      expect(view["nested"]).to be_frozen
+    end
+
+    it "rejects unknown option values" do
+      expect { described_class.decode("\x00\x00\x00\x00".b, time_as: :bogus) }
+        .to raise_error(ArgumentError, "time_as must be one of :time, :integer, got :bogus")
+    end
+  end
 end
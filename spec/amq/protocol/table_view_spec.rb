@@ -0,0 +1,71 @@
+# frozen_string_literal: true
+
+RSpec.describe AMQ::Protocol::Table::View do
+  let(:headers) do
+    {
+      "x-death" => [{ "count" => 2, "queue" => "orders.retry" }],
+      "traceparent" => "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01",
+      "x-retries" => 3,
+      "nothing" => nil
+    }
+  end
+  let(:encoded) { AMQ::Protocol::Table.encode(headers) }
+  let(:view) { described_class.new(encoded) }
+
+  it "decodes only the requested value" do
+    expect(view["traceparent"]).to eq(headers["traceparent"])
+    expect(view["x-death"]).to eq(headers["x-death"])
+    expect(view[:"x-retries"]).to eq(3)
+  end
+
+  it "returns nil for missing keys" do
+    expect(view["missing"]).to be_nil
+    expect(view.key?("missing")).to be(false)
+    expect(view.key?("nothing")).to be(true)
+  end
+
+  it "supports fetch with defaults" do
+    expect(view.fetch("x-retries")).to eq(3)
+    expect(view.fetch("missing", 0)).to eq(0)
+    expect(view.fetch("missing") { |key| key.upcase }).to eq("MISSING")
+    expect { view.fetch("missing") }.to raise_error(KeyError)
+  end
+
+  it "lists keys without decoding values" do
+    expect(view.keys).to eq(headers.keys)
+    expect(view.size).to eq(4)
+  end
+
+  it "enumerates keys and values" do
+    expect(view.to_h).to eq(headers)
+    expect(view.each.next).to eq(["x-death", headers["x-death"]])
+    expect(view.map(&:first)).to eq(headers.keys)
+  end
+
+  it "lists duplicate keys once, with their last value, as Table.decode does" do
+    fields = "\x01aI\x00\x00\x00\x01\x01bI\x00\x00\x00\x02\x01aI\x00\x00\x00\x03".b
+    view = described_class.new([fields.bytesize].pack("N") + fields)
+    decoded = AMQ::Protocol::Table.decode(view.to_s)
+
+    expect(decoded).to eq({ "a" => 3, "b" => 2 })
+    expect(view.keys).to eq(decoded.keys)
+    expect(view.size).to eq(2)
+    expect(view.to_a).to eq(decoded.to_a)
+    expect(view["a"]).to eq(3)
+  end
+
+  it "is unaffected by later changes to the source string" do
+    source = encoded.dup
+    view = described_class.new(source)
+    source.clear
+
+    expect(view["x-retries"]).to eq(3)
+    expect(view.bytesize).to eq(encoded.bytesize)
+  end
+
+  it "raises for truncated tables" do
+    view = described_class.new(encoded[0, 20])
+
+    expect { view.keys }.to raise_error(RuntimeError, /too short/)
+  end
+end